@@ -3,7 +3,6 @@
 use ark_bn254::{Bn254, Fr};
 use ark_std::UniformRand;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use sha2::Sha256;
 use cq::cq::Cq;
 use cq::prover::Prover;
 use cq::verifier::Verifier;
@@ -19,15 +18,15 @@ pub fn batch_verify(c: &mut Criterion) {
             let index = rand::random::<usize>() % big_n;
             t_i[index]
         }).collect();
-        let cq = Cq::<Bn254>::new(&t_i).unwrap();
-        let mut prover = Prover::<Sha256, Bn254>::new(f_i.clone()).unwrap();
-        let proof = prover.prove(&cq, &t_i).unwrap();
+        let Cq { pk, vk } = Cq::<Bn254>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bn254>::new(f_i.clone()).unwrap();
+        let proof = prover.prove(&pk).unwrap();
+        let pvk = vk.prepare();
 
         c.bench_with_input(BenchmarkId::new("[batch verify]: t_i size = ", size), &size, |b, _| {
             b.iter(|| {
-                let mut verifier = Verifier::<Sha256, Bn254>::new();
-                let result = verifier.batched_verify(&t_i, &cq, proof.clone());
-                result
+                let mut verifier = Verifier::<Bn254>::new();
+                verifier.batched_verify(&pvk, proof.clone())
             });
         });
     }
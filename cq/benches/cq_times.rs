@@ -1,29 +1,64 @@
 // benchmark the time of compute cq
+//
+// With the parallel feature, preprocessing and proving run on a single thread and on all threads;
+// without it they run serially.
 
 use ark_bn254::{Bn254, Fr};
 use ark_std::UniformRand;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use cq::cq::Cq;
+use cq::prover::Prover;
+
+// (label, number of threads), 0 meaning all of them
+#[cfg(feature = "parallel")]
+const THREADS: [(&str, usize); 2] = [("1 thread", 1), ("all threads", 0)];
+#[cfg(not(feature = "parallel"))]
+const THREADS: [(&str, usize); 1] = [("serial", 1)];
+
+fn with_threads<R: Send>(_threads: usize, f: impl FnOnce() -> R + Send) -> R {
+    #[cfg(feature = "parallel")]
+    return rayon::ThreadPoolBuilder::new().num_threads(_threads).build().unwrap().install(f);
+    #[cfg(not(feature = "parallel"))]
+    f()
+}
 
 pub fn cq(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cq");
     for size in [6, 10, 15] {
-        c.bench_with_input(BenchmarkId::new("[cq]: t_i size = ", size), &size, |b, size| {
-            let big_n = 2_u64.pow(*size);
-            let mut rng = ark_std::test_rng();
-
-            let t_i: Vec<Fr> = (0..big_n).map(|_| Fr::rand(&mut rng)).collect();
-            
-            b.iter(|| {
-                let cq = Cq::<Bn254>::new(&t_i).unwrap();
-                cq
+        let big_n = 2_u64.pow(size);
+        let mut rng = ark_std::test_rng();
+        let t_i: Vec<Fr> = (0..big_n).map(|_| Fr::rand(&mut rng)).collect();
+
+        for (label, threads) in THREADS {
+            group.bench_with_input(BenchmarkId::new(format!("[cq]: {label}, t_i size"), size), &size, |b, _| {
+                b.iter(|| with_threads(threads, || Cq::<Bn254>::new(&t_i).unwrap()));
+            });
+        }
+    }
+    group.finish();
+}
+
+pub fn prove(c: &mut Criterion) {
+    let mut group = c.benchmark_group("prove");
+    for size in [10, 15] {
+        let big_n = 2_usize.pow(size);
+        let mut rng = ark_std::test_rng();
+        let t_i: Vec<Fr> = (0..big_n).map(|_| Fr::rand(&mut rng)).collect();
+        let f_i: Vec<Fr> = (0..big_n).map(|_| t_i[rand::random::<usize>() % big_n]).collect();
+        let Cq { pk, .. } = Cq::<Bn254>::new(&t_i).unwrap();
+
+        for (label, threads) in THREADS {
+            group.bench_with_input(BenchmarkId::new(format!("[prove]: {label}, f_i size"), size), &size, |b, _| {
+                b.iter(|| with_threads(threads, || Prover::<Bn254>::new(f_i.clone()).unwrap().prove(&pk).unwrap()));
             });
-        });
+        }
     }
+    group.finish();
 }
 
 criterion_group!{
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets = cq
+    targets = cq, prove
 }
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
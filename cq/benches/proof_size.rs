@@ -3,7 +3,6 @@
 use ark_bn254::{Bn254, Fr};
 use ark_serialize::Compress;
 use ark_std::UniformRand;
-use sha2::Sha256;
 use cq::cq::Cq;
 use cq::prover::Prover;
 
@@ -17,9 +16,9 @@ pub fn proof_size(f_i_size: usize, t_i_size: usize) -> usize{
             let index = rand::random::<usize>() % big_n;
             t_i[index]
         }).collect();
-        let cq = Cq::<Bn254>::new(&t_i).unwrap();
-        let mut prover = Prover::<Sha256, Bn254>::new(f_i.clone()).unwrap();
-        let proof = prover.prove(&cq, &t_i).unwrap();
+        let Cq { pk, .. } = Cq::<Bn254>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bn254>::new(f_i.clone()).unwrap();
+        let proof = prover.prove(&pk).unwrap();
         proof.serialized_size(Compress::No)
 }
 
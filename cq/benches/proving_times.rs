@@ -1,37 +1,69 @@
 // benchmark the time of compute cq
 
-use ark_bn254::{Bn254, Fr};
+use std::ops::Mul;
+use ark_bn254::{Bn254, Fr, G1Affine};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_poly::DenseUVPolynomial;
+use ark_poly::univariate::DensePolynomial;
 use ark_std::UniformRand;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use sha2::Sha256;
 use cq::cq::Cq;
+use cq::kzg::Kzg;
 use cq::prover::Prover;
 
+const TABLE_SIZE: u32 = 16;
+
+// Proving against a 2^16 table with witnesses of 2^10..2^16 values.
 pub fn prove(c: &mut Criterion) {
-    for size in [6, 10, 15] {
-        let big_n = 2_usize.pow(size);
-        let small_n = 2_usize.pow(size/2);
-        let mut rng = ark_std::test_rng();
+    let big_n = 2_usize.pow(TABLE_SIZE);
+    let mut rng = ark_std::test_rng();
+    let t_i: Vec<Fr> = (0..big_n).map(|_| Fr::rand(&mut rng)).collect();
+    let Cq { pk, .. } = Cq::<Bn254>::new(&t_i).unwrap();
 
-        let t_i: Vec<Fr> = (0..big_n).map(|_| Fr::rand(&mut rng)).collect();
+    let mut group = c.benchmark_group("prove");
+    for size in 10..=16 {
+        let small_n = 2_usize.pow(size);
         let f_i: Vec<Fr> = (0..small_n).map(|_| {
             let index = rand::random::<usize>() % big_n;
             t_i[index]
         }).collect();
-        let cq = Cq::<Bn254>::new(&t_i).unwrap();
-        c.bench_with_input(BenchmarkId::new("[prove]: t_i size = ", size), &size, |b, _| {
+        group.bench_with_input(BenchmarkId::new("f_i size", size), &size, |b, _| {
             b.iter(|| {
-                let mut prover = Prover::<Sha256, Bn254>::new(f_i.clone()).unwrap();
-                let proof = prover.prove(&cq, &t_i).unwrap();
-                proof
+                let mut prover = Prover::<Bn254>::new(f_i.clone()).unwrap();
+                prover.prove(&pk).unwrap()
             });
         });
     }
+    group.finish();
+}
+
+// The commitments of the prover, with one scalar multiplication per coefficient folded with
+// affine additions as before, and with Kzg::commit_g1.
+pub fn commit(c: &mut Criterion) {
+    let mut rng = ark_std::test_rng();
+    let kzg = Kzg::<Bn254>::new(2_usize.pow(TABLE_SIZE));
+
+    let mut group = c.benchmark_group("commit_g1");
+    for size in 10..=16 {
+        let coeffs: Vec<Fr> = (0..2_usize.pow(size)).map(|_| Fr::rand(&mut rng)).collect();
+        let poly = DensePolynomial::from_coefficients_vec(coeffs);
+        group.bench_with_input(BenchmarkId::new("naive", size), &size, |b, _| {
+            b.iter(|| {
+                poly.coeffs.iter().zip(&kzg.g1_srs)
+                    .map(|(coeff, point)| point.mul(coeff).into_affine())
+                    .fold(G1Affine::zero(), |acc, point| (acc + point).into_affine())
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("msm", size), &size, |b, _| {
+            b.iter(|| kzg.commit_g1(&poly));
+        });
+    }
+    group.finish();
 }
 
 criterion_group!{
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets = prove
+    targets = prove, commit
 }
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+// benchmark the time of generating an SRS from a secret
+
+use ark_bn254::{Bn254, Fr};
+use ark_std::UniformRand;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use cq::kzg::Kzg;
+
+// 2^size powers in G1, with 2^size + 1 powers in G2 as Cq::new needs, or only 2 as in a ceremony.
+pub fn new_from_secret(c: &mut Criterion) {
+    let s = Fr::rand(&mut ark_std::test_rng());
+    let mut group = c.benchmark_group("new_from_secret");
+    for size in [12, 16, 20] {
+        let len = 2_usize.pow(size);
+        group.bench_with_input(BenchmarkId::new("g2 len = len + 1", size), &len, |b, len| {
+            b.iter(|| Kzg::<Bn254>::new_from_secret(s, *len));
+        });
+        group.bench_with_input(BenchmarkId::new("g2 len = 2", size), &len, |b, len| {
+            b.iter(|| Kzg::<Bn254>::new_from_secret_with_lengths(s, *len, 2));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!{
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = new_from_secret
+}
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+// benchmark the time of verifying a batch of cq proofs, reported per proof
+
+use ark_bn254::{Bn254, Fr};
+use ark_std::UniformRand;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cq::cq::Cq;
+use cq::prover::Prover;
+use cq::verifier::Verifier;
+
+pub fn verify_batch(c: &mut Criterion) {
+    let size = 10;
+    let big_n = 2_usize.pow(size);
+    let small_n = 2_usize.pow(size / 2);
+    let mut rng = ark_std::test_rng();
+
+    let t_i: Vec<Fr> = (0..big_n).map(|_| Fr::rand(&mut rng)).collect();
+    let Cq { pk, vk } = Cq::<Bn254>::new(&t_i).unwrap();
+    let pvk = vk.prepare();
+    let proofs: Vec<_> = (0..256).map(|_| {
+        let f_i: Vec<Fr> = (0..small_n).map(|_| {
+            let index = rand::random::<usize>() % big_n;
+            t_i[index]
+        }).collect();
+        Prover::<Bn254>::new(f_i).unwrap().prove(&pk).unwrap()
+    }).collect();
+
+    let mut group = c.benchmark_group("[verify batch]: t_i size = 2^10");
+    for batch_size in [1, 16, 64, 256] {
+        // elements are proofs, so the throughput is the cost per proof
+        group.throughput(Throughput::Elements(batch_size as u64));
+        group.bench_with_input(BenchmarkId::new("proofs = ", batch_size), &batch_size, |b, &batch_size| {
+            b.iter(|| Verifier::<Bn254>::verify_batch(&[&pvk], &proofs[..batch_size]));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!{
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = verify_batch
+}
+criterion_main!(benches);
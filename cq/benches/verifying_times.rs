@@ -1,17 +1,18 @@
-// benchmark the time of compute cq
+// benchmark the time of verifying a cq proof.
+// With a prepared verifying key the verification cost does not depend on the table size,
+// so the timings should stay flat from 2^6 to 2^20.
 
 use ark_bn254::{Bn254, Fr};
 use ark_std::UniformRand;
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use sha2::Sha256;
 use cq::cq::Cq;
 use cq::prover::Prover;
 use cq::verifier::Verifier;
 
 pub fn verify(c: &mut Criterion) {
-    for size in [6, 10, 15] {
+    for size in [6, 8, 10, 12, 14, 16, 18, 20] {
         let big_n = 2_usize.pow(size);
-        let small_n = 2_usize.pow(size/2);
+        let small_n = 2_usize.pow(4);
         let mut rng = ark_std::test_rng();
 
         let t_i: Vec<Fr> = (0..big_n).map(|_| Fr::rand(&mut rng)).collect();
@@ -19,15 +20,15 @@ pub fn verify(c: &mut Criterion) {
             let index = rand::random::<usize>() % big_n;
             t_i[index]
         }).collect();
-        let cq = Cq::<Bn254>::new(&t_i).unwrap();
-        let mut prover = Prover::<Sha256, Bn254>::new(f_i.clone()).unwrap();
-        let proof = prover.prove(&cq, &t_i).unwrap();
+        let Cq { pk, vk } = Cq::<Bn254>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bn254>::new(f_i.clone()).unwrap();
+        let proof = prover.prove(&pk).unwrap();
+        let pvk = vk.prepare();
         
         c.bench_with_input(BenchmarkId::new("[verify]: t_i size = ", size), &size, |b, _| {
             b.iter(|| {
-                let mut verifier = Verifier::<Sha256, Bn254>::new();
-                let result = verifier.verify(&t_i, &cq, proof.clone());
-                result
+                let mut verifier = Verifier::<Bn254>::new();
+                verifier.verify(&pvk, proof.clone())
             });
         });
     }
@@ -38,4 +39,4 @@ criterion_group!{
     config = Criterion::default().sample_size(10);
     targets = verify
 }
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
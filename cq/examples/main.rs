@@ -1,6 +1,5 @@
 use ark_bn254::{Bn254, Fr};
 use ark_std::UniformRand;
-use sha2::Sha256;
 use cq::cq::Cq;
 use cq::prover::Prover;
 use cq::verifier::Verifier;
@@ -18,25 +17,25 @@ fn main() {
     }).collect();
     
     let start = std::time::Instant::now();
-    let cq = Cq::<Bn254>::new(&t_i).unwrap();
+    let Cq { pk, vk } = Cq::<Bn254>::new(&t_i).unwrap();
     println!("Time to create Cq: {:?}", start.elapsed());
 
     let start = std::time::Instant::now();
-    let mut prover = Prover::<Sha256, Bn254>::new(f_i).unwrap();
+    let mut prover = Prover::<Bn254>::new(f_i).unwrap();
     println!("Time to create Prover: {:?}", start.elapsed());
     
     let start = std::time::Instant::now();
-    let proof = prover.prove(&cq, &t_i).unwrap();
+    let proof = prover.prove(&pk).unwrap();
     println!("Time to generate proof: {:?}", start.elapsed());
     
     let start = std::time::Instant::now();
-    let mut verifier = Verifier::<Sha256, Bn254>::new();
+    let mut verifier = Verifier::<Bn254>::new();
+    let pvk = vk.prepare();
     println!("Time to create Verifier: {:?}", start.elapsed());
     
     let start = std::time::Instant::now();
-    let result = verifier.batched_verify(&t_i, &cq, proof);
+    let result = verifier.batched_verify(&pvk, proof);
     println!("Time to verify proof: {:?}", start.elapsed());
     assert!(result.is_ok());
-    assert!(result.unwrap());
     println!("Accepted");
 }
\ No newline at end of file
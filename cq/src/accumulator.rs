@@ -0,0 +1,185 @@
+// Pairing checks whose final pairing is deferred, so the checks of cq proofs can be settled
+// together with the pairing checks of a larger KZG-based proof system.
+//
+// An accumulator holds, for each G2 base, a left and a right G1 point, and stands for the check
+// Π e(left, base) = Π e(right, base). Independent accumulators are merged with a random scalar,
+// so a failing check cannot be cancelled by another one.
+
+use std::ops::Mul;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ec::pairing::Pairing;
+use ark_ff::{UniformRand, Zero};
+use rand::thread_rng;
+use crate::errors::VerificationError;
+use crate::kzg::Kzg;
+
+#[derive(Debug, Clone)]
+pub struct PairingAccumulator<P: Pairing> {
+    // (G2 base, left G1 point, right G1 point), one entry for each distinct base
+    terms: Vec<(P::G2Affine, P::G1, P::G1)>,
+}
+
+impl <P: Pairing> PairingAccumulator<P> {
+    pub fn new() -> Self {
+        Self {
+            terms: vec![],
+        }
+    }
+
+    // The G1 points paired with the base g2.
+    fn entry(&mut self, g2: P::G2Affine) -> &mut (P::G2Affine, P::G1, P::G1) {
+        let index = match self.terms.iter().position(|(base, _, _)| *base == g2) {
+            Some(index) => index,
+            None => {
+                self.terms.push((g2, P::G1::zero(), P::G1::zero()));
+                self.terms.len() - 1
+            }
+        };
+        &mut self.terms[index]
+    }
+
+    // Adds e(g1, g2) to the left side.
+    pub fn add_left(&mut self, g1: P::G1, g2: P::G2Affine) {
+        self.entry(g2).1 += g1;
+    }
+
+    // Adds e(g1, g2) to the right side.
+    pub fn add_right(&mut self, g1: P::G1, g2: P::G2Affine) {
+        self.entry(g2).2 += g1;
+    }
+
+    // The accumulated (G2 base, left, right) points, to merge into a pairing check elsewhere.
+    pub fn terms(&self) -> &[(P::G2Affine, P::G1, P::G1)] {
+        &self.terms
+    }
+
+    // The check of a KZG opening f(z) = f_z of commitment with the opening proof,
+    // e(commitment - [f_z]_1 + z·opening, [1]_2) = e(opening, [x]_2), as in Kzg::verify.
+    pub fn kzg_opening(kzg: &Kzg<P>, commitment: P::G1Affine, opening: P::G1Affine, z: P::ScalarField, f_z: P::ScalarField) -> Self {
+        let mut accumulator = Self::new();
+        let lhs = commitment.into_group() - kzg.g1_srs[0].mul(f_z) + opening.mul(z);
+        accumulator.add_left(lhs, kzg.g2_srs[0]);
+        accumulator.add_right(opening.into_group(), kzg.g2_srs[1]);
+        accumulator
+    }
+
+    // Merges the checks of other scaled by a random scalar.
+    pub fn accumulate(&mut self, other: Self) {
+        self.accumulate_with_scalar(other, P::ScalarField::rand(&mut thread_rng()));
+    }
+
+    // Merges the checks of other scaled by r, e.g. a challenge of an outer transcript.
+    pub fn accumulate_with_scalar(&mut self, other: Self, r: P::ScalarField) {
+        for (g2, left, right) in other.terms {
+            let entry = self.entry(g2);
+            entry.1 += left.mul(r);
+            entry.2 += right.mul(r);
+        }
+    }
+
+    // Runs the accumulated checks with one multi-pairing.
+    pub fn finalize(self) -> Result<(), VerificationError> {
+        if self.terms.is_empty() {
+            return Ok(());
+        }
+        let (g1, g2): (Vec<P::G1>, Vec<P::G2Prepared>) = self.terms.into_iter()
+            .map(|(g2, left, right)| (left - right, g2.into()))
+            .unzip();
+        let res = P::multi_pairing(P::G1::normalize_batch(&g1), g2);
+        if !res.is_zero() {
+            return Err(VerificationError::AccumulatorCheckFailed);
+        }
+        Ok(())
+    }
+}
+
+impl <P: Pairing> Default for PairingAccumulator<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr, G1Affine};
+    use ark_poly::DenseUVPolynomial;
+    use ark_poly::univariate::DensePolynomial;
+    use crate::cq::Cq;
+    use crate::prover::Prover;
+    use crate::verifier::Verifier;
+    use super::*;
+
+    #[test]
+    fn test_deferred_cq_and_kzg_checks() {
+        let kzg = Kzg::<Bls12_381>::new(32);
+        let range: Vec<Fr> = (0..16).map(Fr::from).collect();
+        let squares: Vec<Fr> = (0..8u64).map(|i| Fr::from(i * i)).collect();
+        let range_cq = Cq::new_with_srs(&range, &kzg).unwrap();
+        let squares_cq = Cq::new_with_srs(&squares, &kzg).unwrap();
+        let range_pvk = range_cq.vk.prepare();
+        let squares_pvk = squares_cq.vk.prepare();
+
+        let range_proof = Prover::<Bls12_381>::new(vec![Fr::from(3), Fr::from(15), Fr::from(3)]).unwrap().prove(&range_cq.pk).unwrap();
+        let squares_proof = Prover::<Bls12_381>::new(vec![Fr::from(36), Fr::from(1)]).unwrap().prove(&squares_cq.pk).unwrap();
+
+        // an opening of the outer proof system
+        let poly = DensePolynomial::from_coefficients_slice(&[Fr::from(1), Fr::from(2), Fr::from(3)]);
+        let commitment = kzg.commit_g1(&poly);
+        let z = Fr::from(7);
+        let (opening, f_z) = kzg.open_g1(&poly, z);
+
+        let mut accumulator = PairingAccumulator::kzg_opening(&kzg, commitment, opening, z, f_z);
+        accumulator.accumulate(Verifier::<Bls12_381>::new().deferred_batched_verify(&range_pvk, range_proof.clone()).unwrap());
+        accumulator.accumulate(Verifier::<Bls12_381>::new().deferred_batched_verify(&squares_pvk, squares_proof.clone()).unwrap());
+        // [1]_2, [x]_2, and for each table [T(x)]_2, [Zv(x)]_2 and the degree check bases of its
+        // witness and table sizes, 4, 16 and 2, 8, all distinct
+        assert_eq!(accumulator.terms().len(), 2 + 2 * 4);
+        assert!(accumulator.clone().finalize().is_ok());
+
+        // a tampered opening proof among the accumulated checks
+        let bad_proof = (opening.into_group() + G1Affine::generator()).into_affine();
+        let mut tampered_opening = PairingAccumulator::kzg_opening(&kzg, commitment, bad_proof, z, f_z);
+        tampered_opening.accumulate(Verifier::<Bls12_381>::new().deferred_batched_verify(&range_pvk, range_proof.clone()).unwrap());
+        tampered_opening.accumulate(Verifier::<Bls12_381>::new().deferred_batched_verify(&squares_pvk, squares_proof.clone()).unwrap());
+        assert_eq!(tampered_opening.terms().len(), accumulator.terms().len());
+        assert_eq!(tampered_opening.finalize(), Err(VerificationError::AccumulatorCheckFailed));
+
+        // a wrong opening makes the whole check fail
+        let mut bad_opening = PairingAccumulator::kzg_opening(&kzg, commitment, opening, z, f_z + Fr::from(1));
+        bad_opening.accumulate(accumulator.clone());
+        assert_eq!(bad_opening.finalize(), Err(VerificationError::AccumulatorCheckFailed));
+
+        // so does a tampered cq proof
+        let mut tampered = range_proof;
+        tampered.cm1_b_0 = G1Affine::generator();
+        let mut with_tampered = accumulator.clone();
+        with_tampered.accumulate(Verifier::<Bls12_381>::new().deferred_batched_verify(&range_pvk, tampered).unwrap());
+        assert_eq!(with_tampered.finalize(), Err(VerificationError::AccumulatorCheckFailed));
+
+        // a proof against the wrong table
+        let mut wrong_table = PairingAccumulator::new();
+        wrong_table.accumulate(Verifier::<Bls12_381>::new().deferred_batched_verify(&range_pvk, squares_proof).unwrap());
+        assert!(wrong_table.finalize().is_err());
+
+        assert!(PairingAccumulator::<Bls12_381>::new().finalize().is_ok());
+    }
+
+    #[test]
+    fn test_failing_checks_do_not_cancel() {
+        let kzg = Kzg::<Bls12_381>::new(8);
+        let poly = DensePolynomial::from_coefficients_slice(&[Fr::from(4), Fr::from(5)]);
+        let commitment = kzg.commit_g1(&poly);
+        let z = Fr::from(2);
+        let (opening, f_z) = kzg.open_g1(&poly, z);
+
+        // f_z + 1 and f_z - 1 would cancel if the accumulators were added without a scalar
+        let one = Fr::from(1);
+        let mut accumulator = PairingAccumulator::kzg_opening(&kzg, commitment, opening, z, f_z + one);
+        accumulator.accumulate(PairingAccumulator::kzg_opening(&kzg, commitment, opening, z, f_z - one));
+        assert!(accumulator.finalize().is_err());
+
+        let mut accumulator = PairingAccumulator::kzg_opening(&kzg, commitment, opening, z, f_z + one);
+        accumulator.accumulate_with_scalar(PairingAccumulator::kzg_opening(&kzg, commitment, opening, z, f_z - one), one);
+        assert!(accumulator.finalize().is_ok());
+    }
+}
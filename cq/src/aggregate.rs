@@ -0,0 +1,549 @@
+// One cq proof for k witness columns f_0..f_{k-1} looked up into the same table, whose size does
+// not depend on k. The column commitments [f_j(x)]_1 are part of the statement, as the commitment
+// of a single witness in Prover::prove_committed, and are not carried by the proof.
+//
+// The columns, of common size n, are padded with copies of f_0 to K = k.next_power_of_two()
+// columns and interleaved into one witness F of size K·n, whose entry j + K·i is f_j(ω^i). With
+// ω' the generator of the domain of size K·n, ω = ω'^K and F(ω'^j·X) = f_j(X) on the domain H of
+// the columns. The proof is a single cq proof for F, plus an argument that [F(x)]_1 holds the
+// columns [f_j(x)]_1:
+// - the identities F(ω'^j·X) = f_j(X) on H are combined with the weights mu_j = L_j(tau) of the
+//   Lagrange basis over Ω = {ω'^j, j < 2K - 1}, the rows spilling into the next row of f_{j - K}:
+//   Σ_j mu_j·F(ω'^j·X) - Σ_{j<K} mu_j·f_j(X) - Σ_{j<K-1} mu_{K+j}·f_j(ω·X) = Q_C(X)·Z_H(X),
+// - at a challenge zeta, Σ_j mu_j·F(ω'^j·zeta) = R(tau·zeta) where R, of degree at most 2K - 2,
+//   interpolates F on zeta·Ω, shown by F - R = Q_R·Z_{zeta·Ω} at a challenge xi,
+// - the combinations of the f_j are opened from the combinations of the [f_j(x)]_1, so the
+//   argument adds 8 G1 elements and 5 scalars to the proof whatever the number of columns.
+// Verification is one multi-pairing, as for a single proof.
+
+use std::marker::PhantomData;
+use std::ops::{Add, Mul, Sub};
+use ark_ec::{AffineRepr, CurveGroup, VariableBaseMSM};
+use ark_ec::pairing::Pairing;
+use ark_ff::{batch_inversion, Field, One, UniformRand, Zero};
+use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain, Polynomial};
+use ark_poly::univariate::{DenseOrSparsePolynomial, DensePolynomial};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use rand::thread_rng;
+use sha2::Sha256;
+use crate::cq::{PreparedVerifyingKey, ProvingKey};
+use crate::errors::{GeneralError, VerificationError};
+use crate::fiat_shamir::{Script, Transcript};
+use crate::prover::{Proof, Prover};
+use crate::verifier::{combined_pairing_is_one, powers, scaled_mu_powers, PairingTerms, RoundValues, Verifier};
+
+pub const AGGREGATED_PROTOCOL_LABEL: &[u8] = b"cq-aggregated-lookup-v1";
+
+#[derive(Debug, Clone, CanonicalDeserialize, CanonicalSerialize)]
+pub struct AggregatedProof<P: Pairing> {
+    // the cq proof for the interleaved witness F of size K·n
+    pub proof: Proof<P>,
+    pub cm1_q_c: P::G1Affine,
+    pub cm1_r: P::G1Affine,
+    // [x^{D-(2K-2)}·R(x)]_1 for the degree check of R
+    pub cm1_p_r: P::G1Affine,
+    pub cm1_q_r: P::G1Affine,
+    // Σ_{j<K} mu_j·f_j(zeta) and Σ_{j<K-1} mu_{K+j}·f_j(ω·zeta)
+    pub c_zeta: P::ScalarField,
+    pub c_omega_zeta: P::ScalarField,
+    pub r_tau_zeta: P::ScalarField,
+    pub f_xi: P::ScalarField,
+    pub r_xi: P::ScalarField,
+    // the opening proofs at zeta, ω·zeta, tau·zeta and xi
+    pub cm1_pi_zeta: P::G1Affine,
+    pub cm1_pi_omega_zeta: P::G1Affine,
+    pub cm1_pi_tau_zeta: P::G1Affine,
+    pub cm1_pi_xi: P::G1Affine,
+}
+
+// The witness column interleaved at position j of the K columns, the padding columns are f_0.
+fn column(j: usize, k: usize) -> usize {
+    if j < k { j } else { 0 }
+}
+
+// L_j(x) for the Lagrange basis L_0..L_{m-1} over the m distinct points.
+fn lagrange_coefficients<F: Field>(points: &[F], x: F) -> Vec<F> {
+    let others = |j: usize| points.iter().enumerate().filter(move |(m, _)| *m != j).map(|(_, point)| *point);
+    let mut denominators: Vec<F> = points.iter().enumerate()
+        .map(|(j, point)| others(j).map(|other| *point - other).product())
+        .collect();
+    batch_inversion(&mut denominators);
+    denominators.into_iter().enumerate()
+        .map(|(j, denominator)| others(j).map(|other| x - other).product::<F>() * denominator)
+        .collect()
+}
+
+// The weights of the witness columns in the combinations opened at zeta and at ω·zeta.
+fn column_weights<F: Field>(mu: &[F], k: usize) -> (Vec<F>, Vec<F>) {
+    let big_k = k.next_power_of_two();
+    let mut at_zeta = vec![F::zero(); k];
+    let mut at_omega_zeta = vec![F::zero(); k];
+    for j in 0..big_k {
+        at_zeta[column(j, k)] += mu[j];
+        if j + 1 < big_k {
+            at_omega_zeta[column(j, k)] += mu[big_k + j];
+        }
+    }
+    (at_zeta, at_omega_zeta)
+}
+
+// The witness columns padded to the common size n, with their polynomials over the domain of size n.
+type PaddedColumns<F> = (Vec<Vec<F>>, Vec<DensePolynomial<F>>);
+
+// The transcript defaults to SHA-256, as for a single proof.
+pub struct AggregatedProver<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    f_columns: Vec<Vec<P::ScalarField>>,
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl <P: Pairing, T: Transcript<P>> AggregatedProver<P, T> {
+    // The columns can have different sizes, they are all padded to the same power of two
+    // n >= 2 with the first entry of the table, as in Prover::new.
+    pub fn new(f_columns: Vec<Vec<P::ScalarField>>) -> Result<Self, GeneralError> {
+        if f_columns.is_empty() || f_columns.iter().any(|f_i| f_i.is_empty()) {
+            return Err(GeneralError::EmptyWitness);
+        }
+        Ok(Self {
+            f_columns,
+            _phantom_data: PhantomData,
+        })
+    }
+
+    fn padded_columns(&self, pk: &ProvingKey<P>) -> Result<PaddedColumns<P::ScalarField>, GeneralError> {
+        let big_k = self.f_columns.len().next_power_of_two();
+        let small_n = self.f_columns.iter().map(|f_i| f_i.len()).max().unwrap().next_power_of_two().max(2);
+        if big_k * small_n > pk.kzg.max_degree() + 1 {
+            return Err(GeneralError::WitnessLargerThanSrs);
+        }
+        let f_columns: Vec<Vec<P::ScalarField>> = self.f_columns.iter()
+            .map(|f_i| {
+                let mut f_i = f_i.clone();
+                f_i.resize(small_n, pk.t_i[0]);
+                f_i
+            })
+            .collect();
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(small_n).unwrap();
+        let f_x = f_columns.iter()
+            .map(|f_i| DensePolynomial::from_coefficients_vec(domain.ifft(f_i)))
+            .collect();
+        Ok((f_columns, f_x))
+    }
+
+    // The commitments [f_j(x)]_1 of the witness columns padded to the common size n, for a
+    // caller that has not committed to them in another protocol.
+    pub fn commit_columns(&self, pk: &ProvingKey<P>) -> Result<Vec<P::G1Affine>, GeneralError> {
+        let (_, f_x) = self.padded_columns(pk)?;
+        Ok(f_x.iter().map(|f_x| pk.kzg.commit_g1(f_x)).collect())
+    }
+
+    // cm1_f must hold the commitments of the padded columns, as returned by commit_columns,
+    // the verifier is given the same ones.
+    pub fn prove(&self, pk: &ProvingKey<P>, cm1_f: &[P::G1Affine]) -> Result<AggregatedProof<P>, GeneralError> {
+        let k = self.f_columns.len();
+        let big_k = k.next_power_of_two();
+        let (f_columns, f_x) = self.padded_columns(pk)?;
+        let small_n = f_columns[0].len();
+        if cm1_f.len() != k || f_x.iter().zip(cm1_f).any(|(f_x, cm1_f_j)| pk.kzg.commit_g1(f_x) != *cm1_f_j) {
+            return Err(GeneralError::WitnessCommitmentMismatch);
+        }
+
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(small_n).unwrap();
+        let interleaved_domain = GeneralEvaluationDomain::<P::ScalarField>::new(big_k * small_n).unwrap();
+
+        let mut script = T::new(AGGREGATED_PROTOCOL_LABEL);
+        script.append_statement(&pk.verifying_key(), small_n);
+        script.append_u64(b"k", k as u64);
+        for cm1_f_j in cm1_f {
+            script.append_point(b"cm1_f", cm1_f_j);
+        }
+
+        // the cq proof for F
+        let interleaved: Vec<P::ScalarField> = (0..big_k * small_n)
+            .map(|p| f_columns[column(p % big_k, k)][p / big_k])
+            .collect();
+        let mut prover = Prover::<P, T>::with_transcript(interleaved.clone(), script)?;
+        let proof = prover.prove(pk)?;
+        let mut script = prover.into_transcript();
+        Verifier::<P, T>::append_batch_commitments(&mut script, &proof);
+
+        // Q_C, the coefficient p of Σ_j mu_j·F(ω'^j·X) being M(ω'^p) times the one of F,
+        // with M(X) = Σ_j mu_j·X^j
+        let tau = script.challenge_scalar(b"tau");
+        let omega_points = powers(P::ScalarField::one(), interleaved_domain.group_gen(), 2 * big_k - 1);
+        let mu = lagrange_coefficients(&omega_points, tau);
+        let (w_zeta, w_omega_zeta) = column_weights(&mu, k);
+        let m_evals = interleaved_domain.fft(&mu);
+        let mut numerator = interleaved_domain.ifft(&interleaved);
+        let f_interleaved_x = DensePolynomial::from_coefficients_slice(&numerator);
+        for (coeff, m) in numerator.iter_mut().zip(&m_evals) {
+            *coeff *= m;
+        }
+        let mut omega_p = P::ScalarField::one();
+        for (p, coeff) in numerator.iter_mut().take(small_n).enumerate() {
+            for ((f_j_x, w_zeta_j), w_omega_zeta_j) in f_x.iter().zip(&w_zeta).zip(&w_omega_zeta) {
+                if let Some(f_j_coeff) = f_j_x.coeffs.get(p) {
+                    *coeff -= *f_j_coeff * (*w_zeta_j + *w_omega_zeta_j * omega_p);
+                }
+            }
+            omega_p *= domain.group_gen();
+        }
+        let (q_c_x, rem) = DensePolynomial::from_coefficients_vec(numerator).divide_by_vanishing_poly(domain);
+        if !rem.is_zero() {
+            return Err(GeneralError::CannotDivideByVanishingPolynomial);
+        }
+        let cm1_q_c = pk.kzg.commit_g1(&q_c_x);
+        script.append_point(b"cm1_q_c", &cm1_q_c);
+
+        // R and Q_R, from the division of F by the vanishing polynomial of zeta·Ω
+        let zeta = script.challenge_scalar(b"zeta");
+        let mut z_x = DensePolynomial::from_coefficients_vec(vec![P::ScalarField::one()]);
+        for point in &omega_points {
+            z_x = z_x.naive_mul(&DensePolynomial::from_coefficients_vec(vec![-zeta * point, P::ScalarField::one()]));
+        }
+        let (q_r_x, r_x) = DenseOrSparsePolynomial::from(&f_interleaved_x)
+            .divide_with_q_and_r(&DenseOrSparsePolynomial::from(&z_x))
+            .unwrap();
+        let cm1_r = pk.kzg.commit_g1(&r_x);
+        let cm1_p_r = pk.kzg.fast_commit_g1(&r_x, pk.kzg.max_degree() + 2 - 2 * big_k);
+        let cm1_q_r = pk.kzg.commit_g1(&q_r_x);
+        script.append_point(b"cm1_r", &cm1_r);
+        script.append_point(b"cm1_p_r", &cm1_p_r);
+        script.append_point(b"cm1_q_r", &cm1_q_r);
+
+        let xi = script.challenge_scalar(b"xi");
+        let mut c_zeta_x = DensePolynomial::zero();
+        let mut c_omega_zeta_x = DensePolynomial::zero();
+        for ((f_j_x, w_zeta_j), w_omega_zeta_j) in f_x.iter().zip(&w_zeta).zip(&w_omega_zeta) {
+            c_zeta_x += (*w_zeta_j, f_j_x);
+            c_omega_zeta_x += (*w_omega_zeta_j, f_j_x);
+        }
+        let omega_zeta = domain.group_gen() * zeta;
+        let c_zeta = c_zeta_x.evaluate(&zeta);
+        let c_omega_zeta = c_omega_zeta_x.evaluate(&omega_zeta);
+        let r_tau_zeta = r_x.evaluate(&(tau * zeta));
+        let f_xi = f_interleaved_x.evaluate(&xi);
+        let r_xi = r_x.evaluate(&xi);
+        for value in [&c_zeta, &c_omega_zeta, &r_tau_zeta, &f_xi, &r_xi] {
+            script.append_scalar(b"value", value);
+        }
+
+        // the polynomials opened at the same point are combined with the powers of nu
+        let nu = script.challenge_scalar(b"nu");
+        let (cm1_pi_zeta, _) = pk.kzg.open_g1(&(c_zeta_x + q_c_x.mul(nu)), zeta);
+        let (cm1_pi_omega_zeta, _) = pk.kzg.open_g1(&c_omega_zeta_x, omega_zeta);
+        let (cm1_pi_tau_zeta, _) = pk.kzg.open_g1(&r_x, tau * zeta);
+        let (cm1_pi_xi, _) = pk.kzg.open_g1(&(f_interleaved_x + r_x.mul(nu) + q_r_x.mul(nu * nu)), xi);
+
+        Ok(AggregatedProof {
+            proof,
+            cm1_q_c,
+            cm1_r,
+            cm1_p_r,
+            cm1_q_r,
+            c_zeta,
+            c_omega_zeta,
+            r_tau_zeta,
+            f_xi,
+            r_xi,
+            cm1_pi_zeta,
+            cm1_pi_omega_zeta,
+            cm1_pi_tau_zeta,
+            cm1_pi_xi,
+        })
+    }
+}
+
+// The left sides c - [v]_1 + z·[pi]_1 of the openings at zeta, ω·zeta, tau·zeta and xi.
+struct ColumnValues<P: Pairing> {
+    big_k: usize,
+    cm1_kzg_lhs: [P::G1; 4],
+}
+
+// The transcript must match the one the proof was generated with.
+pub struct AggregatedVerifier<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl <P: Pairing, T: Transcript<P>> AggregatedVerifier<P, T> {
+    pub fn new() -> Self {
+        Self {
+            _phantom_data: PhantomData,
+        }
+    }
+
+    // Replays the transcript, returns it with the round values of the cq proof for F
+    // and the values of the checks that F holds the columns.
+    fn replay(pvk: &PreparedVerifyingKey<P>, proof: &AggregatedProof<P>, cm1_f: &[P::G1Affine]) -> Result<(T, RoundValues<P>, ColumnValues<P>), VerificationError> {
+        let vk = &pvk.vk;
+        let k = cm1_f.len();
+        let big_k = k.next_power_of_two();
+        let interleaved_n = proof.proof.small_n;
+        if k == 0 || interleaved_n < 2 * big_k {
+            return Err(VerificationError::ColumnCountMismatch);
+        }
+        let small_n = interleaved_n / big_k;
+
+        let mut script = T::new(AGGREGATED_PROTOCOL_LABEL);
+        script.append_statement(vk, small_n);
+        script.append_u64(b"k", k as u64);
+        for cm1_f_j in cm1_f {
+            script.append_point(b"cm1_f", cm1_f_j);
+        }
+
+        let mut verifier = Verifier::<P, T>::with_transcript(script);
+        let values = verifier.compute_round_values(pvk, &proof.proof)?;
+        let mut script = verifier.into_transcript();
+        Verifier::<P, T>::append_batch_commitments(&mut script, &proof.proof);
+
+        let tau = script.challenge_scalar(b"tau");
+        script.append_point(b"cm1_q_c", &proof.cm1_q_c);
+        let zeta = script.challenge_scalar(b"zeta");
+        script.append_point(b"cm1_r", &proof.cm1_r);
+        script.append_point(b"cm1_p_r", &proof.cm1_p_r);
+        script.append_point(b"cm1_q_r", &proof.cm1_q_r);
+        let xi = script.challenge_scalar(b"xi");
+        for value in [&proof.c_zeta, &proof.c_omega_zeta, &proof.r_tau_zeta, &proof.f_xi, &proof.r_xi] {
+            script.append_scalar(b"value", value);
+        }
+        let nu = script.challenge_scalar(b"nu");
+        for cm1_pi in [&proof.cm1_pi_zeta, &proof.cm1_pi_omega_zeta, &proof.cm1_pi_tau_zeta, &proof.cm1_pi_xi] {
+            script.append_point(b"cm1_pi", cm1_pi);
+        }
+
+        let omega_prime = GeneralEvaluationDomain::<P::ScalarField>::new(interleaved_n)
+            .ok_or(VerificationError::MalformedSizes { small_n: interleaved_n, big_n: vk.big_n })?
+            .group_gen();
+        let omega_points = powers(P::ScalarField::one(), omega_prime, 2 * big_k - 1);
+        let mu = lagrange_coefficients(&omega_points, tau);
+        let (w_zeta, w_omega_zeta) = column_weights(&mu, k);
+
+        // Q_C(zeta) and Q_R(xi) from the identities they are the quotients of
+        let z_h_zeta = zeta.pow([small_n as u64]) - P::ScalarField::one();
+        let z_h_zeta_inv = z_h_zeta.inverse().ok_or(VerificationError::NonInvertibleChallenge)?;
+        let q_c_zeta = (proof.r_tau_zeta - proof.c_zeta - proof.c_omega_zeta) * z_h_zeta_inv;
+        let z_xi: P::ScalarField = omega_points.iter().map(|point| xi - zeta * point).product();
+        let z_xi_inv = z_xi.inverse().ok_or(VerificationError::NonInvertibleChallenge)?;
+        let q_r_xi = (proof.f_xi - proof.r_xi) * z_xi_inv;
+
+        let kzg_lhs = |cm1_c: P::G1, z: P::ScalarField, v: P::ScalarField, cm1_pi: P::G1Affine| {
+            cm1_c.sub(vk.cm1_1.mul(v)).add(cm1_pi.mul(z))
+        };
+        let cm1_c_zeta = P::G1::msm_unchecked(cm1_f, &w_zeta) + proof.cm1_q_c.mul(nu);
+        let cm1_c_omega_zeta = P::G1::msm_unchecked(cm1_f, &w_omega_zeta);
+        let cm1_c_xi = proof.proof.cm1_f.into_group() + proof.cm1_r.mul(nu) + proof.cm1_q_r.mul(nu * nu);
+        let omega = omega_prime.pow([big_k as u64]);
+        let cm1_kzg_lhs = [
+            kzg_lhs(cm1_c_zeta, zeta, proof.c_zeta + nu * q_c_zeta, proof.cm1_pi_zeta),
+            kzg_lhs(cm1_c_omega_zeta, omega * zeta, proof.c_omega_zeta, proof.cm1_pi_omega_zeta),
+            kzg_lhs(proof.cm1_r.into_group(), tau * zeta, proof.r_tau_zeta, proof.cm1_pi_tau_zeta),
+            kzg_lhs(cm1_c_xi, xi, proof.f_xi + nu * proof.r_xi + nu * nu * q_r_xi, proof.cm1_pi_xi),
+        ];
+
+        Ok((script, values, ColumnValues { big_k, cm1_kzg_lhs }))
+    }
+
+    // The 4 openings and the degree check of R combined with the weights.
+    fn column_pairing_terms(proof: &AggregatedProof<P>, values: &ColumnValues<P>, weights: &[P::ScalarField; 5]) -> PairingTerms<P> {
+        let cm1_pi = [proof.cm1_pi_zeta, proof.cm1_pi_omega_zeta, proof.cm1_pi_tau_zeta, proof.cm1_pi_xi];
+        let mut cm1_1 = -proof.cm1_p_r.mul(weights[4]);
+        let mut cm1_x = P::G1::zero();
+        for ((lhs, cm1_pi), weight) in values.cm1_kzg_lhs.iter().zip(cm1_pi).zip(weights) {
+            cm1_1 -= lhs.mul(weight);
+            cm1_x += cm1_pi.mul(*weight);
+        }
+        PairingTerms {
+            cm1_1,
+            cm1_x,
+            cm1_x_pow: proof.cm1_r.mul(weights[4]),
+            cm1_t_x: P::G1::zero(),
+            cm1_z_v: P::G1::zero(),
+            cm1_a_0_degree: P::G1::zero(),
+        }
+    }
+
+    // e(c - [v]_1 + z·pi, [1]_2) = e(pi, [x]_2) for each opening and
+    // e(r, [x^{D-(2K-2)}]_2) = e(p_r, [1]_2), combined with random weights
+    fn check_columns(pvk: &PreparedVerifyingKey<P>, proof: &AggregatedProof<P>, values: &ColumnValues<P>) -> Result<(), VerificationError> {
+        let weights = scaled_mu_powers(P::ScalarField::rand(&mut thread_rng()), P::ScalarField::one());
+        let terms = Self::column_pairing_terms(proof, values, &weights);
+        let res = P::multi_pairing(
+            P::G1::normalize_batch(&[terms.cm1_1, terms.cm1_x, terms.cm1_x_pow]),
+            [pvk.cm2_1.clone(), pvk.cm2_x.clone(), pvk.degree_check_g2(2 * values.big_k).unwrap().clone()]
+        );
+        if !res.is_zero() {
+            return Err(VerificationError::ColumnLinkFailed);
+        }
+        Ok(())
+    }
+
+    // cm1_f holds the commitments [f_j(x)]_1 of the witness columns.
+    pub fn verify(&mut self, pvk: &PreparedVerifyingKey<P>, proof: AggregatedProof<P>, cm1_f: &[P::G1Affine]) -> Result<(), VerificationError> {
+        let cm2_x_pow = Verifier::<P, T>::check_sizes(pvk, proof.proof.small_n)?;
+        let (_, values, column_values) = Self::replay(pvk, &proof, cm1_f)?;
+        Verifier::<P, T>::check_all(pvk, &proof.proof, cm2_x_pow, &values)?;
+        Self::check_columns(pvk, &proof, &column_values)
+    }
+
+    // The checks of the cq proof weighted by mu^0..mu^4 and the ones of the columns by
+    // mu^5..mu^9, in one multi-pairing. When it fails, the checks are run one by one
+    // to report which one failed.
+    pub fn batched_verify(&mut self, pvk: &PreparedVerifyingKey<P>, proof: AggregatedProof<P>, cm1_f: &[P::G1Affine]) -> Result<(), VerificationError> {
+        let cm2_x_pow = Verifier::<P, T>::check_sizes(pvk, proof.proof.small_n)?;
+        let (mut script, values, column_values) = Self::replay(pvk, &proof, cm1_f)?;
+        let mu = script.challenge_scalar(b"mu");
+        let terms = vec![
+            (0, proof.proof.small_n, Verifier::<P, T>::batched_pairing_terms(&proof.proof, &values, &scaled_mu_powers(mu, P::ScalarField::one()))),
+            (0, 2 * column_values.big_k, Self::column_pairing_terms(&proof, &column_values, &scaled_mu_powers(mu, mu.pow([5])))),
+        ];
+        if !combined_pairing_is_one(&[pvk], terms) {
+            Verifier::<P, T>::check_all(pvk, &proof.proof, cm2_x_pow, &values)?;
+            Self::check_columns(pvk, &proof, &column_values)?;
+            return Err(VerificationError::BatchedCheckFailed);
+        }
+        Ok(())
+    }
+}
+
+impl <P: Pairing, T: Transcript<P>> Default for AggregatedVerifier<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr, G1Affine};
+    use ark_ec::AffineRepr;
+    use ark_serialize::{CanonicalSerialize, Compress};
+    use crate::cq::Cq;
+    use crate::errors::{GeneralError, VerificationError};
+    use crate::kzg::Kzg;
+    use crate::poseidon::PoseidonTranscript;
+    use super::*;
+
+    fn range_table() -> Vec<Fr> {
+        (0..16).map(Fr::from).collect()
+    }
+
+    fn columns(k: u64, n: u64) -> Vec<Vec<Fr>> {
+        (0..k)
+            .map(|j| (0..n).map(|i| Fr::from((i * j + 3) % 16)).collect())
+            .collect()
+    }
+
+    // The commitments of the columns and a proof for them.
+    fn prove<T: Transcript<Bls12_381>>(pk: &ProvingKey<Bls12_381>, f_columns: Vec<Vec<Fr>>) -> Result<(Vec<G1Affine>, AggregatedProof<Bls12_381>), GeneralError> {
+        let prover = AggregatedProver::<Bls12_381, T>::new(f_columns)?;
+        let cm1_f = prover.commit_columns(pk)?;
+        let proof = prover.prove(pk, &cm1_f)?;
+        Ok((cm1_f, proof))
+    }
+
+    #[test]
+    fn test_aggregated_proof() {
+        let kzg = Kzg::<Bls12_381>::new(64);
+        let Cq { pk, vk } = Cq::new_with_srs(&range_table(), &kzg).unwrap();
+        let pvk = vk.prepare();
+
+        let f_columns = columns(6, 8);
+        let (cm1_f, proof) = prove::<Script<Sha256, Bls12_381>>(&pk, f_columns.clone()).unwrap();
+        assert_eq!(cm1_f.len(), 6);
+        assert_eq!(proof.proof.small_n, 8 * 8);
+        assert!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, proof.clone(), &cm1_f).is_ok());
+        assert!(AggregatedVerifier::<Bls12_381>::new().batched_verify(&pvk, proof.clone(), &cm1_f).is_ok());
+
+        let (cm1_f, proof) = prove::<PoseidonTranscript<Bls12_381>>(&pk, f_columns).unwrap();
+        assert!(AggregatedVerifier::<Bls12_381, PoseidonTranscript<Bls12_381>>::new().batched_verify(&pvk, proof, &cm1_f).is_ok());
+
+        let (cm1_f, proof) = prove::<Script<Sha256, Bls12_381>>(&pk, columns(1, 5)).unwrap();
+        assert!(AggregatedVerifier::<Bls12_381>::new().batched_verify(&pvk, proof, &cm1_f).is_ok());
+    }
+
+    #[test]
+    fn test_proof_size_does_not_grow_with_columns() {
+        let kzg = Kzg::<Bls12_381>::new(64);
+        let Cq { pk, vk } = Cq::new_with_srs(&range_table(), &kzg).unwrap();
+        let pvk = vk.prepare();
+
+        let (_, two) = prove::<Script<Sha256, Bls12_381>>(&pk, columns(2, 4)).unwrap();
+        let (cm1_f, eight) = prove::<Script<Sha256, Bls12_381>>(&pk, columns(8, 4)).unwrap();
+        assert!(AggregatedVerifier::<Bls12_381>::new().batched_verify(&pvk, eight.clone(), &cm1_f).is_ok());
+        assert_eq!(two.serialized_size(Compress::Yes), eight.serialized_size(Compress::Yes));
+    }
+
+    #[test]
+    fn test_columns_of_different_sizes() {
+        let kzg = Kzg::<Bls12_381>::new(64);
+        let Cq { pk, vk } = Cq::new_with_srs(&range_table(), &kzg).unwrap();
+        let pvk = vk.prepare();
+
+        let f_columns = vec![
+            vec![Fr::from(15)],
+            vec![Fr::from(1), Fr::from(2), Fr::from(3)],
+            (0..16).rev().map(Fr::from).collect(),
+        ];
+        let (cm1_f, proof) = prove::<Script<Sha256, Bls12_381>>(&pk, f_columns.clone()).unwrap();
+        assert_eq!(proof.proof.small_n, 4 * 16);
+        assert!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, proof, &cm1_f).is_ok());
+
+        // 4 interleaved columns of 16 entries do not fit an SRS of 16 powers
+        let Cq { pk, .. } = Cq::<Bls12_381>::new(&range_table()).unwrap();
+        let result = prove::<Script<Sha256, Bls12_381>>(&pk, f_columns);
+        assert!(matches!(result, Err(GeneralError::WitnessLargerThanSrs)));
+
+        let result = prove::<Script<Sha256, Bls12_381>>(&pk, vec![vec![Fr::from(1)], vec![Fr::from(16)]]);
+        assert!(matches!(result, Err(GeneralError::WitnessNotInTable)));
+        assert!(matches!(AggregatedProver::<Bls12_381>::new(vec![]), Err(GeneralError::EmptyWitness)));
+    }
+
+    #[test]
+    fn test_tampered_aggregated_proofs() {
+        let kzg = Kzg::<Bls12_381>::new(64);
+        let Cq { pk, vk } = Cq::new_with_srs(&range_table(), &kzg).unwrap();
+        let pvk = vk.prepare();
+        let f_columns = vec![
+            vec![Fr::from(1), Fr::from(5), Fr::from(5), Fr::from(9)],
+            vec![Fr::from(2), Fr::from(2), Fr::from(7), Fr::from(0)],
+            vec![Fr::from(3), Fr::from(4), Fr::from(4), Fr::from(15)],
+        ];
+        let (cm1_f, proof) = prove::<Script<Sha256, Bls12_381>>(&pk, f_columns.clone()).unwrap();
+        assert!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, proof.clone(), &cm1_f).is_ok());
+
+        // the prover only accepts the commitments of its own columns
+        let prover = AggregatedProver::<Bls12_381>::new(f_columns.clone()).unwrap();
+        let mut swapped = cm1_f.clone();
+        swapped.swap(0, 1);
+        assert!(matches!(prover.prove(&pk, &swapped), Err(GeneralError::WitnessCommitmentMismatch)));
+        assert!(matches!(prover.prove(&pk, &cm1_f[..2]), Err(GeneralError::WitnessCommitmentMismatch)));
+
+        // a column swapped for another one
+        assert!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, proof.clone(), &swapped).is_err());
+        assert!(AggregatedVerifier::<Bls12_381>::new().batched_verify(&pvk, proof.clone(), &swapped).is_err());
+
+        // a valid proof for other columns, given the commitments of the first ones
+        let mut other_columns = f_columns;
+        other_columns[2][3] = Fr::from(14);
+        let (_, other) = prove::<Script<Sha256, Bls12_381>>(&pk, other_columns).unwrap();
+        assert!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, other.clone(), &cm1_f).is_err());
+        assert!(AggregatedVerifier::<Bls12_381>::new().batched_verify(&pvk, other, &cm1_f).is_err());
+
+        let mut bad_value = proof.clone();
+        bad_value.c_omega_zeta += Fr::from(1);
+        assert_eq!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, bad_value.clone(), &cm1_f), Err(VerificationError::ColumnLinkFailed));
+        assert!(AggregatedVerifier::<Bls12_381>::new().batched_verify(&pvk, bad_value, &cm1_f).is_err());
+
+        let mut bad_r = proof.clone();
+        bad_r.cm1_r = G1Affine::generator();
+        assert!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, bad_r, &cm1_f).is_err());
+
+        let mut bad_b_0 = proof.clone();
+        bad_b_0.proof.cm1_b_0 = G1Affine::generator();
+        assert!(AggregatedVerifier::<Bls12_381>::new().batched_verify(&pvk, bad_b_0, &cm1_f).is_err());
+
+        // 9 columns would need a witness of at least 32 entries
+        let mut extra = cm1_f;
+        extra.extend([G1Affine::generator(); 6]);
+        assert_eq!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, proof.clone(), &extra), Err(VerificationError::ColumnCountMismatch));
+        assert_eq!(AggregatedVerifier::<Bls12_381>::new().verify(&pvk, proof, &[]), Err(VerificationError::ColumnCountMismatch));
+    }
+}
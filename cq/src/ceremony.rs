@@ -0,0 +1,304 @@
+// A multi-party powers-of-tau ceremony for the cq SRS.
+// Starting from the public SRS of secret 1, each contributor k multiplies the i-th power by tau_k^i,
+// so the final secret is the product of all tau_k and stays unknown unless every contributor colludes.
+//
+// Each contribution publishes [tau_k]_1, [tau_k]_2, a Schnorr proof of knowledge of tau_k
+// and the new [x]_1, [x]_2, so the whole chain can be checked with a few pairings per contributor:
+// - the proof of knowledge is bound to the contribution index and the previous [x]_1,
+// - e([tau_k]_1, [1]_2) = e([1]_1, [tau_k]_2),
+// - e([x_k]_1, [1]_2) = e([x_{k-1}]_1, [tau_k]_2) and e([x_k]_1, [1]_2) = e([1]_1, [x_k]_2),
+// - the final SRS are consistent powers ending in [x_k]_1, [x_k]_2.
+
+use std::ops::Mul;
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{One, UniformRand, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use rand::Rng;
+use sha2::Sha256;
+use crate::errors::{CeremonyError, SrsError};
+use crate::fiat_shamir::{hash_to_field, ExpandMsgXmd};
+use crate::kzg::Kzg;
+
+const PROOF_OF_KNOWLEDGE_DST: &[u8] = b"CQ-V01-CEREMONY-POK";
+
+// Schnorr proof of knowledge of tau for [tau]_1: z·[1]_1 = r + c·[tau]_1
+#[derive(Debug, Clone, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct ProofOfKnowledge<P: Pairing> {
+    pub r: P::G1Affine,
+    pub z: P::ScalarField,
+}
+
+// What a contributor publishes besides the updated SRS.
+#[derive(Debug, Clone, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct Contribution<P: Pairing> {
+    // [tau_k]_1
+    pub tau_g1: P::G1Affine,
+    // [tau_k]_2
+    pub tau_g2: P::G2Affine,
+    pub pok: ProofOfKnowledge<P>,
+    // [x_k]_1 and [x_k]_2 of the SRS after this contribution
+    pub x_g1: P::G1Affine,
+    pub x_g2: P::G2Affine,
+}
+
+// The state of a ceremony: the contribution chain and the current SRS.
+#[derive(Debug, Clone, CanonicalSerialize, CanonicalDeserialize)]
+pub struct CeremonyTranscript<P: Pairing> {
+    pub contributions: Vec<Contribution<P>>,
+    pub srs: Kzg<P>,
+}
+
+impl <P: Pairing> CeremonyTranscript<P> {
+    // Start a ceremony for an SRS with len powers in G1 (len + 1 in G2), as used by Cq::new_with_srs for N = len.
+    pub fn new(len: usize) -> Self {
+        Self {
+            contributions: Vec::new(),
+            srs: Kzg::new_from_secret(P::ScalarField::one(), len),
+        }
+    }
+}
+
+// Rerandomize the SRS with a fresh secret and append the contribution to the transcript.
+pub fn contribute<P: Pairing, R: Rng>(transcript: &mut CeremonyTranscript<P>, rng: &mut R) -> Result<(), CeremonyError> {
+    let mut tau = P::ScalarField::rand(rng);
+    while tau.is_zero() {
+        tau = P::ScalarField::rand(rng);
+    }
+    contribute_with_secret(transcript, tau, rng)
+}
+
+// Rerandomize the SRS with the given non-zero secret, rng is only used for the proof of knowledge.
+pub fn contribute_with_secret<P: Pairing, R: Rng>(transcript: &mut CeremonyTranscript<P>, tau: P::ScalarField, rng: &mut R) -> Result<(), CeremonyError> {
+    if tau.is_zero() {
+        return Err(CeremonyError::ZeroSecret);
+    }
+    let srs = &transcript.srs;
+    // [x]_1 and [x]_2 are published with the contribution, as checked by Kzg::check_powers
+    if srs.g1_srs.len() < 2 || srs.g2_srs.len() < 2 {
+        return Err(SrsError::TooShort { group: "G1 and G2", needed: 2, available: srs.g1_srs.len().min(srs.g2_srs.len()) }.into());
+    }
+    let g1_gen = srs.g1_srs[0];
+    let index = transcript.contributions.len() as u64;
+    let prev_x_g1 = srs.g1_srs[1];
+
+    // G1 has more powers than G2 for an SRS truncated to fewer G2 powers
+    let len = srs.g1_srs.len().max(srs.g2_srs.len());
+    let mut tau_pow = P::ScalarField::one();
+    let mut tau_pows = Vec::with_capacity(len);
+    for _ in 0..len {
+        tau_pows.push(tau_pow);
+        tau_pow *= tau;
+    }
+    let g1_srs: Vec<P::G1> = srs.g1_srs.iter().zip(&tau_pows).map(|(p, t)| p.mul(t)).collect();
+    let g2_srs: Vec<P::G2> = srs.g2_srs.iter().zip(&tau_pows).map(|(p, t)| p.mul(t)).collect();
+    let new_srs = Kzg {
+        g1_srs: P::G1::normalize_batch(&g1_srs),
+        g2_srs: P::G2::normalize_batch(&g2_srs),
+    };
+
+    let tau_g1 = g1_gen.mul(tau).into_affine();
+    let tau_g2 = srs.g2_srs[0].mul(tau).into_affine();
+    let k = P::ScalarField::rand(rng);
+    let r = g1_gen.mul(k).into_affine();
+    let c = pok_challenge::<P>(index, &prev_x_g1, &tau_g1, &r);
+    let pok = ProofOfKnowledge { r, z: k + c * tau };
+
+    transcript.contributions.push(Contribution {
+        tau_g1,
+        tau_g2,
+        pok,
+        x_g1: new_srs.g1_srs[1],
+        x_g2: new_srs.g2_srs[1],
+    });
+    transcript.srs = new_srs;
+    Ok(())
+}
+
+// Check the whole contribution chain from the public starting SRS to the current SRS.
+pub fn verify<P: Pairing, R: Rng>(transcript: &CeremonyTranscript<P>, rng: &mut R) -> Result<(), CeremonyError> {
+    if transcript.contributions.is_empty() {
+        return Err(CeremonyError::NoContributions);
+    }
+    let srs = &transcript.srs;
+    srs.check_powers(rng)?;
+    let g1_gen = P::G1Affine::generator();
+    let g2_gen = P::G2Affine::generator();
+    if srs.g1_srs[0] != g1_gen || srs.g2_srs[0] != g2_gen {
+        return Err(CeremonyError::GeneratorsChanged);
+    }
+
+    let mut prev_x_g1 = g1_gen;
+    for (index, contribution) in transcript.contributions.iter().enumerate() {
+        verify_contribution::<P>(index, &prev_x_g1, contribution)?;
+        prev_x_g1 = contribution.x_g1;
+    }
+
+    let last = transcript.contributions.last().unwrap();
+    if srs.g1_srs[1] != last.x_g1 || srs.g2_srs[1] != last.x_g2 {
+        return Err(CeremonyError::InvalidContribution { index: transcript.contributions.len() - 1 });
+    }
+    Ok(())
+}
+
+fn verify_contribution<P: Pairing>(index: usize, prev_x_g1: &P::G1Affine, contribution: &Contribution<P>) -> Result<(), CeremonyError> {
+    let g1_gen = P::G1Affine::generator();
+    let g2_gen = P::G2Affine::generator();
+    let Contribution { tau_g1, tau_g2, pok, x_g1, x_g2 } = contribution;
+    if tau_g1.is_zero() || x_g1.is_zero() {
+        return Err(CeremonyError::InvalidContribution { index });
+    }
+
+    // z·[1]_1 = r + c·[tau]_1
+    let c = pok_challenge::<P>(index as u64, prev_x_g1, tau_g1, &pok.r);
+    if g1_gen.mul(pok.z) != pok.r.into_group() + tau_g1.mul(c) {
+        return Err(CeremonyError::InvalidProofOfKnowledge { index });
+    }
+
+    let neg_g1_gen = (-g1_gen.into_group()).into_affine();
+    let neg_x_g1 = (-x_g1.into_group()).into_affine();
+    // e([tau]_1, [1]_2) = e([1]_1, [tau]_2)
+    let tau_check = P::multi_pairing([*tau_g1, neg_g1_gen], [g2_gen, *tau_g2]);
+    // e([x_k]_1, [1]_2) = e([x_{k-1}]_1, [tau]_2)
+    let update_check = P::multi_pairing([*x_g1, (-prev_x_g1.into_group()).into_affine()], [g2_gen, *tau_g2]);
+    // e([x_k]_1, [1]_2) = e([1]_1, [x_k]_2)
+    let x_check = P::multi_pairing([g1_gen, neg_x_g1], [*x_g2, g2_gen]);
+    if !tau_check.is_zero() || !update_check.is_zero() || !x_check.is_zero() {
+        return Err(CeremonyError::InvalidContribution { index });
+    }
+    Ok(())
+}
+
+fn pok_challenge<P: Pairing>(index: u64, prev_x_g1: &P::G1Affine, tau_g1: &P::G1Affine, r: &P::G1Affine) -> P::ScalarField {
+    let mut msg = index.to_be_bytes().to_vec();
+    prev_x_g1.serialize_compressed(&mut msg).unwrap();
+    tau_g1.serialize_compressed(&mut msg).unwrap();
+    r.serialize_compressed(&mut msg).unwrap();
+    hash_to_field::<P::ScalarField, ExpandMsgXmd<Sha256>>(&msg, PROOF_OF_KNOWLEDGE_DST, 1)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr};
+    use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+    use rand::thread_rng;
+    use crate::cq::Cq;
+    use crate::errors::{CeremonyError, SrsError};
+    use crate::kzg::Kzg;
+    use crate::prover::Prover;
+    use crate::verifier::Verifier;
+    use super::*;
+
+    #[test]
+    fn test_ceremony() {
+        let rng = &mut thread_rng();
+        let mut transcript = CeremonyTranscript::<Bls12_381>::new(8);
+        assert!(matches!(verify(&transcript, rng), Err(CeremonyError::NoContributions)));
+
+        let secrets = [Fr::from(3), Fr::from(5), Fr::from(7)];
+        for tau in secrets {
+            contribute_with_secret(&mut transcript, tau, rng).unwrap();
+            assert!(verify(&transcript, rng).is_ok());
+        }
+        assert_eq!(transcript.contributions.len(), 3);
+
+        let expected = Kzg::<Bls12_381>::new_from_secret(Fr::from(105), 8);
+        assert_eq!(transcript.srs.g1_srs, expected.g1_srs);
+        assert_eq!(transcript.srs.g2_srs, expected.g2_srs);
+
+        let result = contribute_with_secret(&mut transcript, Fr::from(0), rng);
+        assert!(matches!(result, Err(CeremonyError::ZeroSecret)));
+        assert_eq!(transcript.contributions.len(), 3);
+    }
+
+    #[test]
+    fn test_ceremony_with_more_powers_in_g1() {
+        let rng = &mut thread_rng();
+        let mut transcript = CeremonyTranscript::<Bls12_381> {
+            contributions: vec![],
+            srs: Kzg::new_from_secret_with_lengths(Fr::from(1), 16, 4),
+        };
+        contribute_with_secret(&mut transcript, Fr::from(3), rng).unwrap();
+        contribute_with_secret(&mut transcript, Fr::from(5), rng).unwrap();
+        assert!(verify(&transcript, rng).is_ok());
+
+        let expected = Kzg::<Bls12_381>::new_from_secret_with_lengths(Fr::from(15), 16, 4);
+        assert_eq!(transcript.srs.g1_srs, expected.g1_srs);
+        assert_eq!(transcript.srs.g2_srs, expected.g2_srs);
+    }
+
+    #[test]
+    fn test_ceremony_transcript_serialization() {
+        let rng = &mut thread_rng();
+        let mut transcript = CeremonyTranscript::<Bls12_381>::new(8);
+        for _ in 0..4 {
+            contribute(&mut transcript, rng).unwrap();
+        }
+
+        let mut bytes = Vec::new();
+        transcript.serialize_compressed(&mut bytes).unwrap();
+        let transcript_2 = CeremonyTranscript::<Bls12_381>::deserialize_compressed(bytes.as_slice()).unwrap();
+        assert_eq!(transcript_2.contributions, transcript.contributions);
+        assert!(verify(&transcript_2, rng).is_ok());
+
+        // the final SRS can be used to preprocess a table
+        let t_i: Vec<Fr> = (0..8).map(Fr::from).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new_with_srs(&t_i, &transcript_2.srs).unwrap();
+        let proof = Prover::<Bls12_381>::new(vec![Fr::from(2), Fr::from(5)]).unwrap().prove(&pk).unwrap();
+        assert!(Verifier::<Bls12_381>::new().verify(&vk.prepare(), proof).is_ok());
+    }
+
+    #[test]
+    fn test_ceremony_rejects_bad_contributions() {
+        let rng = &mut thread_rng();
+        let mut transcript = CeremonyTranscript::<Bls12_381>::new(8);
+        for _ in 0..3 {
+            contribute(&mut transcript, rng).unwrap();
+        }
+
+        // a proof of knowledge replayed at another index
+        let mut replayed = transcript.clone();
+        replayed.contributions[2].pok = transcript.contributions[1].pok.clone();
+        assert!(matches!(verify(&replayed, rng), Err(CeremonyError::InvalidProofOfKnowledge { index: 2 })));
+
+        // a contribution from another chain: its proof of knowledge is bound to another [x]_1
+        let mut forked = transcript.clone();
+        let mut other = CeremonyTranscript::<Bls12_381>::new(8);
+        contribute(&mut other, rng).unwrap();
+        contribute(&mut other, rng).unwrap();
+        forked.contributions[1] = other.contributions[1].clone();
+        assert!(matches!(verify(&forked, rng), Err(CeremonyError::InvalidProofOfKnowledge { index: 1 })));
+
+        // an updated [x]_1 that is not the previous one times tau
+        let mut skipped = transcript.clone();
+        skipped.contributions[1].x_g1 = transcript.contributions[0].x_g1;
+        skipped.contributions[1].x_g2 = transcript.contributions[0].x_g2;
+        assert!(matches!(verify(&skipped, rng), Err(CeremonyError::InvalidContribution { index: 1 })));
+
+        // an SRS that is not the result of the last contribution
+        let mut replaced = transcript.clone();
+        replaced.srs = Kzg::new(8);
+        assert!(matches!(verify(&replaced, rng), Err(CeremonyError::InvalidContribution { index: 2 })));
+
+        // an SRS with an inconsistent power
+        let mut broken = transcript.clone();
+        broken.srs.g1_srs[4] = broken.srs.g1_srs[5];
+        assert!(matches!(verify(&broken, rng), Err(CeremonyError::Srs(SrsError::InconsistentPowers))));
+    }
+
+    #[test]
+    fn test_contribute_to_short_srs() {
+        let rng = &mut thread_rng();
+        for len in [0, 1] {
+            let mut transcript = CeremonyTranscript::<Bls12_381>::new(len);
+            let result = contribute(&mut transcript, rng);
+            assert!(matches!(result, Err(CeremonyError::Srs(SrsError::TooShort { needed: 2, available, .. })) if available == len));
+            assert!(transcript.contributions.is_empty());
+        }
+
+        let mut truncated = CeremonyTranscript::<Bls12_381>::new(8);
+        truncated.srs.g2_srs.truncate(1);
+        let result = contribute_with_secret(&mut truncated, Fr::from(3), rng);
+        assert!(matches!(result, Err(CeremonyError::Srs(SrsError::TooShort { available: 1, .. }))));
+    }
+}
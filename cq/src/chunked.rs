@@ -0,0 +1,437 @@
+// Memory-bounded preprocessing, for tables whose cached quotients do not fit in RAM.
+//
+// Cq::new holds the SRS as projective points and runs 2N-point group FFTs in memory. Here each
+// group FFT of size n = n1·n2 runs out of core as the four-step FFT: the points are a matrix in a
+// scratch file, transposed tile by tile, with n2-point and n1-point FFTs over its rows.
+// cm1_qi, cm1_li, cm1_l_i_quotient and cm1_l_i_quotient_shifted are streamed to the writer in
+// the format of Cq::save, so the result is read back with Cq::load under the same SRS.
+//
+// The memory budget bounds everything held at once. The SRS and 4N scalars, the table, its
+// coefficients and their 2N-point FFT, stay in memory and are charged to it first, the rest bounds
+// the group elements of the FFTs. The scratch files take 4N points on disk.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::Mul;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ec::pairing::Pairing;
+use ark_ff::{Field, Zero};
+use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain};
+use ark_poly::univariate::DensePolynomial;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, SerializationError, Validate};
+use ark_std::cfg_iter_mut;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use crate::cq::{pad_table, Cq};
+use crate::errors::{GeneralError, PersistError};
+use crate::kzg::Kzg;
+use crate::persist::{finish, write_header, HashingWriter};
+use crate::verifier::powers;
+
+// Where and with how much memory the preprocessing runs.
+#[derive(Debug, Clone)]
+pub struct ChunkedOptions {
+    // bytes held in memory at once, the SRS included
+    pub memory_budget: usize,
+    // directory of the scratch files, removed once done
+    pub scratch_dir: PathBuf,
+}
+
+impl ChunkedOptions {
+    pub fn new(memory_budget: usize, scratch_dir: impl Into<PathBuf>) -> Self {
+        Self { memory_budget, scratch_dir: scratch_dir.into() }
+    }
+
+    // The smallest budget a table of padded size N can be preprocessed with under srs: what stays
+    // in memory and the longest row of the 2N-point FFTs.
+    pub fn min_memory_budget<P: Pairing>(srs: &Kzg<P>, big_n: usize) -> usize {
+        fixed_cost(srs, big_n) + split(2 * big_n).0 * point_cost::<P>()
+    }
+}
+
+impl <P: Pairing> Cq<'_, P> {
+    // Preprocess the table under srs as Cq::new_with_srs does and write it as Cq::save does,
+    // without holding more than options.memory_budget bytes of group elements.
+    pub fn save_chunked<W: Write>(t_i: &[P::ScalarField], srs: &Kzg<P>, options: &ChunkedOptions, writer: W) -> Result<(), PersistError> {
+        let t_i = pad_table(t_i)?;
+        let big_n = t_i.len();
+        Self::check_srs(srs, big_n).map_err(GeneralError::from)?;
+        // FK pads 2-entry tables differently, and they fit in memory anyway
+        if big_n < 4 {
+            return Cq::<P>::new_with_srs(&t_i, srs)?.save(writer);
+        }
+        let needed = ChunkedOptions::min_memory_budget(srs, big_n);
+        if options.memory_budget < needed {
+            return Err(PersistError::MemoryBudgetTooSmall { needed, available: options.memory_budget });
+        }
+        let ooc = OutOfCore::<P>::new((options.memory_budget - fixed_cost(srs, big_n)) / point_cost::<P>());
+
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(big_n).unwrap();
+        let big_n_inv = domain.size_as_field_element().inverse().unwrap();
+        let t_x_coeffs = domain.ifft(&t_i);
+        let t_x_2 = srs.commit_g2(&DensePolynomial::from_coefficients_slice(&t_x_coeffs));
+
+        let mut writer = HashingWriter::new(writer);
+        write_header(&mut writer, srs)?;
+        big_n.serialize_with_mode(&mut writer, Compress::No)?;
+        t_i.serialize_with_mode(&mut writer, Compress::No)?;
+        t_x_2.serialize_with_mode(&mut writer, Compress::No)?;
+
+        let mut a = ScratchFile::<P>::create(&options.scratch_dir, 2 * big_n)?;
+        let mut b = ScratchFile::<P>::create(&options.scratch_dir, 2 * big_n)?;
+
+        // cm1_qi as in Cq::compute_cm1_qi, with the FK Toeplitz product:
+        // ^h = iFFT(FFT(^s) ◦ FFT(^c)), ^s the N first powers reversed and padded with N zeros
+        for (start, len) in ooc.chunks(big_n) {
+            let points: Vec<P::G1Affine> = srs.g1_srs[big_n - start - len..big_n - start].iter().rev().copied().collect();
+            a.write_affine(start, &points)?;
+        }
+        for (start, len) in ooc.chunks(big_n) {
+            a.write_affine(big_n + start, &vec![P::G1Affine::zero(); len])?;
+        }
+        ooc.fft(&mut a, &mut b, 2 * big_n, false)?;
+
+        // FFT(^c) in place, ^c the N - 1 last coefficients of T after N + 1 zeros
+        let mut v = vec![P::ScalarField::zero(); big_n + 1];
+        v.extend_from_slice(&t_x_coeffs[1..]);
+        drop(t_x_coeffs);
+        GeneralEvaluationDomain::<P::ScalarField>::new(2 * big_n).unwrap().fft_in_place(&mut v);
+        for (start, len) in ooc.chunks(2 * big_n) {
+            let mut points = b.read(start, len)?;
+            cfg_iter_mut!(points).zip(&v[start..start + len]).for_each(|(p, v_i)| *p *= v_i);
+            b.write(start, &points)?;
+        }
+        ooc.fft(&mut b, &mut a, 2 * big_n, true)?;
+        // the N first coefficients of ^h
+        ooc.fft(&mut a, &mut b, big_n, false)?;
+
+        write_len(&mut writer, big_n)?;
+        for (start, len) in ooc.chunks(big_n) {
+            let mut points = b.read(start, len)?;
+            let scalars = powers(domain.element(start) * big_n_inv, domain.group_gen(), len);
+            cfg_iter_mut!(points).zip(scalars).for_each(|(p, s)| *p *= s);
+            write_points::<P, _>(&mut writer, &points)?;
+        }
+
+        // cm1_li and cm1_l_i_quotient from the FFT of the N first powers
+        for (start, len) in ooc.chunks(big_n) {
+            a.write_affine(start, &srs.g1_srs[start..start + len])?;
+        }
+        ooc.fft(&mut a, &mut b, big_n, false)?;
+
+        write_len(&mut writer, big_n)?;
+        for (start, len) in ooc.chunks(big_n) {
+            let mut points = read_lagrange(&mut b, start, len, big_n)?;
+            cfg_iter_mut!(points).for_each(|p| *p *= big_n_inv);
+            write_points::<P, _>(&mut writer, &points)?;
+        }
+
+        let sub = srs.g1_srs[big_n - 1].mul(-big_n_inv);
+        write_len(&mut writer, big_n)?;
+        for (start, len) in ooc.chunks(big_n) {
+            let mut points = read_lagrange(&mut b, start, len, big_n)?;
+            let scalars = powers(domain.element(big_n - start) * big_n_inv, domain.group_gen_inv(), len);
+            cfg_iter_mut!(points).zip(scalars).for_each(|(p, s)| *p = *p * s + sub);
+            write_points::<P, _>(&mut writer, &points)?;
+        }
+
+        // cm1_l_i_quotient_shifted as in Cq::compute_cm1_l_i_quotient_shifted
+        let shift = srs.max_degree() + 1 - big_n;
+        if shift > 0 {
+            for (start, len) in ooc.chunks(big_n) {
+                a.write_affine(start, &srs.g1_srs[shift + start..shift + start + len])?;
+            }
+            ooc.fft(&mut a, &mut b, big_n, false)?;
+        }
+        let sub = srs.g1_srs[shift].mul(big_n_inv);
+        write_len(&mut writer, big_n)?;
+        for (start, len) in ooc.chunks(big_n) {
+            let mut points = read_lagrange(&mut b, start, len, big_n)?;
+            cfg_iter_mut!(points).for_each(|p| *p = *p * big_n_inv - sub);
+            write_points::<P, _>(&mut writer, &points)?;
+        }
+
+        finish(writer)
+    }
+}
+
+// The SRS, the table, its coefficients and the FFT of ^c.
+fn fixed_cost<P: Pairing>(srs: &Kzg<P>, big_n: usize) -> usize {
+    srs.g1_srs.len() * size_of::<P::G1Affine>() + srs.g2_srs.len() * size_of::<P::G2Affine>()
+        + 4 * big_n * size_of::<P::ScalarField>()
+}
+
+// A point in memory: projective, its affine form and its uncompressed bytes.
+fn point_cost<P: Pairing>() -> usize {
+    size_of::<P::G1>() + size_of::<P::G1Affine>() + P::G1Affine::zero().uncompressed_size()
+}
+
+// n = n1·n2, with n1 = 2^ceil(log n / 2) >= n2
+fn split(n: usize) -> (usize, usize) {
+    let n1 = 1 << n.trailing_zeros().div_ceil(2);
+    (n1, n / n1)
+}
+
+// The FFT of the SRS has [L_i(x)]_1 scaled by N at index -i mod N.
+fn read_lagrange<P: Pairing>(evals: &mut ScratchFile<P>, start: usize, len: usize, big_n: usize) -> Result<Vec<P::G1>, PersistError> {
+    let mut res = Vec::with_capacity(len);
+    let mut i = start;
+    if i == 0 {
+        res.extend(evals.read(0, 1)?);
+        i = 1;
+    }
+    let end = start + len;
+    if i < end {
+        let mut points = evals.read(big_n + 1 - end, end - i)?;
+        points.reverse();
+        res.extend(points);
+    }
+    Ok(res)
+}
+
+// The length prefix of a serialized Vec.
+fn write_len<W: Write>(writer: &mut W, len: usize) -> Result<(), SerializationError> {
+    (len as u64).serialize_uncompressed(writer)
+}
+
+fn write_points<P: Pairing, W: Write>(writer: &mut W, points: &[P::G1]) -> Result<(), PersistError> {
+    writer.write_all(&serialize_affine::<P>(&P::G1::normalize_batch(points))?)?;
+    Ok(())
+}
+
+fn serialize_affine<P: Pairing>(points: &[P::G1Affine]) -> Result<Vec<u8>, SerializationError> {
+    let mut bytes = Vec::with_capacity(points.len() * P::G1Affine::zero().uncompressed_size());
+    for point in points {
+        point.serialize_uncompressed(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+// Group FFTs over scratch files, holding at most capacity points at once.
+struct OutOfCore<P: Pairing> {
+    capacity: usize,
+    _pairing: PhantomData<P>,
+}
+
+impl <P: Pairing> OutOfCore<P> {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, _pairing: PhantomData }
+    }
+
+    // (start, len) of the chunks of 0..n that fit in memory
+    fn chunks(&self, n: usize) -> impl Iterator<Item = (usize, usize)> {
+        let capacity = self.capacity;
+        (0..n).step_by(capacity).map(move |start| (start, capacity.min(n - start)))
+    }
+
+    // FFT (or inverse FFT) of the n first points of x into the n first points of y, clobbering x.
+    // With x_{j1 + n1·j2} as the n2 x n1 matrix x[j2][j1]:
+    // y_{k2 + n2·k1} = Σ_j1 ω_n1^{j1·k1}·ω^{j1·k2}·Σ_j2 ω_n2^{j2·k2}·x[j2][j1]
+    fn fft(&self, x: &mut ScratchFile<P>, y: &mut ScratchFile<P>, n: usize, inverse: bool) -> Result<(), PersistError> {
+        let (n1, n2) = split(n);
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(n).unwrap();
+        let omega = if inverse { domain.group_gen_inv() } else { domain.group_gen() };
+
+        self.transpose(x, y, n2, n1)?;
+        self.fft_rows(y, n1, n2, Some(omega), inverse)?;
+        self.transpose(y, x, n1, n2)?;
+        self.fft_rows(x, n2, n1, None, inverse)?;
+        self.transpose(x, y, n2, n1)
+    }
+
+    // FFT of each row of length row_len, then entry k of row r multiplied by twiddle^{r·k}.
+    fn fft_rows(&self, file: &mut ScratchFile<P>, rows: usize, row_len: usize, twiddle: Option<P::ScalarField>, inverse: bool) -> Result<(), PersistError> {
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(row_len).unwrap();
+        let max_batch = self.capacity / row_len;
+        for first_row in (0..rows).step_by(max_batch) {
+            let batch = max_batch.min(rows - first_row);
+            let bytes = file.read_bytes(first_row * row_len, batch * row_len)?;
+            let mut batch_rows = bytes.chunks(row_len * file.point_size)
+                .map(|row| file.deserialize(row))
+                .collect::<Result<Vec<_>, _>>()?;
+            drop(bytes);
+            cfg_iter_mut!(batch_rows).enumerate().for_each(|(r, row)| {
+                if inverse {
+                    domain.ifft_in_place(row);
+                } else {
+                    domain.fft_in_place(row);
+                }
+                if let Some(twiddle) = twiddle {
+                    let step = twiddle.pow([(first_row + r) as u64]);
+                    row.iter_mut().zip(powers(P::ScalarField::ONE, step, row_len)).for_each(|(p, s)| *p *= s);
+                }
+            });
+            let mut bytes = Vec::with_capacity(batch * row_len * file.point_size);
+            for row in batch_rows {
+                bytes.extend(serialize_affine::<P>(&P::G1::normalize_batch(&row))?);
+            }
+            file.write_bytes(first_row * row_len, &bytes)?;
+        }
+        Ok(())
+    }
+
+    // Writes the transpose of the rows x cols matrix in src to dst, moving the bytes of square tiles.
+    fn transpose(&self, src: &mut ScratchFile<P>, dst: &mut ScratchFile<P>, rows: usize, cols: usize) -> Result<(), PersistError> {
+        let mut tile = 1;
+        while 4 * tile * tile <= self.capacity && 2 * tile <= rows.min(cols) {
+            tile *= 2;
+        }
+        let size = src.point_size;
+        let mut block = vec![0u8; tile * tile * size];
+        for r0 in (0..rows).step_by(tile) {
+            for c0 in (0..cols).step_by(tile) {
+                for i in 0..tile {
+                    let segment = src.read_bytes((r0 + i) * cols + c0, tile)?;
+                    for (j, point) in segment.chunks(size).enumerate() {
+                        block[(j * tile + i) * size..][..size].copy_from_slice(point);
+                    }
+                }
+                for (j, segment) in block.chunks(tile * size).enumerate() {
+                    dst.write_bytes((c0 + j) * rows + r0, segment)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+static SCRATCH_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// Points on disk as uncompressed affine points, removed when dropped.
+struct ScratchFile<P: Pairing> {
+    path: PathBuf,
+    file: File,
+    point_size: usize,
+    _pairing: PhantomData<P>,
+}
+
+impl <P: Pairing> ScratchFile<P> {
+    fn create(dir: &Path, len: usize) -> io::Result<Self> {
+        let id = SCRATCH_FILES.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("cq-scratch-{}-{id}", std::process::id()));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        let scratch = Self { path, file, point_size: P::G1Affine::zero().uncompressed_size(), _pairing: PhantomData };
+        scratch.file.set_len((len * scratch.point_size) as u64)?;
+        Ok(scratch)
+    }
+
+    fn read_bytes(&mut self, start: usize, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; len * self.point_size];
+        self.file.seek(SeekFrom::Start((start * self.point_size) as u64))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_bytes(&mut self, start: usize, bytes: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start((start * self.point_size) as u64))?;
+        self.file.write_all(bytes)
+    }
+
+    fn read(&mut self, start: usize, len: usize) -> Result<Vec<P::G1>, PersistError> {
+        let bytes = self.read_bytes(start, len)?;
+        Ok(self.deserialize(&bytes)?)
+    }
+
+    // The points were written by this process, they are not validated again.
+    fn deserialize(&self, bytes: &[u8]) -> Result<Vec<P::G1>, SerializationError> {
+        bytes.chunks(self.point_size)
+            .map(|point| P::G1Affine::deserialize_with_mode(point, Compress::No, Validate::No).map(|p| p.into_group()))
+            .collect()
+    }
+
+    fn write(&mut self, start: usize, points: &[P::G1]) -> Result<(), PersistError> {
+        self.write_affine(start, &P::G1::normalize_batch(points))
+    }
+
+    fn write_affine(&mut self, start: usize, points: &[P::G1Affine]) -> Result<(), PersistError> {
+        let bytes = serialize_affine::<P>(points)?;
+        self.write_bytes(start, &bytes)?;
+        Ok(())
+    }
+}
+
+impl <P: Pairing> Drop for ScratchFile<P> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr, G1Affine};
+    use ark_serialize::Validate;
+    use crate::cq::Cq;
+    use crate::errors::PersistError;
+    use crate::kzg::Kzg;
+    use crate::prover::Prover;
+    use crate::verifier::Verifier;
+    use super::ChunkedOptions;
+
+    fn options(memory_budget: usize) -> ChunkedOptions {
+        ChunkedOptions::new(memory_budget, std::env::temp_dir())
+    }
+
+    fn assert_matches_in_memory(t_i: &[Fr], srs: &Kzg<Bls12_381>, memory_budget: usize) {
+        let mut expected = Vec::new();
+        Cq::<Bls12_381>::new_with_srs(t_i, srs).unwrap().save(&mut expected).unwrap();
+        let mut bytes = Vec::new();
+        Cq::<Bls12_381>::save_chunked(t_i, srs, &options(memory_budget), &mut bytes).unwrap();
+        assert!(bytes == expected);
+    }
+
+    #[test]
+    fn test_save_chunked_matches_save() {
+        // tables with repeated values, padding and sizes with n1 = n2 and n1 = 2·n2
+        for size in [2, 4, 13, 32] {
+            let t_i: Vec<Fr> = (0..size).map(|i| Fr::from(i * 7 % 11)).collect();
+            let big_n = t_i.len().next_power_of_two();
+            // a universal SRS, with the shifted quotients computed from its last powers
+            for srs in [Kzg::new(big_n), Kzg::new(4 * big_n)] {
+                let min_budget = ChunkedOptions::min_memory_budget(&srs, big_n);
+                for memory_budget in [min_budget, 5 * min_budget, usize::MAX] {
+                    assert_matches_in_memory(&t_i, &srs, memory_budget);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_chunked() {
+        let t_i: Vec<Fr> = (0..32).map(Fr::from).collect();
+        let srs = Kzg::<Bls12_381>::new(32);
+        let mut bytes = Vec::new();
+        let min_budget = ChunkedOptions::min_memory_budget(&srs, 32);
+        Cq::<Bls12_381>::save_chunked(&t_i, &srs, &options(min_budget), &mut bytes).unwrap();
+
+        let Cq { pk, vk } = Cq::<Bls12_381>::load_for_table(bytes.as_slice(), &t_i, &srs, Validate::Yes).unwrap();
+        assert_eq!(vk, Cq::<Bls12_381>::new_with_srs(&t_i, &srs).unwrap().vk);
+
+        let proof = Prover::<Bls12_381>::new(vec![Fr::from(3), Fr::from(31)]).unwrap().prove(&pk).unwrap();
+        assert!(Verifier::<Bls12_381>::new().verify(&vk.prepare(), proof).is_ok());
+    }
+
+    #[test]
+    fn test_save_chunked_errors() {
+        let t_i: Vec<Fr> = (0..16).map(Fr::from).collect();
+        let srs = Kzg::<Bls12_381>::new(16);
+        let min_budget = ChunkedOptions::min_memory_budget(&srs, 16);
+        let result = Cq::<Bls12_381>::save_chunked(&t_i, &srs, &options(min_budget - 1), Vec::new());
+        assert!(matches!(result, Err(PersistError::MemoryBudgetTooSmall { needed, available }) if needed == min_budget && available == min_budget - 1));
+
+        // the SRS is charged to the budget, a larger one needs a larger budget
+        let universal_srs = Kzg::<Bls12_381>::new(64);
+        assert!(ChunkedOptions::min_memory_budget(&universal_srs, 16) > min_budget + 48 * std::mem::size_of::<G1Affine>());
+        let result = Cq::<Bls12_381>::save_chunked(&t_i, &universal_srs, &options(min_budget), Vec::new());
+        assert!(matches!(result, Err(PersistError::MemoryBudgetTooSmall { .. })));
+
+        let result = Cq::<Bls12_381>::save_chunked(&t_i, &Kzg::new(8), &options(usize::MAX), Vec::new());
+        assert!(matches!(result, Err(PersistError::Setup(_))));
+
+        let result = Cq::<Bls12_381>::save_chunked(&t_i, &Kzg::new(16), &ChunkedOptions::new(usize::MAX, "/nonexistent/cq"), Vec::new());
+        assert!(matches!(result, Err(PersistError::Io(_))));
+    }
+}
@@ -1,107 +1,589 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::ops::{Mul, Sub};
+use ark_ec::CurveGroup;
 use ark_ec::pairing::Pairing;
 use ark_ff::Field;
 use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain};
 use ark_poly::univariate::DensePolynomial;
-use crate::errors::GeneralError;
-use crate::feist_khovratovich_alg::ToeplitzMatrix;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Read, SerializationError, Valid, Validate, Write};
+use ark_std::cfg_iter;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use crate::errors::{GeneralError, SrsError};
+use crate::feist_khovratovich_alg::compute_openings;
 use crate::kzg::Kzg;
 use crate::pre_compute::{compute_quotient_lagrange_basic_commitments, fast_lagrange_basis_commitments_computation};
 
-pub struct Cq<P: Pairing> {
-    pub kzg: Kzg<P>,
+// Preprocessing of a lookup table.
+// The proving key (SRS, table and cached quotients) is used by the prover,
+// the small verifying key is all the verifier needs.
+// The SRS can be borrowed, so that tables of different sizes share one universal SRS.
+pub struct Cq<'a, P: Pairing> {
+    pub pk: ProvingKey<'a, P>,
+    pub vk: VerifyingKey<P>,
+}
+
+// Everything the prover needs to produce proofs against one table.
+// D denotes the degree of the SRS in G1, D = N - 1 unless a larger universal SRS is used.
+pub struct ProvingKey<'a, P: Pairing> {
+    pub kzg: Cow<'a, Kzg<P>>,
     pub big_n: usize,
-    pub z_v_2: P::G2Affine,
+    pub t_i: Vec<P::ScalarField>,
     pub t_hash_map: HashMap<P::ScalarField, usize>,
     pub t_x_2: P::G2Affine,
     pub cm1_qi: Vec<P::G1Affine>,
     pub cm1_li: Vec<P::G1Affine>,
     pub cm1_l_i_quotient: Vec<P::G1Affine>,
+    // [x^{D-(N-2)}·(L_i(x) - L_i(0))/x]_1, for the degree check of A0
+    pub cm1_l_i_quotient_shifted: Vec<P::G1Affine>,
+}
+
+impl <P: Pairing> ProvingKey<'_, P> {
+    pub fn verifying_key(&self) -> VerifyingKey<P> {
+        VerifyingKey::new(&self.kzg, self.big_n, self.t_x_2)
+    }
+}
+
+// Everything the verifier needs: a handful of group elements, independent of the table size.
+#[derive(Debug, Clone, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct VerifyingKey<P: Pairing> {
+    pub big_n: usize,
+    // [1]_1
+    pub cm1_1: P::G1Affine,
+    // [1]_2
+    pub cm2_1: P::G2Affine,
+    // [x]_2
+    pub cm2_x: P::G2Affine,
+    // [Zv(x)]_2
+    pub z_v_2: P::G2Affine,
+    // [T(x)]_2
+    pub t_x_2: P::G2Affine,
+    // [x^{D-(n-2)}]_2 for the degree check of B0, one entry for each n = 2^k <= D + 1.
+    // The entry for n = N is also used for the degree check of A0.
+    pub cm2_x_pows: Vec<P::G2Affine>,
+}
+
+impl <P: Pairing> VerifyingKey<P> {
+    pub fn new(kzg: &Kzg<P>, big_n: usize, t_x_2: P::G2Affine) -> Self {
+        let z_v_2: P::G2Affine = kzg.g2_srs[big_n].sub(kzg.g2_srs[0]).into();
+
+        let max_degree = kzg.max_degree();
+        let cm2_x_pows = (0..=(max_degree + 1).ilog2())
+            .map(|k| kzg.g2_srs[max_degree + 2 - (1 << k)])
+            .collect();
+
+        Self {
+            big_n,
+            cm1_1: kzg.g1_srs[0],
+            cm2_1: kzg.g2_srs[0],
+            cm2_x: kzg.g2_srs[1],
+            z_v_2,
+            t_x_2,
+            cm2_x_pows,
+        }
+    }
+
+    // [x^{D-(n-2)}]_2, or None if n is not a power of 2 or exceeds D + 1.
+    pub fn degree_check_g2(&self, small_n: usize) -> Option<P::G2Affine> {
+        if !small_n.is_power_of_two() {
+            return None;
+        }
+        self.cm2_x_pows.get(small_n.trailing_zeros() as usize).copied()
+    }
+}
+
+impl <P: Pairing> VerifyingKey<P> {
+    pub fn prepare(&self) -> PreparedVerifyingKey<P> {
+        PreparedVerifyingKey {
+            vk: self.clone(),
+            cm2_1: self.cm2_1.into(),
+            cm2_x: self.cm2_x.into(),
+            z_v_2: self.z_v_2.into(),
+            t_x_2: self.t_x_2.into(),
+            cm2_x_pows: self.cm2_x_pows.iter().map(|p| (*p).into()).collect(),
+        }
+    }
+}
+
+// The verifying key with its G2 elements prepared for pairings once,
+// so each verification only runs Miller loops over cached lines.
+#[derive(Debug, Clone)]
+pub struct PreparedVerifyingKey<P: Pairing> {
+    pub vk: VerifyingKey<P>,
+    pub cm2_1: P::G2Prepared,
+    pub cm2_x: P::G2Prepared,
+    pub z_v_2: P::G2Prepared,
+    pub t_x_2: P::G2Prepared,
+    pub cm2_x_pows: Vec<P::G2Prepared>,
+}
+
+impl <P: Pairing> PreparedVerifyingKey<P> {
+    // Prepared [x^{D-(n-2)}]_2, or None if n is not a power of 2 or exceeds D + 1.
+    pub fn degree_check_g2(&self, small_n: usize) -> Option<&P::G2Prepared> {
+        if !small_n.is_power_of_two() {
+            return None;
+        }
+        self.cm2_x_pows.get(small_n.trailing_zeros() as usize)
+    }
+}
+
+impl <P: Pairing> From<VerifyingKey<P>> for PreparedVerifyingKey<P> {
+    fn from(vk: VerifyingKey<P>) -> Self {
+        vk.prepare()
+    }
 }
 
-impl <P: Pairing> Cq<P> {
+// Tables are padded to a power of two N >= 2 by repeating their last entry,
+// which leaves the set of values looked up into unchanged.
+pub(crate) fn pad_table<F: Copy>(t_i: &[F]) -> Result<Vec<F>, GeneralError> {
+    let last = *t_i.last().ok_or(GeneralError::EmptyTable)?;
+    let mut padded = t_i.to_vec();
+    padded.resize(t_i.len().next_power_of_two().max(2), last);
+    Ok(padded)
+}
+
+impl <P: Pairing> Cq<'static, P> {
+    // The SRS has N powers, so witnesses can be as large as the padded table.
     pub fn new(t_i: &[P::ScalarField]) -> Result<Self, GeneralError> {
+        let t_i = pad_table(t_i)?;
         let big_n = t_i.len();
-        if !big_n.is_power_of_two() {
-            return Err(GeneralError::TableSizeNotPowerOf2);
+        Self::setup(&t_i, Cow::Owned(Kzg::<P>::new(big_n)))
+    }
+}
+
+impl <'a, P: Pairing> Cq<'a, P> {
+    // Preprocess the table under a borrowed SRS, e.g. loaded from a powers-of-tau ceremony.
+    // The SRS can be a universal one shared by tables of any size N <= D + 1, it needs
+    // D + 1 powers in G1 and D + 2 powers in G2. Degree checks are done against D.
+    // Witnesses can be larger than the table, up to D + 1 once padded.
+    pub fn new_with_srs(t_i: &[P::ScalarField], srs: &'a Kzg<P>) -> Result<Self, GeneralError> {
+        let t_i = pad_table(t_i)?;
+        Self::check_srs(srs, t_i.len())?;
+        Self::setup(&t_i, Cow::Borrowed(srs))
+    }
+
+    pub(crate) fn check_srs(srs: &Kzg<P>, big_n: usize) -> Result<(), SrsError> {
+        if srs.g1_srs.len() < big_n {
+            return Err(SrsError::TooShort { group: "G1", needed: big_n, available: srs.g1_srs.len() });
         }
+        if srs.g2_srs.len() < srs.g1_srs.len() + 1 {
+            return Err(SrsError::TooShort { group: "G2", needed: srs.g1_srs.len() + 1, available: srs.g2_srs.len() });
+        }
+        Ok(())
+    }
 
-        
+    fn setup(t_i: &[P::ScalarField], kzg: Cow<'a, Kzg<P>>) -> Result<Self, GeneralError> {
+        let big_n = t_i.len();
         let domain = GeneralEvaluationDomain::<P::ScalarField>::new(big_n).unwrap();
-        let kzg = Kzg::<P>::new(big_n);
-        
-        let z_v_2: P::G2Affine = kzg.g2_srs[big_n].sub(kzg.g2_srs[0]).into();
-        
-        let mut t_hash_map = HashMap::<P::ScalarField, usize>::new();
-        for (i, x) in t_i.iter().enumerate() {
-            if t_hash_map.get(x).is_some() {
-                return Err(GeneralError::ElementsNotUnique);
-            }
-            t_hash_map.insert(*x, i);
-        }
-        
+        // the size-N pieces only use the first N powers of the SRS
+        let g1_srs = &kzg.g1_srs[..big_n];
+
+        let t_hash_map = Self::compute_t_hash_map(t_i);
+
         let t_x_coeffs = domain.ifft(t_i);
         let t_x = DensePolynomial::from_coefficients_vec(t_x_coeffs);
         let t_x_2 = kzg.commit_g2(&t_x);
 
         // let start = std::time::Instant::now();
-        let cm1_qi = Self::compute_cm1_qi(&domain, &t_x, &kzg.g1_srs);
+        let cm1_qi = Self::compute_cm1_qi(&domain, &t_x, g1_srs);
         // println!("Time to create cm1_qi: {:?}", start.elapsed());
 
         // let start = std::time::Instant::now();
-        let cm1_li = fast_lagrange_basis_commitments_computation::<P>(&kzg.g1_srs, big_n);
+        let cm1_li = fast_lagrange_basis_commitments_computation::<P>(g1_srs, big_n);
         // println!("Time to create cm1_li: {:?}", start.elapsed());
 
         // let start = std::time::Instant::now();
-        let cm1_l_i_quotient = compute_quotient_lagrange_basic_commitments::<P>(&cm1_li, &kzg.g1_srs, big_n);
+        let cm1_l_i_quotient = compute_quotient_lagrange_basic_commitments::<P>(&cm1_li, g1_srs, big_n);
         // println!("Time to create cm1_l_i_quotient: {:?}", start.elapsed());
-        Ok(Self {
+
+        let cm1_l_i_quotient_shifted = Self::compute_cm1_l_i_quotient_shifted(&kzg, &cm1_li, big_n);
+
+        let pk = ProvingKey {
             kzg,
             big_n,
-            z_v_2,
+            t_i: t_i.to_vec(),
             t_hash_map,
             t_x_2,
             cm1_qi,
             cm1_li,
             cm1_l_i_quotient,
-        })
+            cm1_l_i_quotient_shifted,
+        };
+        let vk = pk.verifying_key();
+        Ok(Self { pk, vk })
     }
 
-    fn compute_cm1_qi(domain: &GeneralEvaluationDomain<P::ScalarField>, t_x: &DensePolynomial<P::ScalarField>, srs_g1: &[P::G1Affine]) -> Vec<P::G1Affine> {
+    // Maps each value to its first index in the table, the canonical index the prover
+    // assigns the multiplicities of a repeated value to.
+    fn compute_t_hash_map(t_i: &[P::ScalarField]) -> HashMap<P::ScalarField, usize> {
+        let mut t_hash_map = HashMap::<P::ScalarField, usize>::new();
+        for (i, x) in t_i.iter().enumerate() {
+            t_hash_map.entry(*x).or_insert(i);
+        }
+        t_hash_map
+    }
+
+    // [x^{D-(N-2)}·(L_i(x) - L_i(0))/x]_1 = [x^{D-(N-1)}·L_i(x)]_1 - 1/N·[x^{D-(N-1)}]_1,
+    // with [x^{D-(N-1)}·L_i(x)]_1 computed from the powers D-(N-1)..D of the SRS.
+    pub(crate) fn compute_cm1_l_i_quotient_shifted(kzg: &Kzg<P>, cm1_li: &[P::G1Affine], big_n: usize) -> Vec<P::G1Affine> {
+        let shift = kzg.max_degree() + 1 - big_n;
+        let shifted_srs = &kzg.g1_srs[shift..];
+        let cm1_li_shifted = if shift == 0 {
+            cm1_li.to_vec()
+        } else {
+            fast_lagrange_basis_commitments_computation::<P>(shifted_srs, big_n)
+        };
+        let big_n_inv = P::ScalarField::from(big_n as u64).inverse().unwrap();
+        let sub = shifted_srs[0].mul(big_n_inv);
+        let res: Vec<P::G1> = cfg_iter!(cm1_li_shifted).map(|l_i| *l_i - sub).collect();
+        P::G1::normalize_batch(&res)
+    }
+
+    pub(crate) fn compute_cm1_qi(domain: &GeneralEvaluationDomain<P::ScalarField>, t_x: &DensePolynomial<P::ScalarField>, srs_g1: &[P::G1Affine]) -> Vec<P::G1Affine> {
         let big_n_inv = domain.size_as_field_element().inverse().unwrap();
-        let toeplitz = ToeplitzMatrix::<P>::new(t_x);
-        let hs: Vec<P::G1> = toeplitz.compute_h_coefficients(srs_g1);
-        
-        // assert_eq!(hs.len(), 2 * domain.size());
-        let ks = domain.fft(&hs[..domain.size()]);
-
-        let res: Vec<P::G1Affine> = ks.iter().zip(domain.elements()).map(|(k_s_i, g_i)| {
-            k_s_i.mul(g_i * big_n_inv).into()
+        // [Q_i(x)]_1 = ω^i/N·[(T(x) - T(ω^i))/(x - ω^i)]_1
+        let ks = compute_openings::<P, _>(srs_g1, t_x, domain);
+
+        let elements: Vec<P::ScalarField> = domain.elements().collect();
+        let res: Vec<P::G1> = cfg_iter!(ks).zip(&elements).map(|(k_s_i, g_i)| {
+            k_s_i.mul(*g_i * big_n_inv)
         }).collect();
 
-        // let res = P::G1::normalize_batch(&tmp);
-        res
+        P::G1::normalize_batch(&res)
+    }
+}
+
+// The table lookup map is not serialized, it is rebuilt from t_i when loading.
+impl <P: Pairing> CanonicalSerialize for ProvingKey<'_, P> {
+    fn serialize_with_mode<W: Write>(&self, mut writer: W, compress: Compress) -> Result<(), SerializationError> {
+        self.kzg.serialize_with_mode(&mut writer, compress)?;
+        self.serialize_without_srs(writer, compress)
+    }
+
+    fn serialized_size(&self, compress: Compress) -> usize {
+        self.kzg.serialized_size(compress)
+            + self.big_n.serialized_size(compress)
+            + self.t_i.serialized_size(compress)
+            + self.t_x_2.serialized_size(compress)
+            + self.cm1_qi.serialized_size(compress)
+            + self.cm1_li.serialized_size(compress)
+            + self.cm1_l_i_quotient.serialized_size(compress)
+            + self.cm1_l_i_quotient_shifted.serialized_size(compress)
+    }
+}
+
+impl <P: Pairing> Valid for ProvingKey<'_, P> {
+    fn check(&self) -> Result<(), SerializationError> {
+        self.kzg.check()?;
+        self.t_x_2.check()?;
+        self.cm1_qi.check()?;
+        self.cm1_li.check()?;
+        self.cm1_l_i_quotient.check()?;
+        self.cm1_l_i_quotient_shifted.check()?;
+        self.check_sizes()
+    }
+}
+
+impl <P: Pairing> CanonicalDeserialize for ProvingKey<'_, P> {
+    fn deserialize_with_mode<R: Read>(mut reader: R, compress: Compress, validate: Validate) -> Result<Self, SerializationError> {
+        let kzg = Kzg::deserialize_with_mode(&mut reader, compress, validate)?;
+        Self::deserialize_with_srs(reader, Cow::Owned(kzg), compress, validate)
+    }
+}
+
+// Files written by Cq::save hold everything but the SRS, which is shared by the tables preprocessed
+// under it and given back when loading.
+impl <'a, P: Pairing> ProvingKey<'a, P> {
+    pub(crate) fn serialize_without_srs<W: Write>(&self, mut writer: W, compress: Compress) -> Result<(), SerializationError> {
+        self.big_n.serialize_with_mode(&mut writer, compress)?;
+        self.t_i.serialize_with_mode(&mut writer, compress)?;
+        self.t_x_2.serialize_with_mode(&mut writer, compress)?;
+        self.cm1_qi.serialize_with_mode(&mut writer, compress)?;
+        self.cm1_li.serialize_with_mode(&mut writer, compress)?;
+        self.cm1_l_i_quotient.serialize_with_mode(&mut writer, compress)?;
+        self.cm1_l_i_quotient_shifted.serialize_with_mode(&mut writer, compress)
+    }
+
+    // The points read are checked with Validate::Yes, the SRS is not.
+    pub(crate) fn deserialize_with_srs<R: Read>(mut reader: R, kzg: Cow<'a, Kzg<P>>, compress: Compress, validate: Validate) -> Result<Self, SerializationError> {
+        let big_n = usize::deserialize_with_mode(&mut reader, compress, validate)?;
+        let t_i = Vec::<P::ScalarField>::deserialize_with_mode(&mut reader, compress, validate)?;
+        let t_x_2 = P::G2Affine::deserialize_with_mode(&mut reader, compress, validate)?;
+        let cm1_qi = Vec::deserialize_with_mode(&mut reader, compress, validate)?;
+        let cm1_li = Vec::deserialize_with_mode(&mut reader, compress, validate)?;
+        let cm1_l_i_quotient = Vec::deserialize_with_mode(&mut reader, compress, validate)?;
+        let cm1_l_i_quotient_shifted = Vec::deserialize_with_mode(&mut reader, compress, validate)?;
+        let t_hash_map = Cq::<P>::compute_t_hash_map(&t_i);
+
+        let pk = Self {
+            kzg,
+            big_n,
+            t_i,
+            t_hash_map,
+            t_x_2,
+            cm1_qi,
+            cm1_li,
+            cm1_l_i_quotient,
+            cm1_l_i_quotient_shifted,
+        };
+        if let Validate::Yes = validate {
+            pk.check_sizes()?;
+        }
+        Ok(pk)
+    }
+
+    fn check_sizes(&self) -> Result<(), SerializationError> {
+        let big_n = self.big_n;
+        if !big_n.is_power_of_two() || big_n < 2 || self.t_i.len() != big_n || self.cm1_qi.len() != big_n || self.cm1_li.len() != big_n
+            || self.cm1_l_i_quotient.len() != big_n || self.cm1_l_i_quotient_shifted.len() != big_n
+            || self.kzg.g1_srs.len() < big_n || self.kzg.g2_srs.len() <= self.kzg.g1_srs.len() {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
     use ark_bls12_381::Bls12_381;
     use ark_bls12_381::Fr;
-    use ark_poly::{EvaluationDomain, GeneralEvaluationDomain};
-    use crate::cq::Cq;
+    use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain};
+    use ark_poly::univariate::DensePolynomial;
+    use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+    use crate::cq::{Cq, ProvingKey, VerifyingKey};
+    use crate::errors::{GeneralError, SrsError};
+    use crate::kzg::Kzg;
+    use crate::prover::Prover;
+    use crate::verifier::Verifier;
 
     #[test]
     fn test_cq() {
         let t_i = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4), Fr::from(5), Fr::from(6), Fr::from(7), Fr::from(8)];
-        let cq = Cq::<Bls12_381>::new(&t_i).unwrap();
-        let domain = GeneralEvaluationDomain::<Fr>::new(cq.big_n).unwrap();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let domain = GeneralEvaluationDomain::<Fr>::new(pk.big_n).unwrap();
         let vanish_poly = domain.vanishing_polynomial();
 
-        let cm2_vanish  = cq.kzg.commit_g2(&vanish_poly.into());
-        assert_eq!(cm2_vanish, cq.z_v_2);
-        assert_eq!(cq.big_n, 8);
-        assert_eq!(cq.t_hash_map.len(), t_i.len());
+        let cm2_vanish  = pk.kzg.commit_g2(&vanish_poly.into());
+        assert_eq!(cm2_vanish, vk.z_v_2);
+        assert_eq!(pk.big_n, 8);
+        assert_eq!(vk.big_n, 8);
+        assert_eq!(pk.t_hash_map.len(), t_i.len());
+    }
+
+    #[test]
+    fn test_tables_with_duplicates() {
+        // a range table 0..5 padded to 8 with its last value
+        let t_i: Vec<Fr> = [0, 1, 2, 3, 4, 4, 4, 4].into_iter().map(Fr::from).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        assert_eq!(pk.t_hash_map.len(), 5);
+        assert_eq!(pk.t_hash_map[&Fr::from(4)], 4);
+
+        let f_i = vec![Fr::from(4), Fr::from(0), Fr::from(4), Fr::from(4)];
+        let proof = Prover::<Bls12_381>::new(f_i).unwrap().prove(&pk).unwrap();
+        // all the multiplicities of 4 are at its first index
+        let m_i: Vec<Fr> = [1, 0, 0, 0, 3, 0, 0, 0].into_iter().map(Fr::from).collect();
+        let domain = GeneralEvaluationDomain::<Fr>::new(8).unwrap();
+        assert_eq!(proof.cm1_m, pk.kzg.commit_g1(&DensePolynomial::from_coefficients_vec(domain.ifft(&m_i))));
+        assert!(Verifier::<Bls12_381>::new().verify(&vk.prepare(), proof).is_ok());
+
+        // the table of x -> x mod 3, every value repeats
+        let t_i: Vec<Fr> = (0..16).map(|x| Fr::from(x % 3)).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let f_i: Vec<Fr> = (0..16).map(|x| Fr::from((x * 7) % 3)).collect();
+        let proof = Prover::<Bls12_381>::new(f_i).unwrap().prove(&pk).unwrap();
+        let pvk = vk.prepare();
+        assert!(Verifier::<Bls12_381>::new().verify(&pvk, proof.clone()).is_ok());
+        assert!(Verifier::<Bls12_381>::new().batched_verify(&pvk, proof).is_ok());
+
+        let f_i = vec![Fr::from(1), Fr::from(3)];
+        assert!(matches!(Prover::<Bls12_381>::new(f_i).unwrap().prove(&pk), Err(GeneralError::WitnessNotInTable)));
+
+        // a table with a single repeated value
+        let t_i = vec![Fr::from(9); 4];
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let proof = Prover::<Bls12_381>::new(vec![Fr::from(9); 4]).unwrap().prove(&pk).unwrap();
+        assert!(Verifier::<Bls12_381>::new().verify(&vk.prepare(), proof).is_ok());
+    }
+
+    #[test]
+    fn test_verifying_key_degree_check_powers() {
+        let t_i: Vec<Fr> = (0..8).map(Fr::from).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+
+        assert_eq!(vk.cm2_x_pows.len(), 4);
+        for small_n in [1, 2, 4, 8] {
+            let mut x_pow = vec![Fr::from(0); pk.big_n + 1 - small_n];
+            x_pow.push(Fr::from(1));
+            let expected = pk.kzg.commit_g2(&DensePolynomial::from_coefficients_vec(x_pow));
+            assert_eq!(vk.degree_check_g2(small_n), Some(expected));
+        }
+        assert_eq!(vk.degree_check_g2(3), None);
+        assert_eq!(vk.degree_check_g2(16), None);
+    }
+
+    #[test]
+    fn test_new_with_srs() {
+        let t_i: Vec<Fr> = (0..8).map(Fr::from).collect();
+        let srs = Kzg::<Bls12_381>::new_from_secret(Fr::from(1234), 8);
+        let Cq { pk, vk } = Cq::<Bls12_381>::new_with_srs(&t_i, &srs).unwrap();
+
+        // an SRS of exactly the table size gives the same keys as Cq::new
+        let expected = Cq::<Bls12_381>::setup(&t_i, Cow::Owned(Kzg::new_from_secret(Fr::from(1234), 8))).unwrap();
+        assert_eq!(pk.cm1_qi, expected.pk.cm1_qi);
+        assert_eq!(pk.cm1_l_i_quotient_shifted, expected.pk.cm1_l_i_quotient_shifted);
+        assert_eq!(vk, expected.vk);
+
+        let small_srs = Kzg::<Bls12_381>::new_from_secret(Fr::from(1234), 4);
+        let result = Cq::<Bls12_381>::new_with_srs(&t_i, &small_srs);
+        assert!(matches!(result, Err(GeneralError::Srs(SrsError::TooShort { needed: 8, available: 4, .. }))));
+
+        let mut no_g2_srs = srs.clone();
+        no_g2_srs.g2_srs.pop();
+        let result = Cq::<Bls12_381>::new_with_srs(&t_i, &no_g2_srs);
+        assert!(matches!(result, Err(GeneralError::Srs(SrsError::TooShort { group: "G2", .. }))));
+    }
+
+    #[test]
+    fn test_universal_srs_pieces() {
+        let max_degree = 31;
+        let srs = Kzg::<Bls12_381>::new(max_degree + 1);
+        let t_i: Vec<Fr> = (0..8).map(|i| Fr::from(i * i)).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new_with_srs(&t_i, &srs).unwrap();
+        assert!(matches!(pk.kzg, Cow::Borrowed(_)));
+
+        let domain = GeneralEvaluationDomain::<Fr>::new(8).unwrap();
+        let t_x = DensePolynomial::from_coefficients_vec(domain.ifft(&t_i));
+        assert_eq!(vk.z_v_2, srs.commit_g2(&domain.vanishing_polynomial().into()));
+        assert_eq!(vk.t_x_2, srs.commit_g2(&t_x));
+
+        for i in 0..8 {
+            let mut evals = vec![Fr::from(0); 8];
+            evals[i] = Fr::from(1);
+            let l_i = DensePolynomial::from_coefficients_vec(domain.ifft(&evals));
+            assert_eq!(pk.cm1_li[i], srs.commit_g1(&l_i));
+
+            let l_i_quotient = &(&l_i - &DensePolynomial::from_coefficients_vec(vec![l_i.coeffs[0]]))
+                / &DensePolynomial::from_coefficients_vec(vec![Fr::from(0), Fr::from(1)]);
+            assert_eq!(pk.cm1_l_i_quotient[i], srs.commit_g1(&l_i_quotient));
+
+            let mut shifted = vec![Fr::from(0); max_degree - 6];
+            shifted.extend(&l_i_quotient.coeffs);
+            assert_eq!(pk.cm1_l_i_quotient_shifted[i], srs.commit_g1(&DensePolynomial::from_coefficients_vec(shifted)));
+        }
+
+        // the degree checks are against the degree of the SRS, not the table size,
+        // and witnesses up to the size of the SRS are supported
+        assert_eq!(vk.cm2_x_pows.len(), 6);
+        for small_n in [1, 2, 4, 8, 16, 32] {
+            let mut x_pow = vec![Fr::from(0); max_degree + 2 - small_n];
+            x_pow.push(Fr::from(1));
+            assert_eq!(vk.degree_check_g2(small_n), Some(srs.commit_g2(&DensePolynomial::from_coefficients_vec(x_pow))));
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tables_of_different_sizes_under_one_srs() {
+        let srs = Kzg::<Bls12_381>::new(1 << 10);
+        let mut vks = vec![];
+        for log_n in [4, 8, 10] {
+            let big_n = 1 << log_n;
+            let t_i: Vec<Fr> = (0..big_n).map(|i| Fr::from(3 * i as u64 + 1)).collect();
+            let Cq { pk, vk } = Cq::<Bls12_381>::new_with_srs(&t_i, &srs).unwrap();
+            let pvk = vk.prepare();
+
+            let f_i = vec![t_i[0], t_i[big_n - 1], t_i[big_n / 2], t_i[0]];
+            let proof = Prover::<Bls12_381>::new(f_i).unwrap().prove(&pk).unwrap();
+            assert!(Verifier::<Bls12_381>::new().verify(&pvk, proof.clone()).is_ok());
+            assert!(Verifier::<Bls12_381>::new().batched_verify(&pvk, proof).is_ok());
+            vks.push(vk);
+        }
+        assert!(vks.iter().all(|vk| vk.cm1_1 == srs.g1_srs[0] && vk.cm2_x == srs.g2_srs[1]));
+    }
+
+    #[test]
+    fn test_keys_serialization() {
+        let t_i: Vec<Fr> = (0..8).map(Fr::from).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+
+        let mut vk_bytes = vec![];
+        vk.serialize_compressed(&mut vk_bytes).unwrap();
+        let vk_2 = VerifyingKey::<Bls12_381>::deserialize_compressed(vk_bytes.as_slice()).unwrap();
+        assert_eq!(vk, vk_2);
+
+        let mut pk_bytes = vec![];
+        pk.serialize_compressed(&mut pk_bytes).unwrap();
+        assert_eq!(pk_bytes.len(), pk.compressed_size());
+        let pk_2 = ProvingKey::<Bls12_381>::deserialize_compressed(pk_bytes.as_slice()).unwrap();
+        assert_eq!(pk.t_i, pk_2.t_i);
+        assert_eq!(pk.t_hash_map, pk_2.t_hash_map);
+        assert_eq!(pk.cm1_qi, pk_2.cm1_qi);
+        assert_eq!(pk.cm1_li, pk_2.cm1_li);
+        assert_eq!(pk.cm1_l_i_quotient, pk_2.cm1_l_i_quotient);
+        assert_eq!(vk, pk_2.verifying_key());
+        assert!(vk_bytes.len() < pk_bytes.len());
+
+        // consistent lengths, but N = 3 is not a table size
+        let bad_pk = ProvingKey::<Bls12_381> {
+            kzg: pk.kzg.clone(),
+            big_n: 3,
+            t_i: pk.t_i[..3].to_vec(),
+            t_hash_map: HashMap::new(),
+            t_x_2: pk.t_x_2,
+            cm1_qi: pk.cm1_qi[..3].to_vec(),
+            cm1_li: pk.cm1_li[..3].to_vec(),
+            cm1_l_i_quotient: pk.cm1_l_i_quotient[..3].to_vec(),
+            cm1_l_i_quotient_shifted: pk.cm1_l_i_quotient_shifted[..3].to_vec(),
+        };
+        let mut bad_bytes = vec![];
+        bad_pk.serialize_compressed(&mut bad_bytes).unwrap();
+        let result = ProvingKey::<Bls12_381>::deserialize_compressed(bad_bytes.as_slice());
+        assert!(matches!(result, Err(SerializationError::InvalidData)));
+    }
+
+    // The commitments of a small table under a fixed SRS, generated without the parallel feature
+    // and checked with and without it.
+    #[test]
+    fn test_fixed_commitments() {
+        let kzg = Kzg::<Bls12_381>::new_from_secret(Fr::from(1234), 8);
+        let t_i: Vec<Fr> = (0..4u64).map(|i| Fr::from(i * i + 7)).collect();
+        let Cq { pk, .. } = Cq::<Bls12_381>::new_with_srs(&t_i, &kzg).unwrap();
+        fn hex(value: &impl CanonicalSerialize) -> String {
+            let mut bytes = vec![];
+            value.serialize_compressed(&mut bytes).unwrap();
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+        assert_eq!(hex(&pk.t_x_2), FIXED_T_X_2);
+        assert_eq!(hex(&pk.cm1_qi), FIXED_CM1_QI);
+        assert_eq!(hex(&pk.cm1_li), FIXED_CM1_LI);
+        assert_eq!(hex(&pk.cm1_l_i_quotient), FIXED_CM1_L_I_QUOTIENT);
+        assert_eq!(hex(&pk.cm1_l_i_quotient_shifted), FIXED_CM1_L_I_QUOTIENT_SHIFTED);
+    }
+
+    const FIXED_T_X_2: &str = "a1d31d773d801175c433c619ef9f851f22cffd42b65780ed13d9c8c1f03ba2d1542fb029ce4ec0464ed4b06aff9c0def0d425b44ca400ade27a05fedaab773a8abe27397d1e58f613707748c91f246a55bda4bf388a1ae5b99be98cfc60f3c0d";
+    const FIXED_CM1_QI: &str = "0400000000000000a51ad9363a8c11eddcb2b709050f48cf2e47df6b537c6b0863c0f3bd8b9ff35b786be79fba1ea0e2ddc3a797664dc842ad624485b6d04d53aa207ceb4047710f7cdd4fbbc7e7e5d46a5442015e21a7528d33dacf80951d3709c6eeeddea0455fa6639315477d75748ebc2886a71377f572a042f9b08f5e3ee8f7dfbfa08bc71eec48734670cb223667e11636fc503b3d8e9684723fa6de5bddc151c234e603df1a3ef833f44546087ec97ed9ed259d15e8762e8e7d1411298ea76e7740f36d0a";
+    const FIXED_CM1_LI: &str = "0400000000000000a7e2411966fb5aeeb0d04ca5e2424886cf2afaf01c1b3e8b4850281408ff7af0f3951b3de2a7a4979cb7f9e0d270a98b86f86a2e826fe5a0487ba5d45029440cbffebfab31ca1a0ab2d5e08f1f625e1831bdc2301cabea491ead39d394b5f5d58bc3c97448e03404d18b1e89298783d7345751d6250d5ed024bd7d820b540e50b6e22a3576adb1e45dc3e90632bd180c8b079098cdcecc7472450a3cc2dc92c0ae6e671a0e5311e66f4dd218f62b37942075c7b8e11acec8be4acff5333e1de8";
+    const FIXED_CM1_L_I_QUOTIENT: &str = "0400000000000000ae2a16a4821f76d4a1d951710bdd9a8d3167eb8806dac08a07d41ad896c5c4cf0596e785cf1b9ef6860c9f77254c928599109452ee5019b9c6a83215add2f9531ebc4cab4e8c0bdd039b4c337735cbefb3af82b5502f1d3e194709b7466a73d6b7d5399b3f130ede59e618e906db103a93edcefbeff26df440cd3aa0eeb5cce8f73f13205acce82302b46afb426063d280fb2d72a9aa563489b749e887d834fe26c53e2742787bb368d884ae9c7f35afd5259ca4b0520758834c9e3b28fc6f84";
+    const FIXED_CM1_L_I_QUOTIENT_SHIFTED: &str = "040000000000000086985c9db2d3a9e3ca1e520f2133a1b01fdaa72a25d4a23e175687aa8a5ad8ac6c959cb943d006105ecc9302a368e1dea6dc7ef15d38f159a158078cd5d5abebbb3ef0e5af64b962925ac310c6821a2c8bf303aa1b0db685b16837afdf57f3cd8e8d0d5f6dcf3eb5dde2929c4d958cba48cab8ccd5e3c60917d0c60e4e883f60bbf899a061eb3356673af735487a4f23865d993234c6c5919387e5be40c79742a06cd69cfba9569f70e6a2926e56896673dba2f33aad7071bf20e46f0c3f4eed";
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_matches_single_thread() {
+        let kzg = Kzg::<Bls12_381>::new(64);
+        let t_i: Vec<Fr> = (0..32u64).map(|i| Fr::from(i * i + 7)).collect();
+        let f_i: Vec<Fr> = (0..20).map(|i| t_i[(3 * i) % 32]).collect();
+        let run = || {
+            let Cq { pk, vk } = Cq::<Bls12_381>::new_with_srs(&t_i, &kzg).unwrap();
+            let proof = Prover::<Bls12_381>::new(f_i.clone()).unwrap().prove(&pk).unwrap();
+            let mut bytes = vec![];
+            (pk.cm1_qi, pk.cm1_li, pk.cm1_l_i_quotient, pk.cm1_l_i_quotient_shifted, vk).serialize_compressed(&mut bytes).unwrap();
+            proof.serialize_compressed(&mut bytes).unwrap();
+            bytes
+        };
+        let single = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap().install(run);
+        let multi = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap().install(run);
+        assert_eq!(single, multi);
+    }
+}
@@ -1,3 +1,4 @@
+use ark_serialize::SerializationError;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -5,15 +6,177 @@ pub enum GeneralError {
     #[error("Cannot divide by vanishing polynomial.")]
     CannotDivideByVanishingPolynomial,
     
-    #[error("The size of witness f must be power of 2")]
-    WitnessSizeNotPowerOf2,
+    #[error("The witness f must not be empty")]
+    EmptyWitness,
 
-    #[error("The size of table t must be power of 2")]
-    TableSizeNotPowerOf2,
+    #[error("The padded size of witness f must not exceed the number of powers of the SRS in G1")]
+    WitnessLargerThanSrs,
+
+    #[error("The padded size of witness f must not exceed the size of the table in zero-knowledge mode")]
+    WitnessLargerThanTable,
+
+    #[error("The commitment cm1_f is not a commitment to the padded witness f")]
+    WitnessCommitmentMismatch,
+
+    #[error("The selector must have one entry for each witness value")]
+    SelectorSizeMismatch,
+
+    #[error("The selector entries must be 0 or 1")]
+    InvalidSelector,
+
+    #[error("The table t must not be empty")]
+    EmptyTable,
     
     #[error("Witness must be in the table")]
     WitnessNotInTable,
 
-    #[error("Elements in t_i must be unique")]
-    ElementsNotUnique,
+    #[error("The columns must be distinct columns of the table, one for each witness column")]
+    InvalidColumns,
+
+    #[error("All the columns must have the same size")]
+    ColumnSizesDiffer,
+
+    #[error("There must be one witness for each table")]
+    TableCountMismatch,
+
+    #[error("The tables must be preprocessed with the same SRS")]
+    SrsNotShared,
+
+    #[error(transparent)]
+    Srs(#[from] SrsError),
+}
+
+#[derive(Debug, Error)]
+pub enum SrsError {
+    #[error("Failed to read the SRS: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to deserialize the SRS: {0}")]
+    Serialization(#[from] SerializationError),
+
+    #[error("Malformed ptau file: {0}")]
+    MalformedPtau(String),
+
+    #[error("The SRS has {available} powers in {group}, {needed} are needed")]
+    TooShort { group: &'static str, needed: usize, available: usize },
+
+    #[error("The SRS contains a point that is not in the prime order subgroup")]
+    InvalidPoint,
+
+    #[error("The SRS points are not consistent powers of the same secret")]
+    InconsistentPowers,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum VerificationError {
+    #[error("Failed to verify e(a, [T(x)]_2) = e(q_a, [Zv(x)]_2) · e(m − β · a, [1]_2)")]
+    QuotientIdentityFailed,
+
+    #[error("Failed to verify e(b_0, [X^(D - (n-2))]_2) = e(p, [1]_2)")]
+    DegreeCheckFailed,
+
+    #[error("Failed to verify e(c - [v]_1 + gamma * [pi_eta]_1, [1]_2) = e([pi_gamma]_1, [x]_2)")]
+    BatchedKzgOpeningFailed,
+
+    #[error("Failed to verify e(a_1 - [a_0]_1, [1]_2) = e(a0_1, [x]_2)")]
+    AZeroCheckFailed,
+
+    #[error("Failed to verify e(a0_1, [X^(D - (N-2))]_2) = e(p_a_0, [1]_2)")]
+    AZeroDegreeCheckFailed,
+
+    #[error("Failed to verify e(b_lift - [b_z]_1 + z * pi_lift, [X^(D - (2k-2))]_2) = e(pi_lift, [X^(D - (k-2))]_2)")]
+    LiftCheckFailed,
+
+    #[error("Failed to verify e(N * a + chi * s - [chi * s_sum / N]_1 - n * b_lift, [1]_2) = e(q_s, [Zv(x)]_2) · e(w, [x]_2)")]
+    SumCheckFailed,
+
+    #[error("The proof is for a different commitment cm1_f than the expected one")]
+    WitnessCommitmentMismatch,
+
+    #[error("The proof must open a selector exactly when the verifier is given its commitment")]
+    SelectorMismatch,
+
+    #[error("Failed to verify the aggregated pairing check")]
+    BatchedCheckFailed,
+
+    #[error("Failed to verify the accumulated pairing check")]
+    AccumulatorCheckFailed,
+
+    #[error("Witness size {small_n} is not supported for table size {big_n}")]
+    MalformedSizes { small_n: usize, big_n: usize },
+
+    #[error("A challenge value is not invertible")]
+    NonInvertibleChallenge,
+
+    #[error("The columns must be distinct columns of the table, one for each witness column")]
+    InvalidColumns,
+
+    #[error("The compressed witness commitment is not the combination of the column commitments")]
+    CompressedWitnessMismatch,
+
+    #[error("The witness size of the proof does not fit the number of witness columns")]
+    ColumnCountMismatch,
+
+    #[error("Failed to verify that the interleaved witness holds the witness columns")]
+    ColumnLinkFailed,
+
+    #[error("There must be one proof for each table")]
+    TableCountMismatch,
+
+    #[error("The verifying keys must share the same SRS")]
+    SrsNotShared,
+}
+
+#[derive(Debug, Error)]
+pub enum CeremonyError {
+    #[error("The ceremony has no contributions")]
+    NoContributions,
+
+    #[error("The contribution secret must not be zero")]
+    ZeroSecret,
+
+    #[error("The SRS generators differ from [1]_1 and [1]_2")]
+    GeneratorsChanged,
+
+    #[error("Invalid proof of knowledge in contribution {index}")]
+    InvalidProofOfKnowledge { index: usize },
+
+    #[error("Contribution {index} does not update the previous SRS")]
+    InvalidContribution { index: usize },
+
+    #[error(transparent)]
+    Srs(#[from] SrsError),
+}
+
+#[derive(Debug, Error)]
+pub enum PersistError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to (de)serialize the proving key: {0}")]
+    Serialization(#[from] SerializationError),
+
+    #[error("Not a cq preprocessing file")]
+    BadMagic,
+
+    #[error("Unsupported cq preprocessing file version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("The file was written for a different curve")]
+    CurveMismatch,
+
+    #[error("The file was written under a different SRS")]
+    SrsMismatch,
+
+    #[error("The file digest does not match its content")]
+    DigestMismatch,
+
+    #[error("The file was written for a different table")]
+    TableMismatch,
+
+    #[error("The memory budget of {available} bytes is too small, {needed} bytes are needed")]
+    MemoryBudgetTooSmall { needed: usize, available: usize },
+
+    #[error(transparent)]
+    Setup(#[from] GeneralError),
 }
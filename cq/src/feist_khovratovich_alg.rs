@@ -2,9 +2,13 @@
 use std::ops::Mul;
 use ark_ec::AffineRepr;
 use ark_ec::pairing::Pairing;
-use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain};
+use ark_ff::Field;
+use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain, Polynomial};
 use ark_poly::univariate::DensePolynomial;
 use ark_ff::Zero;
+use ark_std::{cfg_iter, cfg_iter_mut};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 pub struct ToeplitzMatrix <P: Pairing> {
     vec_f: Vec<P::ScalarField>,
 }
@@ -25,7 +29,7 @@ impl<P: Pairing> ToeplitzMatrix<P> {
         let f_degree = self.vec_f.len();
         let domain: GeneralEvaluationDomain<P::ScalarField> = GeneralEvaluationDomain::new(2 * f_degree).unwrap();
         // compute ^s.
-        let mut hat_s: Vec<P::G1> = g1_points[0..].iter().map(|p| {
+        let mut hat_s: Vec<P::G1> = cfg_iter!(g1_points).map(|p| {
             p.into_group()
         }).collect();
         hat_s.reverse();
@@ -42,15 +46,43 @@ impl<P: Pairing> ToeplitzMatrix<P> {
         let v = domain.fft(&hat_c);
 
         // U = y ◦ v
-        for (i, _) in hat_c.iter().enumerate() {
-            y[i] = y[i].mul(v[i]);
-        }
+        cfg_iter_mut!(y).zip(&v).for_each(|(y_i, v_i)| {
+            *y_i = y_i.mul(v_i);
+        });
 
         // ^h = iFFT(U)
         domain.ifft(&y)
     }
 }
 
+// All the opening proofs [(f(x) - f(z))/(x - z)]_1 for z in the domain, a subgroup or a coset of one,
+// in O(n log n) group operations. The proof at z is sum_j z^j·[H_j]_1 with the h coefficients [H_j]_1,
+// an FFT over the domain once the [H_j]_1 are folded mod n, since z^n is the same for all z.
+// The SRS needs the deg(f) first powers.
+pub fn compute_openings<P: Pairing, D: EvaluationDomain<P::ScalarField>>(g1_srs: &[P::G1Affine], f_x: &DensePolynomial<P::ScalarField>, domain: &D) -> Vec<P::G1> {
+    let n = domain.size();
+    if f_x.degree() == 0 {
+        return vec![P::G1::zero(); n];
+    }
+    // the Toeplitz matrix takes a power of two m >= 4 coefficients, the last one zero
+    let mut coeffs = f_x.coeffs.clone();
+    let m = coeffs.len().next_power_of_two().max(4);
+    coeffs.resize(m, P::ScalarField::zero());
+    // the powers past deg(f) - 1 are multiplied by zero coefficients only
+    let mut g1_points = g1_srs[..m.min(g1_srs.len())].to_vec();
+    g1_points.resize(m, P::G1Affine::zero());
+    let hs = ToeplitzMatrix::<P>::new(&DensePolynomial { coeffs }).compute_h_coefficients(&g1_points);
+
+    let mut folded = hs[..m.min(n)].to_vec();
+    folded.resize(n, P::G1::zero());
+    let offset_pow_n = domain.coset_offset_pow_size();
+    for (t, chunk) in hs[..m].chunks(n).enumerate().skip(1) {
+        let scale = offset_pow_n.pow([t as u64]);
+        cfg_iter_mut!(folded).zip(chunk).for_each(|(g, h)| *g += h.mul(scale));
+    }
+    domain.fft(&folded)
+}
+
 #[cfg(test)]
 mod tests {
     use ark_bls12_381::{Bls12_381, Fr};
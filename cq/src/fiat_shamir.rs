@@ -1,77 +1,184 @@
 // Generates Fiat-Shamir challenges for the KZG scheme.
 
 use std::marker::PhantomData;
+use ark_ec::AffineRepr;
 use ark_ec::pairing::Pairing;
-use ark_ff::Zero;
+use ark_ff::PrimeField;
 use ark_serialize::{CanonicalSerialize, Write};
-use ark_std::rand::rngs::StdRng;
-use ark_std::UniformRand;
-use rand::SeedableRng;
 use sha2::Digest;
+use sha2::digest::{BlockInput, ExtendableOutput, Update, XofReader};
+use sha2::digest::generic_array::typenum::Unsigned;
+use crate::cq::VerifyingKey;
 
-pub struct Script<T: Digest + Default, P: Pairing> {
-    data: Option<Vec<u8>>,
-    generated: bool,
+// Domain separation tag used when mapping the transcript state to field elements.
+const HASH_TO_FIELD_DST: &[u8] = b"CQ-V01-FIAT-SHAMIR-HASH-TO-FIELD";
+
+// Security parameter k (in bits) of hash_to_field, see RFC 9380 section 5.
+const SECURITY_BITS: usize = 128;
+
+// Expands a message into uniformly random bytes, see RFC 9380 section 5.3.
+pub trait ExpandMessage {
+    fn expand_message(msg: &[u8], dst: &[u8], len_in_bytes: usize) -> Vec<u8>;
+}
+
+// expand_message_xmd for fixed-output digests such as SHA-256.
+pub struct ExpandMsgXmd<H>(PhantomData<H>);
+
+// expand_message_xof for extendable-output digests such as SHAKE128.
+pub struct ExpandMsgXof<H>(PhantomData<H>);
+
+impl <H: Digest + Default + BlockInput> ExpandMessage for ExpandMsgXmd<H> {
+    fn expand_message(msg: &[u8], dst: &[u8], len_in_bytes: usize) -> Vec<u8> {
+        let b_in_bytes = <H as Digest>::output_size();
+        let r_in_bytes = H::BlockSize::to_usize();
+        let ell = len_in_bytes.div_ceil(b_in_bytes);
+        assert!(ell <= 255 && len_in_bytes <= 65535 && dst.len() <= 255, "expand_message_xmd: invalid lengths");
+
+        let dst_prime = [dst, &[dst.len() as u8]].concat();
+        let l_i_b_str = (len_in_bytes as u16).to_be_bytes();
+
+        // b_0 = H(Z_pad || msg || l_i_b_str || I2OSP(0, 1) || DST_prime)
+        let mut hasher = H::default();
+        Digest::update(&mut hasher, vec![0u8; r_in_bytes]);
+        Digest::update(&mut hasher, msg);
+        Digest::update(&mut hasher, l_i_b_str);
+        Digest::update(&mut hasher, [0u8]);
+        Digest::update(&mut hasher, &dst_prime);
+        let b_0 = hasher.finalize();
+
+        // b_i = H(strxor(b_0, b_(i-1)) || I2OSP(i, 1) || DST_prime), with b_1 = H(b_0 || I2OSP(1, 1) || DST_prime)
+        let mut uniform_bytes = Vec::with_capacity(ell * b_in_bytes);
+        let mut b_i = vec![0u8; b_in_bytes];
+        for i in 1..=ell {
+            let xored: Vec<u8> = b_0.iter().zip(b_i.iter()).map(|(a, b)| a ^ b).collect();
+            let mut hasher = H::default();
+            Digest::update(&mut hasher, xored);
+            Digest::update(&mut hasher, [i as u8]);
+            Digest::update(&mut hasher, &dst_prime);
+            b_i = hasher.finalize().to_vec();
+            uniform_bytes.extend_from_slice(&b_i);
+        }
+        uniform_bytes.truncate(len_in_bytes);
+        uniform_bytes
+    }
+}
+
+impl <H: ExtendableOutput + Update + Default> ExpandMessage for ExpandMsgXof<H> {
+    fn expand_message(msg: &[u8], dst: &[u8], len_in_bytes: usize) -> Vec<u8> {
+        assert!(len_in_bytes <= 65535 && dst.len() <= 255, "expand_message_xof: invalid lengths");
+
+        // H(msg || I2OSP(len_in_bytes, 2) || DST_prime)
+        let mut hasher = H::default();
+        hasher.update(msg);
+        hasher.update((len_in_bytes as u16).to_be_bytes());
+        hasher.update(dst);
+        hasher.update([dst.len() as u8]);
+        let mut uniform_bytes = vec![0u8; len_in_bytes];
+        hasher.finalize_xof().read(&mut uniform_bytes);
+        uniform_bytes
+    }
+}
+
+// Maps a message to `count` field elements, see RFC 9380 section 5.2.
+// Each element is reduced from L = ceil((ceil(log2(p)) + k) / 8) uniform bytes,
+// so the statistical distance to uniform is at most 2^-k.
+pub fn hash_to_field<F: PrimeField, E: ExpandMessage>(msg: &[u8], dst: &[u8], count: usize) -> Vec<F> {
+    let len_per_elm = (F::MODULUS_BIT_SIZE as usize + SECURITY_BITS).div_ceil(8);
+    let uniform_bytes = E::expand_message(msg, dst, count * len_per_elm);
+    uniform_bytes
+        .chunks(len_per_elm)
+        .map(F::from_be_bytes_mod_order)
+        .collect()
+}
+
+// Domain separator of the cq protocol, bumped whenever the transcript layout changes.
+pub const CQ_PROTOCOL_LABEL: &[u8] = b"cq-lookup-v1";
+
+// The Fiat-Shamir transcript used by the prover and the verifier.
+pub trait Transcript<P: Pairing> {
+    fn new(protocol_label: &[u8]) -> Self;
+
+    fn append_message(&mut self, label: &[u8], message: &[u8]);
+
+    fn append_u64(&mut self, label: &[u8], value: u64);
+
+    fn append_point(&mut self, label: &[u8], point: &impl AffineRepr);
+
+    fn append_scalar(&mut self, label: &[u8], scalar: &P::ScalarField);
+
+    fn challenge_scalar(&mut self, label: &[u8]) -> P::ScalarField;
+
+    // Absorbs the statement of a cq proof: the SRS generators, the table commitment and both sizes.
+    fn append_statement(&mut self, vk: &VerifyingKey<P>, small_n: usize) {
+        self.append_point(b"[1]_1", &vk.cm1_1);
+        self.append_point(b"[1]_2", &vk.cm2_1);
+        self.append_point(b"[x]_2", &vk.cm2_x);
+        self.append_point(b"[T(x)]_2", &vk.t_x_2);
+        self.append_u64(b"N", vk.big_n as u64);
+        self.append_u64(b"n", small_n as u64);
+    }
+}
+
+// A Merlin-style transcript over a byte-oriented hash: every absorbed value is framed with
+// its label and length, and every challenge is bound to its label and fed back into the state.
+pub struct Script<T: Digest + Default, P: Pairing, E = ExpandMsgXmd<T>> {
+    state: Vec<u8>,
     _phantom_data_t: PhantomData<T>,
     _phantom_data_p: PhantomData<P>,
+    _phantom_data_e: PhantomData<E>,
 }
 
-impl <T: Digest + Default, P: Pairing> Script<T, P> {
-    pub fn new() -> Self {
-        Self {
-            data: None,
-            generated: false,
+impl <T: Digest + Default, P: Pairing, E: ExpandMessage> Script<T, P, E> {
+    // state = H(state || len(label) || label || len(data) || data)
+    fn absorb(&mut self, label: &[u8], data: &impl CanonicalSerialize) {
+        let mut hasher = T::default();
+        hasher.update(&self.state);
+        hasher.update((label.len() as u64).to_le_bytes());
+        hasher.update(label);
+        hasher.update((data.uncompressed_size() as u64).to_le_bytes());
+        data.serialize_uncompressed(HashMarshaller(&mut hasher))
+            .expect("HashMarshaller::serialize_uncompressed should be infallible!");
+        self.state = hasher.finalize().to_vec();
+    }
+}
+
+impl <T: Digest + Default, P: Pairing, E: ExpandMessage> Transcript<P> for Script<T, P, E> {
+    fn new(protocol_label: &[u8]) -> Self {
+        let mut script = Self {
+            state: vec![],
             _phantom_data_t: PhantomData,
             _phantom_data_p: PhantomData,
-        }
+            _phantom_data_e: PhantomData,
+        };
+        script.append_message(b"dom-sep", protocol_label);
+        script
     }
 
-    
-    pub fn feed_with_commitments(&mut self, commitments: &[impl CanonicalSerialize]) {
-        let mut hasher = T::default();
-        hasher.update(self.data.take().unwrap_or_default());
-        for commitment in commitments {
-            commitment.serialize_uncompressed(HashMarshaller(&mut hasher))
-                .expect("HashMarshaller::serialize_uncompressed should be infallible!");
-        }
-        self.data = Some(hasher.finalize().to_vec());
-        self.generated = false;
+    fn append_message(&mut self, label: &[u8], message: &[u8]) {
+        self.absorb(label, &message.to_vec());
     }
-    
-    pub fn feed_with_field_elements(&mut self, field_elements: &[P::ScalarField]) {
-        let mut hasher = T::default();
-        hasher.update(self.data.take().unwrap_or_default());
-        for field_element in field_elements {
-            field_element.serialize_uncompressed(HashMarshaller(&mut hasher))
-                .expect("HashMarshaller::serialize_uncompressed should be infallible!");
-        }
-        self.data = Some(hasher.finalize().to_vec());
-        self.generated = false;
+
+    fn append_u64(&mut self, label: &[u8], value: u64) {
+        self.absorb(label, &value);
     }
 
-    fn generate_rng_with_seed(&mut self) -> StdRng {
-        if self.generated {
-            panic!("I'm hungry! Feed me something first");
-        }
-        self.generated = true;
-        let seed = self
-            .data
-            .clone()
-            .map(|data| u64::from_le_bytes(data[..8].try_into().unwrap()))
-            .expect("No data to generate seed from");
-        StdRng::seed_from_u64(seed)
-    }
-
-    pub fn generate_challenges<const N: usize>(&mut self) -> [P::ScalarField; N] {
-        let mut rng = self.generate_rng_with_seed();
-        let mut points = [P::ScalarField::zero(); N];
-        for point in &mut points {
-            *point = P::ScalarField::rand(&mut rng);
-        }
-        points
+    fn append_point(&mut self, label: &[u8], point: &impl AffineRepr) {
+        self.absorb(label, point);
     }
 
+    fn append_scalar(&mut self, label: &[u8], scalar: &P::ScalarField) {
+        self.absorb(label, scalar);
+    }
 
+    // Derives a challenge from the whole transcript state with hash_to_field.
+    fn challenge_scalar(&mut self, label: &[u8]) -> P::ScalarField {
+        self.append_message(b"challenge", label);
+        let [challenge] = hash_to_field::<P::ScalarField, E>(&self.state, HASH_TO_FIELD_DST, 1)
+            .try_into()
+            .unwrap();
+        self.append_scalar(label, &challenge);
+        challenge
+    }
 }
 
 // This private struct works around Serialize taking the pre-existing
@@ -97,39 +204,44 @@ mod tests {
     use sha2::Sha256;
     use std::ops::Mul;
     use ark_bls12_381::{Bls12_381, Fr, G1Affine};
-    use crate::fiat_shamir::Script;
+    use sha2::Digest;
+    use sha2::digest::{ExtendableOutput, Update, XofReader};
+    use crate::fiat_shamir::{hash_to_field, ExpandMessage, ExpandMsgXmd, ExpandMsgXof, Script, Transcript, HASH_TO_FIELD_DST};
+
+    fn challenges(script: &mut Script<Sha256, Bls12_381>) -> [Fr; 3] {
+        [script.challenge_scalar(b"a"), script.challenge_scalar(b"aa"), script.challenge_scalar(b"aaa")]
+    }
 
     #[test]
     fn aggregation_digest_test_01() {
         let commitment1 =  G1Affine::generator().mul(Fr::from(1)).into_affine();
         let commitment2 =  G1Affine::generator().mul(Fr::from(2)).into_affine();
-      
-        let commitments= [commitment1.clone(), commitment2.clone()];
-        let mut script1 = Script::<Sha256, Bls12_381>::new();
-        let mut script2 = Script::<Sha256, Bls12_381>::new();
-        let mut script3 = Script::<Sha256, Bls12_381>::new();
-        let mut script4 = Script::<Sha256, Bls12_381>::new();
-        
-        
-        script1.feed_with_commitments(&commitments);
-        let [a, aa, aaa] = script1.generate_challenges();
-
-        let commitments1 = [commitment1.clone()];
-        let commitments2 = [commitment2.clone()];
-        script2.feed_with_commitments(&commitments2);
-        let [b] =  script2.generate_challenges();
-        println!("b: {:?}", b);
+
+        let mut script1 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script2 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script3 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script4 = Script::<Sha256, Bls12_381>::new(b"test");
+
+
+        script1.append_point(b"c1", &commitment1);
+        script1.append_point(b"c2", &commitment2);
+        let [a, aa, aaa] = challenges(&mut script1);
+
+        script2.append_point(b"c2", &commitment2);
+        let b = script2.challenge_scalar(b"a");
         assert_ne!(a, b, "should be different");
 
-        script3.feed_with_commitments(&commitments);
-        let [c, cc, ccc] = script3.generate_challenges();
+        script3.append_point(b"c1", &commitment1);
+        script3.append_point(b"c2", &commitment2);
+        let [c, cc, ccc] = challenges(&mut script3);
         assert_eq!(a, c, "should be equal");
         assert_eq!(aa, cc, "should be equal");
         assert_eq!(aaa, ccc, "should be equal");
-        
-        script4.feed_with_commitments(&commitments1);
-        script4.feed_with_commitments(&commitments2);
-        let [d, dd, ddd] = script4.generate_challenges();
+
+        // same points, different labels
+        script4.append_point(b"c2", &commitment1);
+        script4.append_point(b"c1", &commitment2);
+        let [d, dd, ddd] = challenges(&mut script4);
         assert_ne!(a, d, "should be different");
         assert_ne!(aa, dd, "should be different");
         assert_ne!(aaa, ddd, "should be different");
@@ -140,30 +252,31 @@ mod tests {
         let field1 = Fr::from(1);
         let field2 = Fr::from(2);
 
-        let commitments= [field1, field2];
-        let mut script1 = Script::<Sha256, Bls12_381>::new();
-        let mut script2 = Script::<Sha256, Bls12_381>::new();
-        let mut script3 = Script::<Sha256, Bls12_381>::new();
-        let mut script4 = Script::<Sha256, Bls12_381>::new();
+        let mut script1 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script2 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script3 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script4 = Script::<Sha256, Bls12_381>::new(b"other protocol");
 
 
-        script1.feed_with_commitments(&commitments);
-        let [a, aa, aaa] = script1.generate_challenges();
+        script1.append_scalar(b"f1", &field1);
+        script1.append_scalar(b"f2", &field2);
+        let [a, aa, aaa] = challenges(&mut script1);
 
-        script2.feed_with_commitments(&[field2]);
-        let [b] =  script2.generate_challenges();
-        println!("b: {:?}", b);
+        script2.append_scalar(b"f2", &field2);
+        let b = script2.challenge_scalar(b"a");
         assert_ne!(a, b, "should be different");
 
-        script3.feed_with_commitments(&commitments);
-        let [c, cc, ccc] = script3.generate_challenges();
+        script3.append_scalar(b"f1", &field1);
+        script3.append_scalar(b"f2", &field2);
+        let [c, cc, ccc] = challenges(&mut script3);
         assert_eq!(a, c, "should be equal");
         assert_eq!(aa, cc, "should be equal");
         assert_eq!(aaa, ccc, "should be equal");
 
-        script4.feed_with_commitments(&[field1]);
-        script4.feed_with_commitments(&[field2]);
-        let [d, dd, ddd] = script4.generate_challenges();
+        // same data, different domain separator
+        script4.append_scalar(b"f1", &field1);
+        script4.append_scalar(b"f2", &field2);
+        let [d, dd, ddd] = challenges(&mut script4);
         assert_ne!(a, d, "should be different");
         assert_ne!(aa, dd, "should be different");
         assert_ne!(aaa, ddd, "should be different");
@@ -171,12 +284,129 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn safe_guard() {
+    fn points_and_scalars_are_framed() {
+        // a message cannot be shifted between two appended values
+        let mut script1 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script2 = Script::<Sha256, Bls12_381>::new(b"test");
+        script1.append_message(b"m", b"ab");
+        script1.append_message(b"m", b"c");
+        script2.append_message(b"m", b"a");
+        script2.append_message(b"m", b"bc");
+        assert_ne!(script1.challenge_scalar(b"x"), script2.challenge_scalar(b"x"));
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // Test vectors from RFC 9380, appendix K.1
+    #[test]
+    fn expand_message_xmd_sha256_test_vectors() {
+        let dst = b"QUUX-V01-CS02-with-expander-SHA256-128";
+        let vectors = [
+            ("", "68a985b87eb6b46952128911f2a4412bbc302a9d759667f87f7a21d803f07235"),
+            ("abc", "d8ccab23b5985ccea865c6c97b6e5b8350e794e603b4b97902f53a8a0d605615"),
+            ("abcdef0123456789", "eff31487c770a893cfb36f912fbfcbff40d5661771ca4b2cb4eafe524333f5c1"),
+        ];
+        for (msg, expected) in vectors {
+            let uniform_bytes = ExpandMsgXmd::<Sha256>::expand_message(msg.as_bytes(), dst, 0x20);
+            assert_eq!(hex(&uniform_bytes), expected);
+        }
+    }
+
+    #[test]
+    fn expand_message_xmd_lengths() {
+        let dst = b"QUUX-V01-CS02-with-expander-SHA256-128";
+        let long = ExpandMsgXmd::<Sha256>::expand_message(b"abc", dst, 0x80);
+        assert_eq!(long.len(), 0x80);
+        let short = ExpandMsgXmd::<Sha256>::expand_message(b"abc", dst, 0x20);
+        // the length is bound into b_0, so a shorter output is not a prefix of a longer one
+        assert_ne!(short[..], long[..0x20]);
+    }
+    
+    // A toy extendable-output function over SHA-256 in counter mode, only used to exercise the XOF path.
+    #[derive(Default)]
+    struct CounterModeSha256 {
+        data: Vec<u8>,
+    }
+
+    struct CounterModeSha256Reader {
+        seed: Vec<u8>,
+        counter: u32,
+    }
+
+    impl Update for CounterModeSha256 {
+        fn update(&mut self, data: impl AsRef<[u8]>) {
+            self.data.extend_from_slice(data.as_ref());
+        }
+    }
+
+    impl ExtendableOutput for CounterModeSha256 {
+        type Reader = CounterModeSha256Reader;
+
+        fn finalize_xof(self) -> Self::Reader {
+            CounterModeSha256Reader { seed: Sha256::digest(&self.data).to_vec(), counter: 0 }
+        }
+
+        fn finalize_xof_reset(&mut self) -> Self::Reader {
+            std::mem::take(self).finalize_xof()
+        }
+    }
+
+    impl XofReader for CounterModeSha256Reader {
+        fn read(&mut self, buffer: &mut [u8]) {
+            for chunk in buffer.chunks_mut(32) {
+                let mut hasher = Sha256::default();
+                Digest::update(&mut hasher, &self.seed);
+                Digest::update(&mut hasher, self.counter.to_be_bytes());
+                self.counter += 1;
+                chunk.copy_from_slice(&hasher.finalize()[..chunk.len()]);
+            }
+        }
+    }
+
+    #[test]
+    fn hash_to_field_with_xof() {
+        let a = hash_to_field::<Fr, ExpandMsgXof<CounterModeSha256>>(b"abc", b"DST-1", 3);
+        let b = hash_to_field::<Fr, ExpandMsgXof<CounterModeSha256>>(b"abc", b"DST-1", 3);
+        let c = hash_to_field::<Fr, ExpandMsgXof<CounterModeSha256>>(b"abc", b"DST-2", 3);
+        assert_eq!(a.len(), 3);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a[0], a[1]);
+
+        let mut script = Script::<Sha256, Bls12_381, ExpandMsgXof<CounterModeSha256>>::new(b"test");
+        script.append_scalar(b"x", &Fr::from(1));
+        let x = script.challenge_scalar(b"x");
+        let mut script = Script::<Sha256, Bls12_381>::new(b"test");
+        script.append_scalar(b"x", &Fr::from(1));
+        assert_ne!(x, script.challenge_scalar(b"x"));
+    }
+
+    #[test]
+    fn challenges_use_the_whole_digest() {
+        // Two states that agree on their first 8 bytes must still produce different challenges.
+        let mut data_1 = vec![7u8; 32];
+        let mut data_2 = vec![7u8; 32];
+        data_1[31] = 1;
+        data_2[31] = 2;
+        let a = hash_to_field::<Fr, ExpandMsgXmd<Sha256>>(&data_1, HASH_TO_FIELD_DST, 1);
+        let b = hash_to_field::<Fr, ExpandMsgXmd<Sha256>>(&data_2, HASH_TO_FIELD_DST, 1);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn consecutive_challenges() {
         let commitment1 = G1Affine::generator().mul(Fr::from(1)).into_affine();
-        let mut script1 = Script::<Sha256, Bls12_381>::new();
-        script1.feed_with_commitments(&[commitment1]);
-        let [_a, _aa, _aaa] = script1.generate_challenges();
-        let [_a, _aa, _aaa] = script1.generate_challenges();
+        let mut script1 = Script::<Sha256, Bls12_381>::new(b"test");
+        let mut script2 = Script::<Sha256, Bls12_381>::new(b"test");
+        script1.append_point(b"c1", &commitment1);
+        script2.append_point(b"c1", &commitment1);
+        // each challenge is fed back, so repeating a label does not repeat the challenge
+        let a = script1.challenge_scalar(b"a");
+        let aa = script1.challenge_scalar(b"a");
+        assert_ne!(a, aa);
+        // and the label is bound to the challenge
+        assert_ne!(a, script2.challenge_scalar(b"b"));
     }
 }
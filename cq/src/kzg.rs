@@ -1,11 +1,16 @@
-use std::ops::{Add, Sub};
+use std::io::Read;
+use std::ops::Sub;
 use std::ops::Mul;
-use ark_ec::{pairing::Pairing, AffineRepr};
-use ark_ff::{UniformRand, One};
-use ark_poly::{DenseUVPolynomial, Polynomial};
+use ark_ec::{pairing::Pairing, AffineRepr, CurveGroup, ScalarMul, VariableBaseMSM};
+use ark_ff::{UniformRand, One, Zero};
+use ark_poly::{DenseUVPolynomial, EvaluationDomain, Polynomial};
 use ark_poly::univariate::DensePolynomial;
-use rand::thread_rng;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress, Validate};
+use rand::{thread_rng, Rng};
+use crate::errors::SrsError;
+use crate::feist_khovratovich_alg::compute_openings;
 
+#[derive(Debug, Clone, CanonicalSerialize, CanonicalDeserialize)]
 pub struct Kzg<P: Pairing> {
     pub g1_srs: Vec<P::G1Affine>,
     pub g2_srs: Vec<P::G2Affine>,
@@ -13,78 +18,136 @@ pub struct Kzg<P: Pairing> {
 
 impl <P: Pairing> Kzg<P> {
 
-    // Generate SRS
+    // Generate SRS with len powers in G1 and len + 1 in G2, as needed by Cq::new_with_srs
     pub fn new(len: usize) -> Self {
+        Self::new_with_lengths(len, len + 1)
+    }
+
+    pub fn new_with_lengths(g1_len: usize, g2_len: usize) -> Self {
         let s = P::ScalarField::rand(&mut thread_rng());
-        Self::new_from_secret(s, len)
+        Self::new_from_secret_with_lengths(s, g1_len, g2_len)
     }
     
     pub fn new_from_secret(s: P::ScalarField, len: usize) -> Self {
-        let g1_gen = P::G1Affine::generator();
-        let g2_gen = P::G2Affine::generator();
-
-        let mut g1_srs = Vec::new();
-        let mut g2_srs = Vec::new();
+        Self::new_from_secret_with_lengths(s, len, len + 1)
+    }
 
+    // The powers [s^i]_1 and [s^i]_2 with windowed fixed-base multiplications of the generators,
+    // normalized to affine in one batch. The multiplications run in parallel with the
+    // parallel feature.
+    pub fn new_from_secret_with_lengths(s: P::ScalarField, g1_len: usize, g2_len: usize) -> Self {
+        let mut s_pows = Vec::with_capacity(g1_len.max(g2_len));
         let mut s_pow = P::ScalarField::one();
-        for _ in 0..len {
-            let g1_point = g1_gen.mul(s_pow);
-            let g2_point = g2_gen.mul(s_pow);
-            g1_srs.push(g1_point.into());
-            g2_srs.push(g2_point.into());
-            s_pow = s_pow * s;
+        for _ in 0..g1_len.max(g2_len) {
+            s_pows.push(s_pow);
+            s_pow *= s;
         }
-        g2_srs.push(g2_gen.mul(s_pow).into());
 
+        let g1_gen = P::G1::from(P::G1Affine::generator());
+        let g2_gen = P::G2::from(P::G2Affine::generator());
         Self {
-            g1_srs,
-            g2_srs
+            g1_srs: g1_gen.batch_mul(&s_pows[..g1_len]),
+            g2_srs: g2_gen.batch_mul(&s_pows[..g2_len]),
+        }
+    }
+
+    // The highest power of x in G1.
+    pub fn max_degree(&self) -> usize {
+        self.g1_srs.len() - 1
+    }
+
+    // Load an SRS serialized with CanonicalSerialize, keeping the first g1_len and g2_len powers.
+    // The points are validated and the powers are checked to be consistent.
+    pub fn from_arkworks_reader<R: Read>(reader: R, compress: Compress, g1_len: usize, g2_len: usize) -> Result<Self, SrsError> {
+        let kzg = Self::deserialize_with_mode(reader, compress, Validate::Yes)?;
+        let kzg = kzg.truncate(g1_len, g2_len)?;
+        kzg.check_powers(&mut thread_rng())?;
+        Ok(kzg)
+    }
+
+    // Keep only the first g1_len powers in G1 and g2_len powers in G2.
+    pub fn truncate(&self, g1_len: usize, g2_len: usize) -> Result<Self, SrsError> {
+        if self.g1_srs.len() < g1_len {
+            return Err(SrsError::TooShort { group: "G1", needed: g1_len, available: self.g1_srs.len() });
         }
+        if self.g2_srs.len() < g2_len {
+            return Err(SrsError::TooShort { group: "G2", needed: g2_len, available: self.g2_srs.len() });
+        }
+        Ok(Self {
+            g1_srs: self.g1_srs[..g1_len].to_vec(),
+            g2_srs: self.g2_srs[..g2_len].to_vec(),
+        })
+    }
+
+    // Check that g1_srs and g2_srs are powers of one secret x with a single batched pairing check:
+    // for random r_i, s_j
+    // e(sum r_i·[x^{i+1}]_1, [1]_2) · e(-sum r_i·[x^i]_1, [x]_2) · e([1]_1, sum s_j·[x^{j+1}]_2) · e(-[x]_1, sum s_j·[x^j]_2) = 1
+    pub fn check_powers<R: Rng>(&self, rng: &mut R) -> Result<(), SrsError> {
+        if self.g1_srs.len() < 2 || self.g2_srs.len() < 2 {
+            return Err(SrsError::TooShort { group: "G1 and G2", needed: 2, available: self.g1_srs.len().min(self.g2_srs.len()) });
+        }
+        if self.g1_srs[0].is_zero() || self.g1_srs[1].is_zero() || self.g2_srs[0].is_zero() {
+            return Err(SrsError::InconsistentPowers);
+        }
+
+        let n1 = self.g1_srs.len() - 1;
+        let r: Vec<P::ScalarField> = (0..n1).map(|_| P::ScalarField::rand(rng)).collect();
+        let cm1_lo = P::G1::msm_unchecked(&self.g1_srs[..n1], &r);
+        let cm1_hi = P::G1::msm_unchecked(&self.g1_srs[1..], &r);
+
+        let n2 = self.g2_srs.len() - 1;
+        let s: Vec<P::ScalarField> = (0..n2).map(|_| P::ScalarField::rand(rng)).collect();
+        let cm2_lo = P::G2::msm_unchecked(&self.g2_srs[..n2], &s);
+        let cm2_hi = P::G2::msm_unchecked(&self.g2_srs[1..], &s);
+
+        let res = P::multi_pairing(
+            [cm1_hi.into_affine(), (-cm1_lo).into_affine(), self.g1_srs[0], (-self.g1_srs[1].into_group()).into_affine()],
+            [self.g2_srs[0], self.g2_srs[1], cm2_hi.into_affine(), cm2_lo.into_affine()],
+        );
+        if !res.is_zero() {
+            return Err(SrsError::InconsistentPowers);
+        }
+        Ok(())
     }
     
     
-    // Commit a polynomial with SRS in G1
+    // Commit a polynomial with SRS in G1, with one multi-scalar multiplication (Pippenger)
     pub fn commit_g1(&self, dense_polynomial: &DensePolynomial<P::ScalarField>) -> P::G1Affine {
-        assert!(self.g1_srs.len() > dense_polynomial.degree());
-        let poly_coeffs = dense_polynomial.coeffs.iter();
-        let g1_points = self.g1_srs.iter();
-        let res = poly_coeffs.zip(g1_points).map(|(coeff, point)| {
-            point.mul(coeff).into()
-        }).reduce(|acc, point| acc.add(point).into()).unwrap_or(P::G1Affine::zero());
-        res
+        self.fast_commit_g1(dense_polynomial, 0)
     }
     
+    // Commit x^index·f(x), i.e. the coefficients of f with the powers of x starting at index
     pub fn fast_commit_g1(&self, dense_polynomial: &DensePolynomial<P::ScalarField>, index: usize) -> P::G1Affine {
         assert!(self.g1_srs.len() > dense_polynomial.degree() + index);
-        let poly_coeffs = dense_polynomial.coeffs.iter();
-        let g1_points = self.g1_srs.iter().skip(index);
-        let res = poly_coeffs.zip(g1_points).map(|(coeff, point)| {
-            point.mul(coeff).into()
-        }).reduce(|acc, point| acc.add(point).into()).unwrap_or(P::G1Affine::zero());
-        res
+        let coeffs = &dense_polynomial.coeffs;
+        P::G1::msm_unchecked(&self.g1_srs[index..index + coeffs.len()], coeffs).into_affine()
     }
 
     // Commit a polynomial with SRS in G2
     pub fn commit_g2(&self, dense_polynomial: &DensePolynomial<P::ScalarField>) -> P::G2Affine {
         assert!(self.g2_srs.len() > dense_polynomial.degree());
-        let poly_coeffs = dense_polynomial.coeffs.iter();
-        let g2_points = self.g2_srs.iter();
-        let res = poly_coeffs.zip(g2_points).map(|(coeff, point)| {
-            point.mul(coeff).into()
-        }).reduce(|acc, point| acc.add(point).into()).unwrap_or(P::G2Affine::zero());
-        res
+        let coeffs = &dense_polynomial.coeffs;
+        P::G2::msm_unchecked(&self.g2_srs[..coeffs.len()], coeffs).into_affine()
     }
 
     // Open a polynomial at a point z
     pub fn open_g1(&self, f_x: &DensePolynomial<P::ScalarField>, z: P::ScalarField) -> (P::G1Affine, P::ScalarField) {
         let f_z = f_x.evaluate(&z);
-        let f_z_poly = DensePolynomial::from_coefficients_slice(&vec![f_z]);
-        let x_minus_z_poly = DensePolynomial::from_coefficients_slice(&vec![-z, 1.into()]);
+        let f_z_poly = DensePolynomial::from_coefficients_slice(&[f_z]);
+        let x_minus_z_poly = DensePolynomial::from_coefficients_slice(&[-z, 1.into()]);
         let f_q = (f_x - f_z_poly) / x_minus_z_poly;
         let opening = self.commit_g1(&f_q);
         (opening, f_z)
     }
 
+    // Open a polynomial at every point of the domain, a subgroup or a coset, with FK in
+    // O(n log n) group operations rather than n commitments. Returns the proofs of open_g1
+    // in the order of domain.elements().
+    pub fn open_all_on_domain<D: EvaluationDomain<P::ScalarField>>(&self, f_x: &DensePolynomial<P::ScalarField>, domain: &D) -> Vec<P::G1Affine> {
+        assert!(self.g1_srs.len() >= f_x.degree());
+        P::G1::normalize_batch(&compute_openings::<P, D>(&self.g1_srs, f_x, domain))
+    }
+
     // Verify the opening
     pub fn verify(&self, commitment: P::G1Affine, opening: P::G1Affine, z: P::ScalarField, f_z: P::ScalarField) -> bool {
         let x_minus_z_g2 = self.g2_srs[1].sub(self.g2_srs[0].mul(z).into()).into();
@@ -100,11 +163,15 @@ impl <P: Pairing> Kzg<P> {
 
 #[cfg(test)]
 mod tests {
-    use ark_poly::DenseUVPolynomial;
+    use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain, Polynomial};
     use ark_poly::univariate::DensePolynomial;
     use crate::kzg::Kzg;
-    use ark_bls12_381::{Fr, Bls12_381};
+    use ark_bls12_381::{Fr, Bls12_381, G1Affine, G2Affine};
+    use ark_ec::{AffineRepr, CurveGroup};
     use ark_ff::One;
+    use ark_serialize::{CanonicalSerialize, Compress};
+    use rand::thread_rng;
+    use crate::errors::SrsError;
 
     #[test]
     pub fn test_kzg_commitment() {
@@ -116,4 +183,78 @@ mod tests {
 
         assert!(kzg.verify(commitment, opening, challenge, f_z));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_open_all_on_domain() {
+        let mut rng = thread_rng();
+        for (num_coeffs, n) in [(1, 4), (2, 4), (3, 8), (8, 8), (9, 8), (20, 4)] {
+            let poly = DensePolynomial::<Fr>::rand(num_coeffs - 1, &mut rng);
+            // exactly the deg(f) powers the quotients need
+            let kzg = Kzg::<Bls12_381>::new(poly.degree().max(1));
+            let subgroup = GeneralEvaluationDomain::<Fr>::new(n).unwrap();
+            let coset = GeneralEvaluationDomain::<Fr>::new_coset(n, Fr::from(7)).unwrap();
+            for domain in [subgroup, coset] {
+                let openings = kzg.open_all_on_domain(&poly, &domain);
+                let expected: Vec<_> = domain.elements().map(|z| kzg.open_g1(&poly, z).0).collect();
+                assert_eq!(openings, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_from_secret() {
+        let s = Fr::from(42);
+        let kzg = Kzg::<Bls12_381>::new_from_secret(s, 40);
+        assert_eq!((kzg.g1_srs.len(), kzg.g2_srs.len()), (40, 41));
+        let mut s_pow = Fr::one();
+        for i in 0..41 {
+            if i < 40 {
+                assert_eq!(kzg.g1_srs[i], (G1Affine::generator() * s_pow).into_affine());
+            }
+            assert_eq!(kzg.g2_srs[i], (G2Affine::generator() * s_pow).into_affine());
+            s_pow *= s;
+        }
+
+        // a ceremony-style SRS with few powers in G2, and the other way around
+        let short_g2 = Kzg::<Bls12_381>::new_from_secret_with_lengths(s, 40, 2);
+        assert_eq!(short_g2.g1_srs, kzg.g1_srs);
+        assert_eq!(short_g2.g2_srs, kzg.g2_srs[..2]);
+        let short_g1 = Kzg::<Bls12_381>::new_from_secret_with_lengths(s, 3, 41);
+        assert_eq!(short_g1.g1_srs, kzg.g1_srs[..3]);
+        assert_eq!(short_g1.g2_srs, kzg.g2_srs);
+        assert!(Kzg::<Bls12_381>::new_with_lengths(64, 2).check_powers(&mut thread_rng()).is_ok());
+    }
+
+    #[test]
+    fn test_check_powers() {
+        let kzg = Kzg::<Bls12_381>::new_from_secret(Fr::from(42), 8);
+        assert!(kzg.check_powers(&mut thread_rng()).is_ok());
+
+        let mut bad_g1 = kzg.clone();
+        bad_g1.g1_srs[5] = kzg.g1_srs[6];
+        assert!(matches!(bad_g1.check_powers(&mut thread_rng()), Err(SrsError::InconsistentPowers)));
+
+        let mut bad_g2 = kzg.clone();
+        bad_g2.g2_srs[8] = kzg.g2_srs[7];
+        assert!(matches!(bad_g2.check_powers(&mut thread_rng()), Err(SrsError::InconsistentPowers)));
+
+        // G1 and G2 powers of different secrets
+        let other = Kzg::<Bls12_381>::new_from_secret(Fr::from(43), 8);
+        let mixed = Kzg::<Bls12_381> { g1_srs: kzg.g1_srs.clone(), g2_srs: other.g2_srs.clone() };
+        assert!(matches!(mixed.check_powers(&mut thread_rng()), Err(SrsError::InconsistentPowers)));
+    }
+
+    #[test]
+    fn test_from_arkworks_reader() {
+        let kzg = Kzg::<Bls12_381>::new(16);
+        let mut bytes = Vec::new();
+        kzg.serialize_compressed(&mut bytes).unwrap();
+
+        let loaded = Kzg::<Bls12_381>::from_arkworks_reader(bytes.as_slice(), Compress::Yes, 8, 9).unwrap();
+        assert_eq!(loaded.g1_srs, kzg.g1_srs[..8]);
+        assert_eq!(loaded.g2_srs, kzg.g2_srs[..9]);
+
+        let too_long = Kzg::<Bls12_381>::from_arkworks_reader(bytes.as_slice(), Compress::Yes, 32, 33);
+        assert!(matches!(too_long, Err(SrsError::TooShort { needed: 32, available: 16, .. })));
+    }
+}
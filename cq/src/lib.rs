@@ -1,11 +1,21 @@
-mod kzg;
+pub mod kzg;
+mod ptau;
+mod persist;
+pub mod chunked;
 mod feist_khovratovich_alg;
 mod pre_compute;
 pub mod cq;
 pub mod prover;
-mod fiat_shamir;
-mod errors;
+pub mod fiat_shamir;
+pub mod errors;
 pub mod verifier;
+pub mod poseidon;
+pub mod ceremony;
+pub mod multi_column;
+pub mod aggregate;
+pub mod multi_table;
+pub mod accumulator;
+pub mod zk;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -0,0 +1,482 @@
+// Multi-column (vector) lookups: prove that every row (f_1[i], .., f_k[i]) of the witness columns
+// is a row of a subset of the columns of a table.
+//
+// Each table column j is preprocessed separately (its cached quotients and [T_j(x)]_2).
+// For a lookup into the columns c_1..c_k the prover commits to the witness columns, the verifier
+// replies with a challenge zeta and the lookup runs as a single-column cq over
+// t = Σ zeta^(j-1)·t_{c_j} and f = Σ zeta^(j-1)·f_j. The cached quotients, the Lagrange
+// commitments and [T(x)]_2 of the compressed table are linear combinations of the preprocessed ones,
+// so the same preprocessing serves any subset of the columns.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Mul;
+use ark_ec::CurveGroup;
+use ark_ec::pairing::Pairing;
+use ark_ff::Field;
+use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain};
+use ark_poly::univariate::DensePolynomial;
+use sha2::Sha256;
+use crate::cq::{pad_table, Cq, PreparedVerifyingKey, VerifyingKey};
+use crate::errors::{GeneralError, VerificationError};
+use crate::fiat_shamir::{Script, Transcript};
+use crate::kzg::Kzg;
+use crate::pre_compute::{compute_quotient_lagrange_basic_commitments, fast_lagrange_basis_commitments_computation};
+use crate::prover::{LookupTable, Proof, Prover};
+use crate::verifier::{powers, Verifier};
+
+pub const MULTI_COLUMN_PROTOCOL_LABEL: &[u8] = b"cq-multi-column-lookup-v1";
+
+// Preprocessing of a table with several columns of the same size N.
+pub struct MultiColumnCq<'a, P: Pairing> {
+    pub pk: MultiColumnProvingKey<'a, P>,
+    pub vk: MultiColumnVerifyingKey<P>,
+}
+
+pub struct MultiColumnProvingKey<'a, P: Pairing> {
+    pub kzg: Cow<'a, Kzg<P>>,
+    pub big_n: usize,
+    pub t_columns: Vec<Vec<P::ScalarField>>,
+    // [T_j(x)]_2 for each column
+    pub t_x_2: Vec<P::G2Affine>,
+    // cached quotients for each column
+    pub cm1_qi: Vec<Vec<P::G1Affine>>,
+    // the commitments below only depend on N and are shared by the columns
+    pub cm1_li: Vec<P::G1Affine>,
+    pub cm1_l_i_quotient: Vec<P::G1Affine>,
+    pub cm1_l_i_quotient_shifted: Vec<P::G1Affine>,
+}
+
+// The verifying key of the first column, and [T_j(x)]_2 for every column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiColumnVerifyingKey<P: Pairing> {
+    pub vk: VerifyingKey<P>,
+    pub t_x_2: Vec<P::G2Affine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PreparedMultiColumnVerifyingKey<P: Pairing> {
+    pub pvk: PreparedVerifyingKey<P>,
+    pub t_x_2: Vec<P::G2Affine>,
+}
+
+impl <P: Pairing> MultiColumnVerifyingKey<P> {
+    pub fn prepare(&self) -> PreparedMultiColumnVerifyingKey<P> {
+        PreparedMultiColumnVerifyingKey {
+            pvk: self.vk.prepare(),
+            t_x_2: self.t_x_2.clone(),
+        }
+    }
+}
+
+impl <P: Pairing> MultiColumnProvingKey<'_, P> {
+    pub fn verifying_key(&self) -> MultiColumnVerifyingKey<P> {
+        MultiColumnVerifyingKey {
+            vk: VerifyingKey::new(&self.kzg, self.big_n, self.t_x_2[0]),
+            t_x_2: self.t_x_2.clone(),
+        }
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.t_columns.len()
+    }
+
+    // Maps each row of the table projected on the columns to its first index.
+    fn row_hash_map(&self, columns: &[usize]) -> HashMap<Vec<P::ScalarField>, usize> {
+        let mut row_hash_map = HashMap::new();
+        for i in (0..self.big_n).rev() {
+            let row = columns.iter().map(|c| self.t_columns[*c][i]).collect();
+            row_hash_map.insert(row, i);
+        }
+        row_hash_map
+    }
+}
+
+impl <P: Pairing> MultiColumnCq<'static, P> {
+    pub fn new(t_columns: &[Vec<P::ScalarField>]) -> Result<Self, GeneralError> {
+        let t_columns = pad_columns(t_columns)?;
+        let big_n = t_columns[0].len();
+        Self::setup(&t_columns, Cow::Owned(Kzg::<P>::new(big_n)))
+    }
+}
+
+impl <'a, P: Pairing> MultiColumnCq<'a, P> {
+    // Preprocess the table under a borrowed SRS, see Cq::new_with_srs.
+    pub fn new_with_srs(t_columns: &[Vec<P::ScalarField>], srs: &'a Kzg<P>) -> Result<Self, GeneralError> {
+        let t_columns = pad_columns(t_columns)?;
+        Cq::<P>::check_srs(srs, t_columns[0].len())?;
+        Self::setup(&t_columns, Cow::Borrowed(srs))
+    }
+
+    fn setup(t_columns: &[Vec<P::ScalarField>], kzg: Cow<'a, Kzg<P>>) -> Result<Self, GeneralError> {
+        let big_n = t_columns[0].len();
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(big_n).unwrap();
+        let g1_srs = &kzg.g1_srs[..big_n];
+
+        let mut t_x_2 = vec![];
+        let mut cm1_qi = vec![];
+        for t_i in t_columns {
+            let t_x = DensePolynomial::from_coefficients_vec(domain.ifft(t_i));
+            t_x_2.push(kzg.commit_g2(&t_x));
+            cm1_qi.push(Cq::<P>::compute_cm1_qi(&domain, &t_x, g1_srs));
+        }
+
+        let cm1_li = fast_lagrange_basis_commitments_computation::<P>(g1_srs, big_n);
+        let cm1_l_i_quotient = compute_quotient_lagrange_basic_commitments::<P>(&cm1_li, g1_srs, big_n);
+        let cm1_l_i_quotient_shifted = Cq::<P>::compute_cm1_l_i_quotient_shifted(&kzg, &cm1_li, big_n);
+
+        let pk = MultiColumnProvingKey {
+            kzg,
+            big_n,
+            t_columns: t_columns.to_vec(),
+            t_x_2,
+            cm1_qi,
+            cm1_li,
+            cm1_l_i_quotient,
+            cm1_l_i_quotient_shifted,
+        };
+        let vk = pk.verifying_key();
+        Ok(Self { pk, vk })
+    }
+}
+
+// Returns the common size of the columns.
+fn check_columns<F>(columns: &[Vec<F>]) -> Result<usize, GeneralError> {
+    let len = columns.first().ok_or(GeneralError::InvalidColumns)?.len();
+    if columns.iter().any(|column| column.len() != len) {
+        return Err(GeneralError::ColumnSizesDiffer);
+    }
+    Ok(len)
+}
+
+// Pads the table by repeating its last row, see pad_table.
+fn pad_columns<F: Copy>(t_columns: &[Vec<F>]) -> Result<Vec<Vec<F>>, GeneralError> {
+    check_columns(t_columns)?;
+    t_columns.iter().map(|t_i| pad_table(t_i)).collect()
+}
+
+// The columns looked up must be distinct columns of the table.
+fn check_column_indices(columns: &[usize], num_columns: usize) -> bool {
+    !columns.is_empty()
+        && columns.iter().all(|c| *c < num_columns)
+        && columns.iter().enumerate().all(|(i, c)| !columns[..i].contains(c))
+}
+
+// Absorbs the columns looked up, the table and witness column commitments, and derives zeta.
+fn derive_zeta<P: Pairing, T: Transcript<P>>(
+    script: &mut T, big_n: usize, columns: &[usize], t_x_2: &[P::G2Affine], cm1_f: &[P::G1Affine],
+) -> P::ScalarField {
+    script.append_u64(b"N", big_n as u64);
+    script.append_u64(b"columns", columns.len() as u64);
+    for (c, cm1_f_j) in columns.iter().zip(cm1_f) {
+        script.append_u64(b"column", *c as u64);
+        script.append_point(b"[T_j(x)]_2", &t_x_2[*c]);
+        script.append_point(b"cm1_f_j", cm1_f_j);
+    }
+    script.challenge_scalar(b"zeta")
+}
+
+// The table columns compressed with the powers of zeta, evaluated lazily at the indices used.
+struct CompressedTable<'b, 'a, P: Pairing> {
+    pk: &'b MultiColumnProvingKey<'a, P>,
+    columns: &'b [usize],
+    zeta_powers: Vec<P::ScalarField>,
+    vk: VerifyingKey<P>,
+}
+
+impl <'b, 'a, P: Pairing> CompressedTable<'b, 'a, P> {
+    fn new(pk: &'b MultiColumnProvingKey<'a, P>, columns: &'b [usize], zeta: P::ScalarField) -> Self {
+        let zeta_powers = powers(P::ScalarField::ONE, zeta, columns.len());
+        let t_x_2 = columns.iter().zip(&zeta_powers)
+            .map(|(c, z)| pk.t_x_2[*c].mul(z))
+            .sum::<P::G2>();
+        let vk = VerifyingKey::new(&pk.kzg, pk.big_n, t_x_2.into_affine());
+        Self { pk, columns, zeta_powers, vk }
+    }
+}
+
+impl <P: Pairing> LookupTable<P> for CompressedTable<'_, '_, P> {
+    fn kzg(&self) -> &Kzg<P> {
+        &self.pk.kzg
+    }
+
+    fn big_n(&self) -> usize {
+        self.pk.big_n
+    }
+
+    fn verifying_key(&self) -> VerifyingKey<P> {
+        self.vk.clone()
+    }
+
+    fn t_i(&self, index: usize) -> P::ScalarField {
+        self.columns.iter().zip(&self.zeta_powers)
+            .map(|(c, z)| self.pk.t_columns[*c][index] * z)
+            .sum()
+    }
+
+    fn cm1_li(&self, index: usize) -> P::G1Affine {
+        self.pk.cm1_li[index]
+    }
+
+    fn cm1_qi(&self, index: usize) -> P::G1Affine {
+        self.columns.iter().zip(&self.zeta_powers)
+            .map(|(c, z)| self.pk.cm1_qi[*c][index].mul(z))
+            .sum::<P::G1>()
+            .into_affine()
+    }
+
+    fn cm1_l_i_quotient(&self, index: usize) -> P::G1Affine {
+        self.pk.cm1_l_i_quotient[index]
+    }
+
+    fn cm1_l_i_quotient_shifted(&self, index: usize) -> P::G1Affine {
+        self.pk.cm1_l_i_quotient_shifted[index]
+    }
+}
+
+// A proof that the witness rows are rows of the table projected on the columns looked up.
+#[derive(Debug, Clone)]
+pub struct MultiColumnProof<P: Pairing> {
+    // the commitment of each witness column
+    pub cm1_f: Vec<P::G1Affine>,
+    // the lookup of the compressed witness in the compressed table
+    pub proof: Proof<P>,
+}
+
+pub struct MultiColumnProver<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    f_columns: Vec<Vec<P::ScalarField>>,
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl <P: Pairing, T: Transcript<P>> MultiColumnProver<P, T> {
+    pub fn new(f_columns: Vec<Vec<P::ScalarField>>) -> Result<Self, GeneralError> {
+        if check_columns(&f_columns)? == 0 {
+            return Err(GeneralError::EmptyWitness);
+        }
+        Ok(Self {
+            f_columns,
+            _phantom_data: PhantomData,
+        })
+    }
+
+    // Prove that the witness columns are a lookup into the table columns `columns`, in this order.
+    pub fn prove(&self, pk: &MultiColumnProvingKey<P>, columns: &[usize]) -> Result<MultiColumnProof<P>, GeneralError> {
+        if columns.len() != self.f_columns.len() || !check_column_indices(columns, pk.num_columns()) {
+            return Err(GeneralError::InvalidColumns);
+        }
+        // the witness rows are padded with the first row of the table, see Prover::new
+        let small_n = self.f_columns[0].len().next_power_of_two().max(2);
+        if small_n > pk.kzg.max_degree() + 1 {
+            return Err(GeneralError::WitnessLargerThanSrs);
+        }
+        let f_columns: Vec<Vec<P::ScalarField>> = self.f_columns.iter().zip(columns)
+            .map(|(f_i, c)| {
+                let mut f_i = f_i.clone();
+                f_i.resize(small_n, pk.t_columns[*c][0]);
+                f_i
+            })
+            .collect();
+
+        // the multiplicities of the witness rows, at the first index of each row in the table
+        let row_hash_map = pk.row_hash_map(columns);
+        let mut m_i_map = HashMap::<usize, usize>::new();
+        for i in 0..small_n {
+            let row: Vec<P::ScalarField> = f_columns.iter().map(|f_i| f_i[i]).collect();
+            let index = row_hash_map.get(&row).ok_or(GeneralError::WitnessNotInTable)?;
+            *m_i_map.entry(*index).or_insert(0) += 1;
+        }
+
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(small_n).unwrap();
+        let cm1_f: Vec<P::G1Affine> = f_columns.iter()
+            .map(|f_i| pk.kzg.commit_g1(&DensePolynomial::from_coefficients_vec(domain.ifft(f_i))))
+            .collect();
+
+        let mut script = T::new(MULTI_COLUMN_PROTOCOL_LABEL);
+        let zeta = derive_zeta::<P, T>(&mut script, pk.big_n, columns, &pk.t_x_2, &cm1_f);
+
+        let table = CompressedTable::new(pk, columns, zeta);
+        let f_i: Vec<P::ScalarField> = (0..small_n)
+            .map(|i| f_columns.iter().zip(&table.zeta_powers).map(|(f_j, z)| f_j[i] * z).sum())
+            .collect();
+        let mut prover = Prover::<P, T>::with_transcript(f_i, script)?;
+        let proof = prover.prove_with_table(&table, m_i_map.into_iter().collect())?;
+
+        Ok(MultiColumnProof { cm1_f, proof })
+    }
+}
+
+// The transcript must match the one the proof was generated with.
+pub struct MultiColumnVerifier<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl <P: Pairing, T: Transcript<P>> MultiColumnVerifier<P, T> {
+    pub fn new() -> Self {
+        Self {
+            _phantom_data: PhantomData,
+        }
+    }
+
+    // Checks the compression of the witness columns and returns the verifier of the compressed
+    // lookup with the verifying key of the compressed table.
+    fn compress(&self, pvk: &PreparedMultiColumnVerifyingKey<P>, columns: &[usize], proof: &MultiColumnProof<P>)
+        -> Result<(Verifier<P, T>, PreparedVerifyingKey<P>), VerificationError> {
+        if columns.len() != proof.cm1_f.len() || !check_column_indices(columns, pvk.t_x_2.len()) {
+            return Err(VerificationError::InvalidColumns);
+        }
+
+        let mut script = T::new(MULTI_COLUMN_PROTOCOL_LABEL);
+        let zeta = derive_zeta::<P, T>(&mut script, pvk.pvk.vk.big_n, columns, &pvk.t_x_2, &proof.cm1_f);
+        let zeta_powers = powers(P::ScalarField::ONE, zeta, columns.len());
+
+        let cm1_f = proof.cm1_f.iter().zip(&zeta_powers)
+            .map(|(cm1_f_j, z)| cm1_f_j.mul(z))
+            .sum::<P::G1>();
+        if cm1_f.into_affine() != proof.proof.cm1_f {
+            return Err(VerificationError::CompressedWitnessMismatch);
+        }
+
+        let t_x_2 = columns.iter().zip(&zeta_powers)
+            .map(|(c, z)| pvk.t_x_2[*c].mul(z))
+            .sum::<P::G2>()
+            .into_affine();
+        let mut compressed_pvk = pvk.pvk.clone();
+        compressed_pvk.vk.t_x_2 = t_x_2;
+        compressed_pvk.t_x_2 = t_x_2.into();
+
+        Ok((Verifier::with_transcript(script), compressed_pvk))
+    }
+
+    pub fn verify(&mut self, pvk: &PreparedMultiColumnVerifyingKey<P>, columns: &[usize], proof: MultiColumnProof<P>) -> Result<(), VerificationError> {
+        let (mut verifier, compressed_pvk) = self.compress(pvk, columns, &proof)?;
+        verifier.verify(&compressed_pvk, proof.proof)
+    }
+
+    pub fn batched_verify(&mut self, pvk: &PreparedMultiColumnVerifyingKey<P>, columns: &[usize], proof: MultiColumnProof<P>) -> Result<(), VerificationError> {
+        let (mut verifier, compressed_pvk) = self.compress(pvk, columns, &proof)?;
+        verifier.batched_verify(&compressed_pvk, proof.proof)
+    }
+}
+
+impl <P: Pairing, T: Transcript<P>> Default for MultiColumnVerifier<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr};
+    use crate::errors::{GeneralError, VerificationError};
+    use crate::poseidon::PoseidonTranscript;
+    use super::*;
+
+    // The XOR table of 3-bit values: rows (a, b, a ^ b).
+    fn xor_table() -> Vec<Vec<Fr>> {
+        let mut columns = vec![vec![], vec![], vec![]];
+        for a in 0..8u64 {
+            for b in 0..8u64 {
+                columns[0].push(Fr::from(a));
+                columns[1].push(Fr::from(b));
+                columns[2].push(Fr::from(a ^ b));
+            }
+        }
+        columns
+    }
+
+    fn witness(rows: &[(u64, u64, u64)]) -> Vec<Vec<Fr>> {
+        vec![
+            rows.iter().map(|r| Fr::from(r.0)).collect(),
+            rows.iter().map(|r| Fr::from(r.1)).collect(),
+            rows.iter().map(|r| Fr::from(r.2)).collect(),
+        ]
+    }
+
+    #[test]
+    fn test_multi_column_lookup() {
+        let MultiColumnCq { pk, vk } = MultiColumnCq::<Bls12_381>::new(&xor_table()).unwrap();
+        let pvk = vk.prepare();
+
+        let f_columns = witness(&[(3, 5, 6), (7, 7, 0), (3, 5, 6), (1, 4, 5)]);
+        let proof = MultiColumnProver::<Bls12_381>::new(f_columns.clone()).unwrap().prove(&pk, &[0, 1, 2]).unwrap();
+        assert!(MultiColumnVerifier::<Bls12_381>::new().verify(&pvk, &[0, 1, 2], proof.clone()).is_ok());
+        assert!(MultiColumnVerifier::<Bls12_381>::new().batched_verify(&pvk, &[0, 1, 2], proof.clone()).is_ok());
+
+        // the proof is bound to the columns looked up
+        assert!(MultiColumnVerifier::<Bls12_381>::new().verify(&pvk, &[1, 0, 2], proof).is_err());
+
+        let proof = MultiColumnProver::<Bls12_381, PoseidonTranscript<Bls12_381>>::new(f_columns).unwrap().prove(&pk, &[0, 1, 2]).unwrap();
+        assert!(MultiColumnVerifier::<Bls12_381, PoseidonTranscript<Bls12_381>>::new().verify(&pvk, &[0, 1, 2], proof).is_ok());
+    }
+
+    #[test]
+    fn test_column_subsets() {
+        let MultiColumnCq { pk, vk } = MultiColumnCq::<Bls12_381>::new(&xor_table()).unwrap();
+        let pvk = vk.prepare();
+
+        // (a ^ b, a): the projected rows are unique, the values of column 0 alone repeat 8 times
+        let f_columns = vec![
+            vec![Fr::from(6), Fr::from(0), Fr::from(5), Fr::from(5)],
+            vec![Fr::from(3), Fr::from(7), Fr::from(1), Fr::from(4)],
+        ];
+        let proof = MultiColumnProver::<Bls12_381>::new(f_columns).unwrap().prove(&pk, &[2, 0]).unwrap();
+        assert!(MultiColumnVerifier::<Bls12_381>::new().verify(&pvk, &[2, 0], proof).is_ok());
+
+        let f_columns = vec![vec![Fr::from(7), Fr::from(7)]];
+        let proof = MultiColumnProver::<Bls12_381>::new(f_columns).unwrap().prove(&pk, &[1]).unwrap();
+        assert!(MultiColumnVerifier::<Bls12_381>::new().verify(&pvk, &[1], proof).is_ok());
+    }
+
+    #[test]
+    fn test_rows_not_in_table() {
+        let MultiColumnCq { pk, vk } = MultiColumnCq::<Bls12_381>::new(&xor_table()).unwrap();
+        let pvk = vk.prepare();
+
+        // every value is in its column, but (3, 5, 7) is not a row
+        let prover = MultiColumnProver::<Bls12_381>::new(witness(&[(3, 5, 7), (1, 1, 0)])).unwrap();
+        assert!(matches!(prover.prove(&pk, &[0, 1, 2]), Err(GeneralError::WitnessNotInTable)));
+
+        assert!(matches!(prover.prove(&pk, &[0, 1]), Err(GeneralError::InvalidColumns)));
+        assert!(matches!(prover.prove(&pk, &[0, 1, 3]), Err(GeneralError::InvalidColumns)));
+        assert!(matches!(prover.prove(&pk, &[0, 1, 1]), Err(GeneralError::InvalidColumns)));
+
+        // a proof for (a, b, a ^ b) with a witness column replaced after compression
+        let prover = MultiColumnProver::<Bls12_381>::new(witness(&[(3, 5, 6), (1, 1, 0)])).unwrap();
+        let mut proof = prover.prove(&pk, &[0, 1, 2]).unwrap();
+        proof.cm1_f[2] = proof.cm1_f[1];
+        let result = MultiColumnVerifier::<Bls12_381>::new().verify(&pvk, &[0, 1, 2], proof);
+        assert_eq!(result, Err(VerificationError::CompressedWitnessMismatch));
+    }
+
+    #[test]
+    fn test_padded_sizes() {
+        // three rows of (x, x^2), padded to four with the last row
+        let t_columns = vec![
+            vec![Fr::from(1), Fr::from(2), Fr::from(3)],
+            vec![Fr::from(1), Fr::from(4), Fr::from(9)],
+        ];
+        let MultiColumnCq { pk, vk } = MultiColumnCq::<Bls12_381>::new(&t_columns).unwrap();
+        assert_eq!(pk.big_n, 4);
+        let pvk = vk.prepare();
+
+        for f_columns in [
+            vec![vec![Fr::from(3)], vec![Fr::from(9)]],
+            vec![vec![Fr::from(2), Fr::from(3), Fr::from(2)], vec![Fr::from(4), Fr::from(9), Fr::from(4)]],
+        ] {
+            let proof = MultiColumnProver::<Bls12_381>::new(f_columns).unwrap().prove(&pk, &[0, 1]).unwrap();
+            assert!(MultiColumnVerifier::<Bls12_381>::new().verify(&pvk, &[0, 1], proof).is_ok());
+        }
+
+        let result = MultiColumnProver::<Bls12_381>::new(vec![vec![], vec![]]);
+        assert!(matches!(result, Err(GeneralError::EmptyWitness)));
+    }
+
+    #[test]
+    fn test_invalid_tables() {
+        let result = MultiColumnCq::<Bls12_381>::new(&[vec![Fr::from(1), Fr::from(2)], vec![Fr::from(1)]]);
+        assert!(matches!(result, Err(GeneralError::ColumnSizesDiffer)));
+        let result = MultiColumnCq::<Bls12_381>::new(&[]);
+        assert!(matches!(result, Err(GeneralError::InvalidColumns)));
+    }
+}
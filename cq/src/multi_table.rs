@@ -0,0 +1,227 @@
+// One proof for lookups into several tables preprocessed with the same SRS, e.g. a range table,
+// an XOR table and an S-box table.
+//
+// The cq proofs of the tables are generated one after the other on a shared transcript, so the
+// challenges of each proof depend on all the previous ones. The verifier replays the transcript,
+// draws one challenge mu and checks the 5 pairing equations of every proof, the equations of
+// proof i scaled by mu^{5i}..mu^{5i+4}, in a single multi-pairing. The terms paired with [1]_2
+// and [x]_2 are summed over the tables, so the multi-pairing has 4 pairs per table plus 2.
+
+use std::marker::PhantomData;
+use ark_ec::pairing::Pairing;
+use ark_ff::{Field, One};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use sha2::Sha256;
+use crate::cq::{PreparedVerifyingKey, ProvingKey};
+use crate::errors::{GeneralError, VerificationError};
+use crate::fiat_shamir::{Script, Transcript};
+use crate::kzg::Kzg;
+use crate::prover::{Proof, Prover};
+use crate::verifier::{combined_pairing_is_one, scaled_mu_powers, Verifier};
+
+pub const MULTI_TABLE_PROTOCOL_LABEL: &[u8] = b"cq-multi-table-lookup-v1";
+
+#[derive(Debug, Clone, CanonicalDeserialize, CanonicalSerialize)]
+pub struct MultiTableProof<P: Pairing> {
+    // one proof for each table, in the order of the tables
+    pub proofs: Vec<Proof<P>>,
+}
+
+impl <P: Pairing> MultiTableProof<P> {
+    pub fn num_tables(&self) -> usize {
+        self.proofs.len()
+    }
+}
+
+// [1]_1, [x]_1, [1]_2 and [x]_2 determine the secret and the generators of the SRS.
+fn same_srs<P: Pairing>(kzg: &Kzg<P>, other: &Kzg<P>) -> bool {
+    kzg.g1_srs.get(..2) == other.g1_srs.get(..2) && kzg.g2_srs.get(..2) == other.g2_srs.get(..2)
+}
+
+// The transcript defaults to SHA-256, as for a single proof.
+pub struct MultiTableProver<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    witnesses: Vec<Vec<P::ScalarField>>,
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl <P: Pairing, T: Transcript<P>> MultiTableProver<P, T> {
+    // One witness for each table, each of any size as in Prover::new.
+    pub fn new(witnesses: Vec<Vec<P::ScalarField>>) -> Result<Self, GeneralError> {
+        if witnesses.is_empty() || witnesses.iter().any(|f_i| f_i.is_empty()) {
+            return Err(GeneralError::EmptyWitness);
+        }
+        Ok(Self {
+            witnesses,
+            _phantom_data: PhantomData,
+        })
+    }
+
+    pub fn prove(&self, pks: &[&ProvingKey<P>]) -> Result<MultiTableProof<P>, GeneralError> {
+        if pks.len() != self.witnesses.len() {
+            return Err(GeneralError::TableCountMismatch);
+        }
+        if pks.iter().any(|pk| !same_srs(&pk.kzg, &pks[0].kzg)) {
+            return Err(GeneralError::SrsNotShared);
+        }
+
+        let mut script = T::new(MULTI_TABLE_PROTOCOL_LABEL);
+        script.append_u64(b"tables", pks.len() as u64);
+        let mut proofs = Vec::with_capacity(pks.len());
+        for (f_i, pk) in self.witnesses.iter().zip(pks) {
+            let mut prover = Prover::<P, T>::with_transcript(f_i.clone(), script)?;
+            let proof = prover.prove(pk)?;
+            script = prover.into_transcript();
+            Verifier::<P, T>::append_batch_commitments(&mut script, &proof);
+            proofs.push(proof);
+        }
+
+        Ok(MultiTableProof { proofs })
+    }
+}
+
+// The transcript must match the one the proof was generated with.
+pub struct MultiTableVerifier<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl <P: Pairing, T: Transcript<P>> MultiTableVerifier<P, T> {
+    pub fn new() -> Self {
+        Self {
+            _phantom_data: PhantomData,
+        }
+    }
+
+    // The verifying keys are in the order of the tables the proof was generated for.
+    // When the multi-pairing fails, the checks of each proof are run to report which one failed.
+    pub fn verify(&mut self, pvks: &[&PreparedVerifyingKey<P>], proof: MultiTableProof<P>) -> Result<(), VerificationError> {
+        if pvks.is_empty() || pvks.len() != proof.num_tables() {
+            return Err(VerificationError::TableCountMismatch);
+        }
+        Verifier::<P, T>::check_shared_srs(pvks)?;
+
+        let mut script = T::new(MULTI_TABLE_PROTOCOL_LABEL);
+        script.append_u64(b"tables", pvks.len() as u64);
+        let mut checks = Vec::with_capacity(pvks.len());
+        for (pvk, proof) in pvks.iter().zip(&proof.proofs) {
+            let cm2_x_pow = Verifier::<P, T>::check_sizes(pvk, proof.small_n)?;
+            let mut verifier = Verifier::<P, T>::with_transcript(script);
+            let values = verifier.compute_round_values(pvk, proof)?;
+            script = verifier.into_transcript();
+            Verifier::<P, T>::append_batch_commitments(&mut script, proof);
+            checks.push((cm2_x_pow, values));
+        }
+
+        let mu = script.challenge_scalar(b"mu");
+        let mu_5 = mu.pow([5]);
+        let mut scale = P::ScalarField::one();
+        let mut terms = Vec::with_capacity(pvks.len());
+        for (i, (proof, (_, values))) in proof.proofs.iter().zip(&checks).enumerate() {
+            let mu_powers = scaled_mu_powers(mu, scale);
+            terms.push((i, proof.small_n, Verifier::<P, T>::batched_pairing_terms(proof, values, &mu_powers)));
+            scale *= mu_5;
+        }
+
+        if !combined_pairing_is_one(pvks, terms) {
+            for ((pvk, proof), (cm2_x_pow, values)) in pvks.iter().zip(&proof.proofs).zip(&checks) {
+                Verifier::<P, T>::check_all(pvk, proof, cm2_x_pow, values)?;
+            }
+            return Err(VerificationError::BatchedCheckFailed);
+        }
+
+        Ok(())
+    }
+}
+
+impl <P: Pairing, T: Transcript<P>> Default for MultiTableVerifier<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr};
+    use crate::cq::Cq;
+    use crate::poseidon::PoseidonTranscript;
+    use super::*;
+
+    // The PRESENT S-box.
+    const SBOX: [u64; 16] = [0xC, 0x5, 0x6, 0xB, 0x9, 0x0, 0xA, 0xD, 0x3, 0xE, 0xF, 0x8, 0x4, 0x7, 0x1, 0x2];
+
+    // 3-bit range, 2-bit XOR encoded as a + 4b + 16(a ^ b), S-box encoded as x + 16·S(x).
+    fn tables() -> Vec<Vec<Fr>> {
+        let range = (0..8).map(Fr::from).collect();
+        let xor = (0..16u64).map(|i| Fr::from(i + 16 * ((i & 3) ^ (i >> 2)))).collect();
+        let sbox = (0..16u64).map(|x| Fr::from(x + 16 * SBOX[x as usize])).collect();
+        vec![range, xor, sbox]
+    }
+
+    fn witnesses() -> Vec<Vec<Fr>> {
+        let tables = tables();
+        vec![
+            vec![tables[0][7], tables[0][0], tables[0][3]],
+            (0..8).map(|i| tables[1][(5 * i) % 16]).collect(),
+            (0..16).map(|i| tables[2][15 - i]).collect(),
+        ]
+    }
+
+    #[test]
+    fn test_multi_table_proof() {
+        let kzg = Kzg::<Bls12_381>::new(32);
+        let cqs: Vec<Cq<Bls12_381>> = tables().iter().map(|t_i| Cq::new_with_srs(t_i, &kzg).unwrap()).collect();
+        let pks: Vec<&ProvingKey<Bls12_381>> = cqs.iter().map(|cq| &cq.pk).collect();
+        let pvks: Vec<PreparedVerifyingKey<Bls12_381>> = cqs.iter().map(|cq| cq.vk.prepare()).collect();
+        let pvks: Vec<&PreparedVerifyingKey<Bls12_381>> = pvks.iter().collect();
+
+        let proof = MultiTableProver::<Bls12_381>::new(witnesses()).unwrap().prove(&pks).unwrap();
+        assert_eq!(proof.num_tables(), 3);
+        assert!(MultiTableVerifier::<Bls12_381>::new().verify(&pvks, proof.clone()).is_ok());
+
+        // the tables in another order
+        let mut swapped = proof.clone();
+        swapped.proofs.swap(1, 2);
+        assert!(MultiTableVerifier::<Bls12_381>::new().verify(&pvks, swapped).is_err());
+        let reordered = [pvks[0], pvks[2], pvks[1]];
+        assert!(MultiTableVerifier::<Bls12_381>::new().verify(&reordered, proof.clone()).is_err());
+
+        let mut tampered = proof.clone();
+        tampered.proofs[2].cm1_m = proof.proofs[1].cm1_m;
+        assert!(MultiTableVerifier::<Bls12_381>::new().verify(&pvks, tampered).is_err());
+
+        let mut dropped = proof;
+        dropped.proofs.pop();
+        assert_eq!(MultiTableVerifier::<Bls12_381>::new().verify(&pvks, dropped), Err(VerificationError::TableCountMismatch));
+
+        let proof = MultiTableProver::<Bls12_381, PoseidonTranscript<Bls12_381>>::new(witnesses()).unwrap().prove(&pks).unwrap();
+        assert!(MultiTableVerifier::<Bls12_381, PoseidonTranscript<Bls12_381>>::new().verify(&pvks, proof).is_ok());
+    }
+
+    #[test]
+    fn test_multi_table_errors() {
+        let kzg = Kzg::<Bls12_381>::new(32);
+        let tables = tables();
+        let cqs: Vec<Cq<Bls12_381>> = tables.iter().map(|t_i| Cq::new_with_srs(t_i, &kzg).unwrap()).collect();
+        let pks: Vec<&ProvingKey<Bls12_381>> = cqs.iter().map(|cq| &cq.pk).collect();
+
+        // an S-box entry looked up in the XOR table
+        let mut wrong = witnesses();
+        wrong[1][0] = tables[2][1];
+        let result = MultiTableProver::<Bls12_381>::new(wrong).unwrap().prove(&pks);
+        assert!(matches!(result, Err(GeneralError::WitnessNotInTable)));
+
+        let result = MultiTableProver::<Bls12_381>::new(witnesses()).unwrap().prove(&pks[..2]);
+        assert!(matches!(result, Err(GeneralError::TableCountMismatch)));
+        assert!(matches!(MultiTableProver::<Bls12_381>::new(vec![]), Err(GeneralError::EmptyWitness)));
+        assert!(matches!(MultiTableProver::<Bls12_381>::new(vec![vec![Fr::from(1)], vec![]]), Err(GeneralError::EmptyWitness)));
+
+        // a table with its own SRS
+        let other = Cq::<Bls12_381>::new(&tables[0]).unwrap();
+        let result = MultiTableProver::<Bls12_381>::new(witnesses()).unwrap().prove(&[&other.pk, pks[1], pks[2]]);
+        assert!(matches!(result, Err(GeneralError::SrsNotShared)));
+
+        let proof = MultiTableProver::<Bls12_381>::new(witnesses()).unwrap().prove(&pks).unwrap();
+        let pvks: Vec<PreparedVerifyingKey<Bls12_381>> = [&other.vk, &cqs[1].vk, &cqs[2].vk].into_iter().map(|vk| vk.prepare()).collect();
+        let pvks: Vec<&PreparedVerifyingKey<Bls12_381>> = pvks.iter().collect();
+        assert_eq!(MultiTableVerifier::<Bls12_381>::new().verify(&pvks, proof), Err(VerificationError::SrsNotShared));
+    }
+}
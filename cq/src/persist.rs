@@ -0,0 +1,265 @@
+// Persisting the cq preprocessing, so the cached quotients of large tables are computed once.
+//
+// File layout:
+//   "CQPK" | version: u32 LE | curve id: 32 bytes | SRS digest: 32 bytes
+//   | proving key without the SRS, uncompressed CanonicalSerialize
+//   | digest of everything before: 32 bytes
+// Digests are SHA-256. The SRS is not written: the tables preprocessed under one universal SRS share
+// it, and loading takes the trusted SRS and rejects files written under another one. The file digest
+// detects corruption, it does not authenticate the file: load files only from a trusted location,
+// or with Validate::Yes to also check every point.
+// Loading streams from any reader, so a memory-mapped file can be passed as a byte slice.
+
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use ark_ec::AffineRepr;
+use ark_ec::pairing::Pairing;
+use ark_ff::{BigInteger, PrimeField};
+use ark_serialize::{CanonicalSerialize, Compress, Validate};
+use sha2::{Digest, Sha256};
+use crate::cq::{pad_table, Cq, ProvingKey};
+use crate::errors::PersistError;
+use crate::kzg::Kzg;
+
+const MAGIC: &[u8; 4] = b"CQPK";
+const FILE_VERSION: u32 = 2;
+
+impl <P: Pairing> Cq<'_, P> {
+    // The SRS is only identified by its digest, Cq::load needs the same one.
+    pub fn save<W: Write>(&self, writer: W) -> Result<(), PersistError> {
+        let pk = &self.pk;
+        let mut writer = HashingWriter::new(writer);
+        write_header(&mut writer, &pk.kzg)?;
+        pk.serialize_without_srs(&mut writer, Compress::No)?;
+        finish(writer)
+    }
+}
+
+pub(crate) fn write_header<P: Pairing, W: Write>(writer: &mut W, kzg: &Kzg<P>) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FILE_VERSION.to_le_bytes())?;
+    writer.write_all(&curve_id::<P>())?;
+    writer.write_all(&digest(kzg))?;
+    Ok(())
+}
+
+// Appends the digest of everything written before.
+pub(crate) fn finish<W: Write>(writer: HashingWriter<W>) -> Result<(), PersistError> {
+    let (mut writer, file_digest) = writer.finalize();
+    writer.write_all(&file_digest)?;
+    Ok(())
+}
+
+impl <'a, P: Pairing> Cq<'a, P> {
+    // Load preprocessing written by Cq::save under srs without recomputing it.
+    // srs is trusted, Validate::Yes only checks the points read from the file.
+    pub fn load<R: Read>(reader: R, srs: &'a Kzg<P>, validate: Validate) -> Result<Self, PersistError> {
+        let mut reader = HashingReader::new(reader);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(PersistError::BadMagic);
+        }
+        let version = read_u32(&mut reader)?;
+        if version != FILE_VERSION {
+            return Err(PersistError::UnsupportedVersion(version));
+        }
+        let mut curve = [0u8; 32];
+        reader.read_exact(&mut curve)?;
+        if curve != curve_id::<P>() {
+            return Err(PersistError::CurveMismatch);
+        }
+        let mut srs_digest = [0u8; 32];
+        reader.read_exact(&mut srs_digest)?;
+        if srs_digest != digest(srs) {
+            return Err(PersistError::SrsMismatch);
+        }
+
+        let pk = ProvingKey::<P>::deserialize_with_srs(&mut reader, Cow::Borrowed(srs), Compress::No, validate)?;
+
+        let (mut reader, file_digest) = reader.finalize();
+        let mut expected_digest = [0u8; 32];
+        reader.read_exact(&mut expected_digest)?;
+        if file_digest != expected_digest {
+            return Err(PersistError::DigestMismatch);
+        }
+
+        let vk = pk.verifying_key();
+        Ok(Self { pk, vk })
+    }
+
+    // Load preprocessing and check that it was computed for the table t_i under srs.
+    pub fn load_for_table<R: Read>(reader: R, t_i: &[P::ScalarField], srs: &'a Kzg<P>, validate: Validate) -> Result<Self, PersistError> {
+        let cq = Self::load(reader, srs, validate)?;
+        if pad_table(t_i).ok().as_ref() != Some(&cq.pk.t_i) {
+            return Err(PersistError::TableMismatch);
+        }
+        Ok(cq)
+    }
+}
+
+// Identifies the curve by its generators and scalar field.
+fn curve_id<P: Pairing>() -> [u8; 32] {
+    let mut writer = HashingWriter::new(io::sink());
+    P::G1Affine::generator().serialize_uncompressed(&mut writer).unwrap();
+    P::G2Affine::generator().serialize_uncompressed(&mut writer).unwrap();
+    writer.write_all(&P::ScalarField::MODULUS.to_bytes_le()).unwrap();
+    writer.finalize().1
+}
+
+fn digest<T: CanonicalSerialize + ?Sized>(value: &T) -> [u8; 32] {
+    let mut writer = HashingWriter::new(io::sink());
+    value.serialize_uncompressed(&mut writer).unwrap();
+    writer.finalize().1
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl <W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, hasher: Sha256::new() }
+    }
+
+    fn finalize(self) -> (W, [u8; 32]) {
+        (self.inner, self.hasher.finalize().into())
+    }
+}
+
+impl <W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl <R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, hasher: Sha256::new() }
+    }
+
+    fn finalize(self) -> (R, [u8; 32]) {
+        (self.inner, self.hasher.finalize().into())
+    }
+}
+
+impl <R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr};
+    use ark_serialize::{CanonicalSerialize, Compress, Validate};
+    use crate::cq::Cq;
+    use crate::errors::PersistError;
+    use crate::kzg::Kzg;
+    use crate::prover::Prover;
+    use crate::verifier::Verifier;
+
+    fn saved_table(srs: &Kzg<Bls12_381>) -> (Vec<Fr>, Vec<u8>) {
+        let t_i: Vec<Fr> = (0..16).map(|i| Fr::from(i * 7)).collect();
+        let mut bytes = Vec::new();
+        Cq::<Bls12_381>::new_with_srs(&t_i, srs).unwrap().save(&mut bytes).unwrap();
+        (t_i, bytes)
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let t_i: Vec<Fr> = (0..16).map(|i| Fr::from(i * 7)).collect();
+        let cq = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let mut bytes = Vec::new();
+        cq.save(&mut bytes).unwrap();
+
+        for validate in [Validate::Yes, Validate::No] {
+            let Cq { pk, vk } = Cq::<Bls12_381>::load_for_table(bytes.as_slice(), &t_i, &cq.pk.kzg, validate).unwrap();
+            assert_eq!(pk.cm1_qi, cq.pk.cm1_qi);
+            assert_eq!(pk.cm1_li, cq.pk.cm1_li);
+            assert_eq!(pk.cm1_l_i_quotient, cq.pk.cm1_l_i_quotient);
+            assert_eq!(pk.cm1_l_i_quotient_shifted, cq.pk.cm1_l_i_quotient_shifted);
+            assert_eq!(pk.t_hash_map, cq.pk.t_hash_map);
+            assert_eq!(vk, cq.vk);
+
+            let proof = Prover::<Bls12_381>::new(vec![Fr::from(14), Fr::from(0)]).unwrap().prove(&pk).unwrap();
+            assert!(Verifier::<Bls12_381>::new().verify(&vk.prepare(), proof).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_save_with_universal_srs() {
+        let srs = Kzg::<Bls12_381>::new(32);
+        let small_t_i: Vec<Fr> = (0..8).map(Fr::from).collect();
+        let small = Cq::<Bls12_381>::new_with_srs(&small_t_i, &srs).unwrap();
+        let (t_i, bytes) = saved_table(&srs);
+        let mut small_bytes = Vec::new();
+        small.save(&mut small_bytes).unwrap();
+        // the header, the proving key without the SRS and the file digest
+        let pk_size = small.pk.serialized_size(Compress::No) - srs.serialized_size(Compress::No);
+        assert_eq!(small_bytes.len(), 4 + 4 + 32 + 32 + pk_size + 32);
+
+        let loaded = Cq::<Bls12_381>::load_for_table(small_bytes.as_slice(), &small_t_i, &srs, Validate::No).unwrap();
+        assert!(std::ptr::eq(loaded.pk.kzg.as_ref(), &srs));
+        assert_eq!(loaded.vk, small.vk);
+        let loaded = Cq::<Bls12_381>::load_for_table(bytes.as_slice(), &t_i, &srs, Validate::Yes).unwrap();
+        assert_eq!(loaded.vk, Cq::<Bls12_381>::new_with_srs(&t_i, &srs).unwrap().vk);
+    }
+
+    #[test]
+    fn test_load_rejects_mismatching_files() {
+        let srs = Kzg::<Bls12_381>::new(16);
+        let (t_i, bytes) = saved_table(&srs);
+
+        let other_t_i: Vec<Fr> = (0..16).map(Fr::from).collect();
+        let result = Cq::<Bls12_381>::load_for_table(bytes.as_slice(), &other_t_i, &srs, Validate::No);
+        assert!(matches!(result, Err(PersistError::TableMismatch)));
+
+        // an SRS of the same size with another secret
+        let other_srs = Kzg::<Bls12_381>::new(16);
+        let result = Cq::<Bls12_381>::load_for_table(bytes.as_slice(), &t_i, &other_srs, Validate::No);
+        assert!(matches!(result, Err(PersistError::SrsMismatch)));
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(Cq::<Bls12_381>::load(bad_magic.as_slice(), &srs, Validate::No), Err(PersistError::BadMagic)));
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 3;
+        assert!(matches!(Cq::<Bls12_381>::load(bad_version.as_slice(), &srs, Validate::No), Err(PersistError::UnsupportedVersion(3))));
+
+        let bn254_srs = Kzg::<ark_bn254::Bn254>::new(16);
+        let result = Cq::<ark_bn254::Bn254>::load(bytes.as_slice(), &bn254_srs, Validate::No);
+        assert!(matches!(result, Err(PersistError::CurveMismatch)));
+
+        // a flipped bit in the cached quotients
+        let mut corrupted = bytes.clone();
+        let index = bytes.len() - 100;
+        corrupted[index] ^= 1;
+        let result = Cq::<Bls12_381>::load_for_table(corrupted.as_slice(), &t_i, &srs, Validate::No);
+        assert!(matches!(result, Err(PersistError::DigestMismatch)));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(Cq::<Bls12_381>::load(truncated, &srs, Validate::No), Err(PersistError::Io(_))));
+    }
+}
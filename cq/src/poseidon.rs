@@ -0,0 +1,396 @@
+// An algebraic Fiat-Shamir transcript hashing natively over the scalar field with Poseidon,
+// so the cq verifier can be expressed cheaply inside another SNARK over the same field.
+// Poseidon: https://eprint.iacr.org/2019/458.pdf
+//
+// Parameters (width 3, 128-bit security for ~255-bit fields), those of the reference
+// implementation https://extgit.iaik.tugraz.at/krypto/hadeshash (poseidonperm_x5_255_3 for
+// BLS12-381, poseidonperm_x5_254_3 for BN254):
+// - state width t = 3, rate 2, capacity 1,
+// - S-box x^5 (requires gcd(5, p - 1) = 1, true for the BLS12-381 and BN254 scalar fields),
+// - R_F = 8 full rounds and R_P = 57 partial rounds,
+// - round constants and the Cauchy MDS matrix M[i][j] = 1 / (x_i + y_j) drawn from the Grain LFSR
+//   of generate_parameters_grain.sage, seeded with the field size and the parameters above.
+//
+// The sponge follows section 4.2 of the paper for variable-length inputs: the capacity starts
+// at 2^64, and each absorbed sequence is padded with a 1 and as many zeros as needed to fill the
+// rate before squeezing.
+
+use std::marker::PhantomData;
+use ark_ec::AffineRepr;
+use ark_ec::pairing::Pairing;
+use ark_ff::{BigInteger, Field, PrimeField, Zero};
+use crate::fiat_shamir::Transcript;
+
+pub const WIDTH: usize = 3;
+pub const RATE: usize = 2;
+pub const CAPACITY: usize = 1;
+pub const ALPHA: u64 = 5;
+pub const FULL_ROUNDS: usize = 8;
+pub const PARTIAL_ROUNDS: usize = 57;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoseidonParameters<F: PrimeField> {
+    pub round_constants: Vec<[F; WIDTH]>,
+    pub mds: [[F; WIDTH]; WIDTH],
+}
+
+impl <F: PrimeField> PoseidonParameters<F> {
+    pub fn new() -> Self {
+        let mut grain = Grain::new(F::MODULUS_BIT_SIZE);
+        // rejection sampling: the constants are uniform below p
+        let round_constants = (0..FULL_ROUNDS + PARTIAL_ROUNDS)
+            .map(|_| std::array::from_fn(|_| loop {
+                if let Some(c) = F::from_bigint(grain.next_bigint::<F>()) {
+                    break c;
+                }
+            }))
+            .collect();
+
+        // the points x_i, y_j are reduced modulo p, and drawn again until the x_i + y_j are
+        // invertible and the 2t points distinct
+        let mds = loop {
+            let points: Vec<F> = (0..2 * WIDTH)
+                .map(|_| F::from_be_bytes_mod_order(&grain.next_bigint::<F>().to_bytes_be()))
+                .collect();
+            let distinct = points.iter().enumerate().all(|(i, a)| points[..i].iter().all(|b| a != b));
+            if !distinct {
+                continue;
+            }
+            let (xs, ys) = points.split_at(WIDTH);
+            let mut mds = [[F::zero(); WIDTH]; WIDTH];
+            let invertible = mds.iter_mut().zip(xs).all(|(row, x)| {
+                row.iter_mut().zip(ys).all(|(entry, y)| (*x + y).inverse().map(|inv| *entry = inv).is_some())
+            });
+            if invertible {
+                break mds;
+            }
+        };
+
+        Self {
+            round_constants,
+            mds,
+        }
+    }
+
+    pub fn permute(&self, state: &mut [F; WIDTH]) {
+        let half_full_rounds = FULL_ROUNDS / 2;
+        for (r, constants) in self.round_constants.iter().enumerate() {
+            for (s, c) in state.iter_mut().zip(constants) {
+                *s += c;
+            }
+
+            let is_full_round = r < half_full_rounds || r >= half_full_rounds + PARTIAL_ROUNDS;
+            if is_full_round {
+                for s in state.iter_mut() {
+                    *s = s.pow([ALPHA]);
+                }
+            } else {
+                state[0] = state[0].pow([ALPHA]);
+            }
+
+            let mut mixed = [F::zero(); WIDTH];
+            for (m, row) in mixed.iter_mut().zip(&self.mds) {
+                *m = row.iter().zip(state.iter()).map(|(a, b)| *a * b).sum();
+            }
+            *state = mixed;
+        }
+    }
+}
+
+impl <F: PrimeField> Default for PoseidonParameters<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The 80-bit Grain LFSR of the reference parameter generation. Bit i of the state is the i-th bit
+// of the seed: field type 1 (prime) on 2 bits, S-box 0 (x^alpha) on 4 bits, the field size on 12,
+// t on 12, R_F on 10, R_P on 10, then 30 ones, each written most significant bit first.
+struct Grain {
+    state: u128,
+}
+
+impl Grain {
+    fn new(field_bits: u32) -> Self {
+        let mut state = 0u128;
+        let mut position = 0;
+        for (value, width) in [(1, 2), (0, 4), (field_bits as u128, 12), (WIDTH as u128, 12), (FULL_ROUNDS as u128, 10), (PARTIAL_ROUNDS as u128, 10), ((1 << 30) - 1, 30)] {
+            for bit in (0..width).rev() {
+                state |= ((value >> bit) & 1) << position;
+                position += 1;
+            }
+        }
+        let mut grain = Self { state };
+        for _ in 0..160 {
+            grain.update();
+        }
+        grain
+    }
+
+    fn update(&mut self) -> bool {
+        let bit = [62, 51, 38, 23, 13, 0].iter().fold(0, |acc, i| acc ^ (self.state >> i) & 1);
+        self.state = (self.state >> 1) | (bit << 79);
+        bit == 1
+    }
+
+    // The bits come in pairs, the second one is output when the first one is set.
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let keep = self.update();
+            let bit = self.update();
+            if keep {
+                return bit;
+            }
+        }
+    }
+
+    // MODULUS_BIT_SIZE bits, most significant first.
+    fn next_bigint<F: PrimeField>(&mut self) -> F::BigInt {
+        let bits: Vec<bool> = (0..F::MODULUS_BIT_SIZE).map(|_| self.next_bit()).collect();
+        F::BigInt::from_bits_be(&bits)
+    }
+}
+
+// A duplex sponge over the Poseidon permutation.
+#[derive(Debug, Clone)]
+pub struct PoseidonSponge<F: PrimeField> {
+    params: PoseidonParameters<F>,
+    state: [F; WIDTH],
+    absorb_pos: usize,
+}
+
+impl <F: PrimeField> PoseidonSponge<F> {
+    pub fn new(params: PoseidonParameters<F>) -> Self {
+        let mut state = [F::zero(); WIDTH];
+        state[0] = F::from(1u128 << 64);
+        Self {
+            params,
+            state,
+            absorb_pos: 0,
+        }
+    }
+
+    pub fn absorb(&mut self, element: F) {
+        if self.absorb_pos == RATE {
+            self.params.permute(&mut self.state);
+            self.absorb_pos = 0;
+        }
+        self.state[CAPACITY + self.absorb_pos] += element;
+        self.absorb_pos += 1;
+    }
+
+    // Pads what was absorbed since the last squeeze, so that inputs of different lengths differ.
+    pub fn squeeze(&mut self) -> F {
+        self.absorb(F::one());
+        self.params.permute(&mut self.state);
+        self.absorb_pos = 0;
+        self.state[CAPACITY]
+    }
+}
+
+// A transcript over the Poseidon sponge.
+// Scalars are absorbed natively, byte strings are length-prefixed and packed into field elements,
+// and curve points are absorbed as their coordinates split into limbs that fit the scalar field.
+pub struct PoseidonTranscript<P: Pairing> {
+    sponge: PoseidonSponge<P::ScalarField>,
+    _phantom_data_p: PhantomData<P>,
+}
+
+impl <P: Pairing> PoseidonTranscript<P> {
+    // Number of bytes packed into one field element without reduction.
+    const BYTES_PER_ELEMENT: usize = (P::ScalarField::MODULUS_BIT_SIZE as usize - 1) / 8;
+
+    fn absorb_bytes(&mut self, bytes: &[u8]) {
+        self.sponge.absorb(P::ScalarField::from(bytes.len() as u64));
+        for chunk in bytes.chunks(Self::BYTES_PER_ELEMENT) {
+            self.sponge.absorb(P::ScalarField::from_le_bytes_mod_order(chunk));
+        }
+    }
+
+    fn absorb_limbs(&mut self, element: impl PrimeField) {
+        let bytes = element.into_bigint().to_bytes_le();
+        for chunk in bytes.chunks(Self::BYTES_PER_ELEMENT) {
+            self.sponge.absorb(P::ScalarField::from_le_bytes_mod_order(chunk));
+        }
+    }
+}
+
+impl <P: Pairing> Transcript<P> for PoseidonTranscript<P> {
+    fn new(protocol_label: &[u8]) -> Self {
+        let mut transcript = Self {
+            sponge: PoseidonSponge::new(PoseidonParameters::new()),
+            _phantom_data_p: PhantomData,
+        };
+        transcript.append_message(b"dom-sep", protocol_label);
+        transcript
+    }
+
+    fn append_message(&mut self, label: &[u8], message: &[u8]) {
+        self.absorb_bytes(label);
+        self.absorb_bytes(message);
+    }
+
+    fn append_u64(&mut self, label: &[u8], value: u64) {
+        self.absorb_bytes(label);
+        self.sponge.absorb(P::ScalarField::from(value));
+    }
+
+    fn append_point(&mut self, label: &[u8], point: &impl AffineRepr) {
+        self.absorb_bytes(label);
+        match point.xy() {
+            Some((x, y)) => {
+                self.sponge.absorb(P::ScalarField::zero());
+                for coordinate in x.to_base_prime_field_elements().chain(y.to_base_prime_field_elements()) {
+                    self.absorb_limbs(coordinate);
+                }
+            }
+            // the point at infinity
+            None => self.sponge.absorb(P::ScalarField::from(1u64)),
+        }
+    }
+
+    fn append_scalar(&mut self, label: &[u8], scalar: &P::ScalarField) {
+        self.absorb_bytes(label);
+        self.sponge.absorb(*scalar);
+    }
+
+    fn challenge_scalar(&mut self, label: &[u8]) -> P::ScalarField {
+        self.absorb_bytes(label);
+        self.sponge.squeeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use ark_bls12_381::{Bls12_381, Fr, G1Affine};
+    use ark_ec::{AffineRepr, CurveGroup};
+    use ark_ff::{BigInteger, One, PrimeField, Zero};
+    use crate::fiat_shamir::Transcript;
+    use super::*;
+
+    #[test]
+    fn test_alpha_is_a_permutation() {
+        // x -> x^5 is a bijection iff gcd(5, p - 1) = 1
+        let p_minus_one = |mut modulus: Vec<u8>| {
+            modulus[0] -= 1;
+            modulus
+        };
+        for modulus in [
+            p_minus_one(Fr::MODULUS.to_bytes_le()),
+            p_minus_one(ark_bn254::Fr::MODULUS.to_bytes_le()),
+        ] {
+            let rem = modulus.iter().rev().fold(0u64, |acc, b| (acc * 256 + *b as u64) % ALPHA);
+            assert_ne!(rem, 0);
+        }
+    }
+
+    // Hex strings of the reference implementation, https://extgit.iaik.tugraz.at/krypto/hadeshash
+    fn from_hex<F: PrimeField>(hex: &str) -> F {
+        let bytes: Vec<u8> = (2..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        F::from_be_bytes_mod_order(&bytes)
+    }
+
+    #[test]
+    fn test_parameters() {
+        let params = PoseidonParameters::<Fr>::new();
+        assert_eq!(params.round_constants.len(), FULL_ROUNDS + PARTIAL_ROUNDS);
+        assert_eq!(params, PoseidonParameters::<Fr>::new());
+
+        // the first round constant and MDS entry of poseidonperm_x5_254_3, as in circomlib
+        let params = PoseidonParameters::<ark_bn254::Fr>::new();
+        assert_eq!(params.round_constants[0][0], from_hex("0x0ee9a592ba9a9518d05986d656f40c2114c4993c11bb29938d21d47304cd8e6e"));
+        assert_eq!(params.mds[0][0], from_hex("0x109b7f411ba0e4c9b2b70caf5c36a7b194be7c11ad24378bfedb68592ba8118b"));
+    }
+
+    // The test vectors of the reference implementation: the permutation of [0, 1, 2].
+    #[test]
+    fn test_permutation_known_answer() {
+        let mut state = [Fr::from(0), Fr::from(1), Fr::from(2)];
+        PoseidonParameters::<Fr>::new().permute(&mut state);
+        assert_eq!(state, [
+            from_hex("0x28ce19420fc246a05553ad1e8c98f5c9d67166be2c18e9e4cb4b4e317dd2a78a"),
+            from_hex("0x51f3e312c95343a896cfd8945ea82ba956c1118ce9b9859b6ea56637b4b1ddc4"),
+            from_hex("0x3b2b69139b235626a0bfb56c9527ae66a7bf486ad8c11c14d1da0c69bbe0f79a"),
+        ]);
+
+        let mut state = [ark_bn254::Fr::from(0), ark_bn254::Fr::from(1), ark_bn254::Fr::from(2)];
+        PoseidonParameters::<ark_bn254::Fr>::new().permute(&mut state);
+        assert_eq!(state, [
+            from_hex("0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"),
+            from_hex("0x0fca49b798923ab0239de1c9e7a4a9a2210312b6a2f616d18b5a87f9b628ae29"),
+            from_hex("0x0e7ae82e40091e63cbd4f16a6d16310b3729d4b6e138fcf54110e2867045a30c"),
+        ]);
+    }
+
+    #[test]
+    fn test_sponge_known_answer() {
+        let mut sponge = PoseidonSponge::new(PoseidonParameters::<Fr>::new());
+        for i in 0..5 {
+            sponge.absorb(Fr::from(i));
+        }
+        assert_eq!(sponge.squeeze(), Fr::from_str(KAT_SPONGE[0]).unwrap());
+        assert_eq!(sponge.squeeze(), Fr::from_str(KAT_SPONGE[1]).unwrap());
+    }
+
+    // Outputs of this sponge over the reference permutation, recorded to catch regressions.
+    const KAT_SPONGE: [&str; 2] = [
+        "1360619467029203887624983535749006032634882855946908163544358567704358384258",
+        "23594815209006347340382874432539725086831048868651810058339717931893762376664",
+    ];
+
+    #[test]
+    fn test_sponge_absorbs_rate_elements_per_permutation() {
+        let params = PoseidonParameters::<Fr>::new();
+        let mut sponge = PoseidonSponge::new(params.clone());
+        sponge.absorb(Fr::from(1));
+        sponge.absorb(Fr::from(2));
+        let challenge = sponge.squeeze();
+
+        // the full rate is permuted, then the padding fills the next one
+        let mut state = [Fr::from(1u128 << 64), Fr::from(1), Fr::from(2)];
+        params.permute(&mut state);
+        state[CAPACITY] += Fr::one();
+        params.permute(&mut state);
+        assert_eq!(challenge, state[CAPACITY]);
+    }
+
+    #[test]
+    fn test_sponge_padding() {
+        let squeeze = |elements: &[Fr]| {
+            let mut sponge = PoseidonSponge::new(PoseidonParameters::<Fr>::new());
+            elements.iter().for_each(|e| sponge.absorb(*e));
+            sponge.squeeze()
+        };
+        // trailing zeros and a trailing 1 change the output
+        let a = Fr::from(7);
+        assert_ne!(squeeze(&[a]), squeeze(&[a, Fr::zero()]));
+        assert_ne!(squeeze(&[a, Fr::zero()]), squeeze(&[a, Fr::zero(), Fr::zero()]));
+        assert_ne!(squeeze(&[a]), squeeze(&[a, Fr::one()]));
+        assert_ne!(squeeze(&[]), squeeze(&[Fr::zero()]));
+
+        // the capacity starts at the variable-length domain tag, not at zero
+        let mut state = [Fr::zero(), Fr::one(), Fr::zero()];
+        PoseidonParameters::<Fr>::new().permute(&mut state);
+        assert_ne!(squeeze(&[]), state[CAPACITY]);
+    }
+
+    #[test]
+    fn test_transcript() {
+        let point = G1Affine::generator().mul_bigint([7u64]).into_affine();
+        let challenge = |label: &[u8], point: G1Affine| {
+            let mut transcript = PoseidonTranscript::<Bls12_381>::new(b"test");
+            transcript.append_point(b"point", &point);
+            transcript.append_scalar(b"scalar", &Fr::from(3));
+            transcript.challenge_scalar(label)
+        };
+        assert_eq!(challenge(b"c", point), challenge(b"c", point));
+        assert_ne!(challenge(b"c", point), challenge(b"d", point));
+        assert_ne!(challenge(b"c", point), challenge(b"c", G1Affine::zero()));
+
+        let mut transcript = PoseidonTranscript::<Bls12_381>::new(b"test");
+        let a = transcript.challenge_scalar(b"c");
+        let b = transcript.challenge_scalar(b"c");
+        assert_ne!(a, b);
+    }
+}
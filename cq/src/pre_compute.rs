@@ -3,17 +3,20 @@ use ark_ec::{AffineRepr, CurveGroup};
 use ark_ec::pairing::Pairing;
 use ark_ff::Field;
 use ark_poly::{EvaluationDomain, GeneralEvaluationDomain};
+use ark_std::cfg_iter;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 // Compute the commitments of the Lagrange basis polynomials
 // in O(NLogN) curve operations using FFT
 // The algorithm in the section 3.3 of: https://eprint.iacr.org/2017/602.pdf
 pub fn fast_lagrange_basis_commitments_computation<P: Pairing>(srs: &[P::G1Affine],  n: usize) -> Vec<P::G1Affine>{
-    let coefficients = &srs[0..].iter().map(|s| s.into_group()).collect::<Vec<_>>();
+    let coefficients = &cfg_iter!(srs).map(|s| s.into_group()).collect::<Vec<_>>();
     let domain = GeneralEvaluationDomain::<P::ScalarField>::new(n).unwrap();
     
     let n_inv = domain.size_as_field_element().inverse().unwrap();
     
-    let mut evals = domain.fft(&coefficients);
+    let mut evals = domain.fft(coefficients);
     // do reordering
     evals.reverse();
     
@@ -21,9 +24,8 @@ pub fn fast_lagrange_basis_commitments_computation<P: Pairing>(srs: &[P::G1Affin
     let mut reordered_evals = vec![last];
     reordered_evals.extend(&evals);
     
-    let lagrange_basis_commitments: Vec<P::G1> = reordered_evals.iter().map(|li| li.mul(n_inv)).collect();
-    let lagrange_basis_commitment_affines = P::G1::normalize_batch(&lagrange_basis_commitments);
-    lagrange_basis_commitment_affines
+    let lagrange_basis_commitments: Vec<P::G1> = cfg_iter!(reordered_evals).map(|li| li.mul(n_inv)).collect();
+    P::G1::normalize_batch(&lagrange_basis_commitments)
 }
 
 // compute [(l_i(X) - l_i(0)) / X]_1 = g^-i· [L_i(X)]_1 - (1/N)·[x^(N-1)]_1
@@ -32,15 +34,12 @@ pub fn compute_quotient_lagrange_basic_commitments<P: Pairing>(l_i_commitments:
     let n_inv = domain.size_as_field_element().inverse().unwrap();
     let sub = srs[n-1].mul(-n_inv);
     
-    let mut res: Vec<P::G1Affine> = Vec::new();
-    
-    for (i, l_i_commitment) in l_i_commitments.iter().enumerate() {
-        let quotient_com = l_i_commitment.mul(domain.element(n - i));
-        res.push((quotient_com + sub).into());
-    }
-    
-    res
+    let res: Vec<P::G1> = cfg_iter!(l_i_commitments).enumerate().map(|(i, l_i_commitment)| {
+        l_i_commitment.mul(domain.element(n - i)) + sub
+    }).collect();
     
+    P::G1::normalize_batch(&res)
+
 }
 
 #[cfg(test)]
@@ -1,28 +1,38 @@
 use ark_ff::{Field, One};
 use std::collections::HashMap;
 use ark_poly::{DenseUVPolynomial, EvaluationDomain, Polynomial};
-use std::ops::{AddAssign, Mul, };
-use ark_ec::CurveGroup;
+use std::ops::Mul;
+use ark_ec::{CurveGroup, VariableBaseMSM};
 use ark_ec::pairing::Pairing;
 use ark_ff::Zero;
 use ark_poly::GeneralEvaluationDomain;
 use ark_poly::univariate::DensePolynomial;
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Compress};
-use sha2::Digest;
-use crate::cq::Cq;
+use std::marker::PhantomData;
+use sha2::Sha256;
+use ark_std::cfg_iter;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use crate::cq::{ProvingKey, VerifyingKey};
 use crate::errors::GeneralError;
-use crate::fiat_shamir::Script;
+use crate::fiat_shamir::{Script, Transcript, CQ_PROTOCOL_LABEL};
+use crate::kzg::Kzg;
 
-pub struct Prover<T: Digest + Default, P: Pairing> {
+// The transcript defaults to SHA-256; use `PoseidonTranscript` for recursion-friendly proofs.
+pub struct Prover<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
     f_i: Vec<P::ScalarField>,
     f_i_map: HashMap<P::ScalarField, usize>,
-    script: Script<T, P>,
+    // the selector q_i of gated lookups, f_i must be in the table only where q_i = 1
+    q_i: Option<Vec<P::ScalarField>>,
+    script: T,
+    _phantom_data_p: PhantomData<P>,
     
 }
 
 struct RoundTwoResponse<P: Pairing> {
     b_x: DensePolynomial<P::ScalarField>,
     b_0_x: DensePolynomial<P::ScalarField>,
+    q_x: Option<DensePolynomial<P::ScalarField>>,
     q_b_x: DensePolynomial<P::ScalarField>,
     // p_x: DensePolynomial<P::ScalarField>,
     a_sparse: Vec<(P::ScalarField, usize)>,
@@ -37,8 +47,10 @@ struct RoundThreeResponse<P: Pairing> {
     b_0_gamma: P::ScalarField,
     f_gamma: P::ScalarField,
     a_0: P::ScalarField,
+    q_gamma: Option<P::ScalarField>,
     cm1_pi_eta: P::G1Affine,
     cm1_a_0_x: P::G1Affine,
+    cm1_p_a_0: P::G1Affine,
 }
 
 #[derive(Debug, Clone, CanonicalDeserialize, CanonicalSerialize)]
@@ -54,8 +66,12 @@ pub struct Proof<P: Pairing> {
     pub b_0_gamma: P::ScalarField,
     pub f_gamma: P::ScalarField,
     pub a_0: P::ScalarField,
+    // q(gamma) for selector-gated lookups
+    pub q_gamma: Option<P::ScalarField>,
     pub cm1_pi_eta: P::G1Affine,
     pub cm1_a_0_x: P::G1Affine,
+    // [x^{D-(N-2)}·A0(x)]_1 for the degree check of A0
+    pub cm1_p_a_0: P::G1Affine,
 }
 
 impl <P: Pairing> Proof<P> {
@@ -71,100 +87,221 @@ impl <P: Pairing> Proof<P> {
             + self.b_0_gamma.serialized_size(compress)
             + self.f_gamma.serialized_size(compress)
             + self.a_0.serialized_size(compress)
+            + self.q_gamma.serialized_size(compress)
             + self.cm1_pi_eta.serialized_size(compress)
             + self.cm1_a_0_x.serialized_size(compress)
+            + self.cm1_p_a_0.serialized_size(compress)
     }
 }
 
 
-impl <T: Digest + Default, P: Pairing> Prover<T, P> {
+// The preprocessed table column a proof looks up into: a proving key,
+// or several columns of a multi-column table compressed with a challenge.
+pub(crate) trait LookupTable<P: Pairing> {
+    fn kzg(&self) -> &Kzg<P>;
+    fn big_n(&self) -> usize;
+    fn verifying_key(&self) -> VerifyingKey<P>;
+    fn t_i(&self, index: usize) -> P::ScalarField;
+    fn cm1_li(&self, index: usize) -> P::G1Affine;
+    fn cm1_qi(&self, index: usize) -> P::G1Affine;
+    fn cm1_l_i_quotient(&self, index: usize) -> P::G1Affine;
+    fn cm1_l_i_quotient_shifted(&self, index: usize) -> P::G1Affine;
+}
+
+impl <P: Pairing> LookupTable<P> for ProvingKey<'_, P> {
+    fn kzg(&self) -> &Kzg<P> {
+        &self.kzg
+    }
+
+    fn big_n(&self) -> usize {
+        self.big_n
+    }
+
+    fn verifying_key(&self) -> VerifyingKey<P> {
+        ProvingKey::verifying_key(self)
+    }
+
+    fn t_i(&self, index: usize) -> P::ScalarField {
+        self.t_i[index]
+    }
+
+    fn cm1_li(&self, index: usize) -> P::G1Affine {
+        self.cm1_li[index]
+    }
+
+    fn cm1_qi(&self, index: usize) -> P::G1Affine {
+        self.cm1_qi[index]
+    }
+
+    fn cm1_l_i_quotient(&self, index: usize) -> P::G1Affine {
+        self.cm1_l_i_quotient[index]
+    }
+
+    fn cm1_l_i_quotient_shifted(&self, index: usize) -> P::G1Affine {
+        self.cm1_l_i_quotient_shifted[index]
+    }
+}
+
+// Σ scalar_i * base(index_i) over the sparse (scalar, index) pairs, with one multi-scalar multiplication
+fn sparse_msm<P: Pairing>(terms: &[(P::ScalarField, usize)], base: impl Fn(usize) -> P::G1Affine) -> P::G1Affine {
+    let (bases, scalars): (Vec<P::G1Affine>, Vec<P::ScalarField>) = terms.iter()
+        .map(|(scalar, index)| (base(*index), *scalar))
+        .unzip();
+    P::G1::msm_unchecked(&bases, &scalars).into_affine()
+}
+
+// compute cm1_mx = Σ cm1_li * m_i
+pub(crate) fn compute_cm1_mx<P: Pairing>(pk: &impl LookupTable<P>, m_i_vec: &[(usize, usize)]) -> P::G1Affine {
+    let m_sparse: Vec<(P::ScalarField, usize)> = m_i_vec.iter()
+        .map(|(index, value)| (P::ScalarField::from(*value as u64), *index))
+        .collect();
+    sparse_msm::<P>(&m_sparse, |index| pk.cm1_li(index))
+}
+
+// The sparse a_i with their indices, A_1 and Q_1.
+pub(crate) type SparseA<P> = (Vec<(<P as Pairing>::ScalarField, usize)>, <P as Pairing>::G1Affine, <P as Pairing>::G1Affine);
+
+// A_1 = Σ cm1_li * m_i / (t_i + beta) =  Σ cm1_li * a_i
+// Q_1 = Σ cm1_qi * m_i / (t_i + beta) =  Σ cm1_qi * a_i
+pub(crate) fn compute_cm1_a<P: Pairing>(
+    pk: &impl LookupTable<P>, m_i_vec: &[(usize, usize)], beta: P::ScalarField,
+) -> SparseA<P> {
+    let a_sparse: Vec<(P::ScalarField, usize)> = m_i_vec.iter().map(|(index, value)| {
+        let value = P::ScalarField::from(*value as u64);
+        let tmp = pk.t_i(*index) + beta;
+        (tmp.inverse().unwrap() * value, *index)
+    }).collect();
+
+    let cm1_a = sparse_msm::<P>(&a_sparse, |index| pk.cm1_li(index));
+    let cm1_q_a = sparse_msm::<P>(&a_sparse, |index| pk.cm1_qi(index));
+    (a_sparse, cm1_a, cm1_q_a)
+}
+
+// [A_0_X]_1 = ∑ cm1_l_i_quotient * a_i
+// [x^{D-(N-2)}·A_0_X]_1 = ∑ cm1_l_i_quotient_shifted * a_i
+pub(crate) fn compute_cm1_a_0_x<P: Pairing>(pk: &impl LookupTable<P>, a_sparse: &[(P::ScalarField, usize)]) -> (P::G1Affine, P::G1Affine) {
+    let cm1_a_0_x = sparse_msm::<P>(a_sparse, |index| pk.cm1_l_i_quotient(index));
+    let cm1_p_a_0 = sparse_msm::<P>(a_sparse, |index| pk.cm1_l_i_quotient_shifted(index));
+    (cm1_a_0_x, cm1_p_a_0)
+}
+
+impl <P: Pairing, T: Transcript<P>> Prover<P, T> {
+    // The witness can have any size. It is padded to a power of two n >= 2 with the first entry
+    // of the table when proving, the proof and cm1_f are for the padded witness.
     pub fn new(f_i: Vec<P::ScalarField>) -> Result<Self, GeneralError> {
-        if !f_i.len().is_power_of_two() {
-            return Err(GeneralError::WitnessSizeNotPowerOf2);
-        }
-        let mut hash_map = HashMap::<P::ScalarField, usize>::new();
-        for (_, f) in f_i.iter().enumerate() {
-            let value = hash_map.get(f);
-            if value.is_none() {
-                hash_map.insert(*f, 1);
-            } else {
-                hash_map.insert(*f, value.unwrap() + 1);
-            }
+        Self::with_transcript(f_i, T::new(CQ_PROTOCOL_LABEL))
+    }
+
+    // A prover continuing a transcript that already absorbed some messages.
+    pub(crate) fn with_transcript(f_i: Vec<P::ScalarField>, script: T) -> Result<Self, GeneralError> {
+        if f_i.is_empty() {
+            return Err(GeneralError::EmptyWitness);
         }
+        let hash_map = Self::count_values(&f_i);
         
         Ok(Self {
             f_i,
             f_i_map: hash_map,
-            script: Script::new(),
+            q_i: None,
+            script,
+            _phantom_data_p: PhantomData,
         })
     }
-    
-    fn compute_cm1_fx(&self, cq: &Cq<P>) -> (DensePolynomial<P::ScalarField>, P::G1Affine) {
-        let n = self.f_i.len();
-        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(n).unwrap();
-        
-        let f_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&self.f_i));
-        let cm1_fx = cq.kzg.commit_g1(&f_x);
-        (f_x, cm1_fx)
+
+    // A prover for the rows of f_i where the selector q_i is 1, the other rows can hold any value.
+    // The selector is padded with zeros, so its commitment [q(x)]_1 is public input of the verifier.
+    pub fn new_gated(f_i: Vec<P::ScalarField>, q_i: Vec<P::ScalarField>) -> Result<Self, GeneralError> {
+        if q_i.len() != f_i.len() {
+            return Err(GeneralError::SelectorSizeMismatch);
+        }
+        if q_i.iter().any(|q| !q.is_zero() && !q.is_one()) {
+            return Err(GeneralError::InvalidSelector);
+        }
+        let mut prover = Self::new(f_i)?;
+        let selected: Vec<P::ScalarField> = prover.f_i.iter().zip(&q_i)
+            .filter(|(_, q)| q.is_one())
+            .map(|(f, _)| *f)
+            .collect();
+        prover.f_i_map = Self::count_values(&selected);
+        prover.q_i = Some(q_i);
+        Ok(prover)
     }
 
-    // compute cm1_mx = Σ cm1_li * m_i
-    fn compute_cm1_mx(&self, cq: &Cq<P>, m_i_vec: &[(usize, usize)]) -> P::G1Affine {
-        let mut cm1_mx = P::G1::zero();
-        for (index, value) in m_i_vec {
-            let value = P::ScalarField::from(*value as u64);
-            let tmp = cq.cm1_li[*index].mul(value);
-            cm1_mx.add_assign(tmp);
+    fn count_values(f_i: &[P::ScalarField]) -> HashMap<P::ScalarField, usize> {
+        let mut hash_map = HashMap::<P::ScalarField, usize>::new();
+        for f in f_i {
+            *hash_map.entry(*f).or_insert(0) += 1;
         }
+        hash_map
+    }
+    
+    // The witness is padded to a power of two n >= 2 with the first entry of the table,
+    // whose multiplicity is added to m_i_vec. A selector is padded with zeros instead,
+    // so the padding rows are not looked up.
+    fn pad_witness(&self, pk: &impl LookupTable<P>, m_i_vec: &mut Vec<(usize, usize)>) -> (Vec<P::ScalarField>, Option<Vec<P::ScalarField>>) {
+        let small_n = self.f_i.len().next_power_of_two().max(2);
+        let padding = small_n - self.f_i.len();
+        let mut f_i = self.f_i.clone();
+        f_i.resize(small_n, pk.t_i(0));
+        if let Some(q_i) = &self.q_i {
+            let mut q_i = q_i.clone();
+            q_i.resize(small_n, P::ScalarField::zero());
+            return (f_i, Some(q_i));
+        }
+        if padding > 0 {
+            match m_i_vec.iter_mut().find(|(index, _)| *index == 0) {
+                Some((_, value)) => *value += padding,
+                None => m_i_vec.push((0, padding)),
+            }
+        }
+        (f_i, None)
+    }
 
-        cm1_mx.into_affine()
+    fn compute_cm1_fx(&self, pk: &impl LookupTable<P>, f_i: &[P::ScalarField]) -> (DensePolynomial<P::ScalarField>, P::G1Affine) {
+        let n = f_i.len();
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(n).unwrap();
+        
+        let f_x = DensePolynomial::from_coefficients_slice(&domain.ifft(f_i));
+        let cm1_fx = pk.kzg().commit_g1(&f_x);
+        (f_x, cm1_fx)
     }
 
     fn compute_round_2(
         &self,
-        cq: &Cq<P>, f_x: &DensePolynomial<P::ScalarField>,
-        m_i_vec: &[(usize, usize)], t_i: &[P::ScalarField], beta: P::ScalarField
+        pk: &impl LookupTable<P>, f_i: &[P::ScalarField], q_i: Option<&[P::ScalarField]>,
+        f_x: &DensePolynomial<P::ScalarField>, m_i_vec: &[(usize, usize)], beta: P::ScalarField
     ) -> Result<RoundTwoResponse<P>, GeneralError>
     {
         // Step 2 3 4
-        // A_1 = Σ cm1_li * m_i / (t_i + beta) =  Σ cm1_li * a_i
-        // Q_1 = Σ cm1_qi * m_i / (t_i + beta) =  Σ cm1_qi * a_i
-
-        let mut a_sparse: Vec<(P::ScalarField, usize)> = vec![];
-
-        let mut cm1_a = P::G1::zero();
-        let mut cm1_q_a = P::G1::zero();
-        for (index, value) in m_i_vec {
-            let value = P::ScalarField::from(*value as u64);
-            let tmp = t_i[*index] + beta;
-            let a_i = tmp.inverse().unwrap() * value;
-            a_sparse.push((a_i, *index));
-            cm1_a.add_assign(cq.cm1_li[*index].mul(a_i));
-            cm1_q_a.add_assign(cq.cm1_qi[*index].mul(a_i));
-        }
+        let (a_sparse, cm1_a, cm1_q_a) = compute_cm1_a(pk, m_i_vec, beta);
 
         // Step 5 & 6
-        // B_i = 1 / (f_i + beta)
+        // B_i = q_i / (f_i + beta), with q_i = 1 without a selector
         // B_0_x = (b_x - b[0]) / X
-        let b_evals = self.f_i.iter().map(|f_i| {
-            (*f_i + beta).inverse().unwrap()
+        let b_evals = cfg_iter!(f_i).enumerate().map(|(i, f_i)| {
+            match q_i {
+                Some(q_i) if q_i[i].is_zero() => P::ScalarField::zero(),
+                _ => (*f_i + beta).inverse().unwrap(),
+            }
         }).collect::<Vec<P::ScalarField>>();
 
-        let small_n = self.f_i.len();
+        let small_n = f_i.len();
         let domain = GeneralEvaluationDomain::<P::ScalarField>::new(small_n).unwrap();
+        let q_x = q_i.map(|q_i| DensePolynomial::from_coefficients_slice(&domain.ifft(q_i)));
 
         let b_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&b_evals));
-        let b_0_x = DensePolynomial::from_coefficients_slice(&b_x.coeffs[1..]);
+        let b_0_x = DensePolynomial::from_coefficients_slice(b_x.coeffs.get(1..).unwrap_or(&[]));
 
         // Step 7
-        let cm1_b_0 = cq.kzg.commit_g1(&b_0_x);
+        let cm1_b_0 = pk.kzg().commit_g1(&b_0_x);
 
         // Step 8
         let mut f_x_plus_beta = f_x.clone();
         f_x_plus_beta.coeffs[0] += beta;
 
-        // b_x(f_x + beta) - 1
-        let tmp = b_x.clone().mul(&f_x_plus_beta) - DensePolynomial::from_coefficients_slice(&vec![P::ScalarField::one()]);
+        // b_x(f_x + beta) - q_x, with q_x = 1 without a selector
+        let q_or_one = q_x.clone().unwrap_or(DensePolynomial::from_coefficients_slice(&[P::ScalarField::one()]));
+        let tmp = b_x.clone().mul(&f_x_plus_beta) - q_or_one;
         let (q_b_x, rem) = tmp.divide_by_vanishing_poly(domain);
 
         if !rem.is_zero() {
@@ -172,20 +309,21 @@ impl <T: Digest + Default, P: Pairing> Prover<T, P> {
         }
 
         // Step 9
-        let cm1_q_b = cq.kzg.commit_g1(&q_b_x);
+        let cm1_q_b = pk.kzg().commit_g1(&q_b_x);
 
         // Step 10
-        let index = cq.big_n - 1 - (small_n - 2);
-        let cm1_p = cq.kzg.fast_commit_g1(&b_0_x, index);
+        let index = pk.kzg().max_degree() + 2 - small_n;
+        let cm1_p = pk.kzg().fast_commit_g1(&b_0_x, index);
 
         Ok(RoundTwoResponse {
             b_x,
             b_0_x,
+            q_x,
             q_b_x,
             // p_x,
             a_sparse,
-            cm1_a: cm1_a.into_affine(),
-            cm1_q_a: cm1_q_a.into_affine(),
+            cm1_a,
+            cm1_q_a,
             cm1_b_0,
             cm1_q_b,
             cm1_p,
@@ -198,13 +336,13 @@ impl <T: Digest + Default, P: Pairing> Prover<T, P> {
 
     fn compute_round_3(
         &mut self,
-        cq: &Cq<P>, gamma: P::ScalarField, b_0: P::ScalarField,
-        q_b_x: &DensePolynomial<P::ScalarField>,
-        f_x: &DensePolynomial<P::ScalarField>, b_0_x: &DensePolynomial<P::ScalarField>,
-        a_sparse: &[(P::ScalarField, usize)]
+        pk: &impl LookupTable<P>, small_n: usize, gamma: P::ScalarField,
+        f_x: &DensePolynomial<P::ScalarField>, proof_2: &RoundTwoResponse<P>,
     ) -> Result<RoundThreeResponse<P>, GeneralError> 
     {
-        let small_n = self.f_i.len();
+        let RoundTwoResponse { b_x, b_0_x, q_x, q_b_x, a_sparse, .. } = proof_2;
+        let q_x = q_x.as_ref();
+        let b_0 = b_x.evaluate(&P::ScalarField::zero());
 
         // Step 2, 3
         let b_0_gamma = b_0_x.evaluate(&gamma);
@@ -212,81 +350,134 @@ impl <T: Digest + Default, P: Pairing> Prover<T, P> {
 
         // Step 4
         // small_n * B[0] = big_n * A[0] (via Aurora lemma)
-        let big_n_inv = P::ScalarField::from(cq.big_n as u128).inverse().unwrap();
+        let big_n_inv = P::ScalarField::from(pk.big_n() as u128).inverse().unwrap();
         let a_0 = b_0 * P::ScalarField::from(small_n as u128) * big_n_inv;
         // Batch KZG checks
         // Prover needs to send above values to verifier to receive a challenge.
-        self.script.feed_with_field_elements(&[b_0_gamma, f_gamma, a_0]);
-        let [eta] = self.script.generate_challenges();
+        self.script.append_scalar(b"b_0_gamma", &b_0_gamma);
+        self.script.append_scalar(b"f_gamma", &f_gamma);
+        self.script.append_scalar(b"a_0", &a_0);
+        let q_gamma = q_x.map(|q_x| q_x.evaluate(&gamma));
+        if let Some(q_gamma) = &q_gamma {
+            self.script.append_scalar(b"q_gamma", q_gamma);
+        }
+        let eta = self.script.challenge_scalar(b"eta");
 
         // Step 6a
         let q_b_gamma = q_b_x.evaluate(&gamma);
-        let v = b_0_gamma + eta * f_gamma + eta * eta * q_b_gamma;
+        let mut v = b_0_gamma + eta * f_gamma + eta * eta * q_b_gamma;
         // Step 6b
-        let tmp = b_0_x + f_x.mul(eta) + q_b_x.mul(eta * eta) - DensePolynomial::from_coefficients_slice(&vec![v]);
+        let mut tmp = b_0_x + f_x.mul(eta) + q_b_x.mul(eta * eta);
+        // the selector is opened with eta^3
+        if let (Some(q_x), Some(q_gamma)) = (q_x, q_gamma) {
+            let eta_3 = eta * eta * eta;
+            v += eta_3 * q_gamma;
+            tmp += &q_x.mul(eta_3);
+        }
+        let tmp = tmp - DensePolynomial::from_coefficients_slice(&[v]);
         let h_x = tmp / DensePolynomial::from_coefficients_vec(vec![-gamma, P::ScalarField::one()]);
-        let cm1_pi_eta = cq.kzg.commit_g1(&h_x);
+        let cm1_pi_eta = pk.kzg().commit_g1(&h_x);
 
         // Step 7
-        // [A_0_X]_1 = ∑ cm1_l_i_quotient * a_i
-        let mut cm1_a_0_x = P::G1::zero();
-        for (a_i, index) in a_sparse {
-            cm1_a_0_x.add_assign(cq.cm1_l_i_quotient[*index].mul(a_i));
-        }
+        let (cm1_a_0_x, cm1_p_a_0) = compute_cm1_a_0_x(pk, a_sparse);
 
         Ok(RoundThreeResponse {
             b_0_gamma,
             f_gamma,
             a_0,
+            q_gamma,
             cm1_pi_eta,
-            cm1_a_0_x: cm1_a_0_x.into_affine(),
+            cm1_a_0_x,
+            cm1_p_a_0,
         })
 
     }
-    pub fn prove(&mut self, cq: &Cq<P>, t_i: &[P::ScalarField]) -> Result<Proof<P>, GeneralError>{
-        
-        let (f_x, cm1_f) = self.compute_cm1_fx(cq);
-
-        self.script.feed_with_commitments(&[cm1_f]);
+    // The multiplicities of the witness values as (table index, count) pairs.
+    fn multiplicities(&self, pk: &ProvingKey<P>) -> Result<Vec<(usize, usize)>, GeneralError> {
         let mut m_i_vec: Vec<(usize, usize)> = vec![];
         
         for (f_i, value) in self.f_i_map.clone() {
-            if let Some(&index) = cq.t_hash_map.get(&f_i) {
+            if let Some(&index) = pk.t_hash_map.get(&f_i) {
                 m_i_vec.push((index, value));
             } else {
                 return Err(GeneralError::WitnessNotInTable);
             }
         }
+        Ok(m_i_vec)
+    }
+
+    pub fn prove(&mut self, pk: &ProvingKey<P>) -> Result<Proof<P>, GeneralError>{
+        let m_i_vec = self.multiplicities(pk)?;
+        self.prove_with_table(pk, m_i_vec)
+    }
+
+    // A proof for a witness already committed by another protocol over the same SRS, e.g. a wire
+    // of a PLONK circuit. cm1_f must be the commitment [f(x)]_1 of the padded witness over the
+    // domain of size n, the proof carries it unchanged so the verifier can match it.
+    pub fn prove_committed(&mut self, pk: &ProvingKey<P>, cm1_f: P::G1Affine) -> Result<Proof<P>, GeneralError> {
+        let m_i_vec = self.multiplicities(pk)?;
+        self.prove_with_commitment(pk, m_i_vec, Some(cm1_f))
+    }
+
+    pub(crate) fn into_transcript(self) -> T {
+        self.script
+    }
+
+    // m_i_vec holds the multiplicities as (table index, count) pairs.
+    pub(crate) fn prove_with_table(&mut self, pk: &impl LookupTable<P>, m_i_vec: Vec<(usize, usize)>) -> Result<Proof<P>, GeneralError> {
+        self.prove_with_commitment(pk, m_i_vec, None)
+    }
+
+    // expected_cm1_f is a commitment to the witness computed elsewhere, checked against [f(x)]_1.
+    fn prove_with_commitment(
+        &mut self, pk: &impl LookupTable<P>, mut m_i_vec: Vec<(usize, usize)>, expected_cm1_f: Option<P::G1Affine>
+    ) -> Result<Proof<P>, GeneralError> {
+        let (f_i, q_i) = self.pad_witness(pk, &mut m_i_vec);
+        let small_n = f_i.len();
+        if small_n > pk.kzg().max_degree() + 1 {
+            return Err(GeneralError::WitnessLargerThanSrs);
+        }
+
+        // The statement is bound before any witness commitment.
+        self.script.append_statement(&pk.verifying_key(), small_n);
+        if let Some(q_i) = &q_i {
+            let (_, cm1_q) = self.compute_cm1_fx(pk, q_i);
+            self.script.append_point(b"cm1_q", &cm1_q);
+        }
+
+        let (f_x, cm1_f) = self.compute_cm1_fx(pk, &f_i);
+        if expected_cm1_f.is_some_and(|expected| expected != cm1_f) {
+            return Err(GeneralError::WitnessCommitmentMismatch);
+        }
+
+        self.script.append_point(b"cm1_f", &cm1_f);
 
         // Round 1
         // compute m_x_1
-        let cm1_m = self.compute_cm1_mx(cq, &m_i_vec);
+        let cm1_m = compute_cm1_mx(pk, &m_i_vec);
         // send m_x_1 to verifier
-        self.script.feed_with_commitments(&[cm1_m]);
+        self.script.append_point(b"cm1_m", &cm1_m);
 
         // Round 2
-        let [beta] = self.script.generate_challenges();
+        let beta = self.script.challenge_scalar(b"beta");
         // let beta = P::ScalarField::one();
-        let proof_2 = self.compute_round_2(cq, &f_x, &m_i_vec, t_i, beta)?;
+        let proof_2 = self.compute_round_2(pk, &f_i, q_i.as_deref(), &f_x, &m_i_vec, beta)?;
 
         // send cm1_a, cm1_q_a, cm1_b_0, cm1_q_b, cm1_p to verifier
-        self.script.feed_with_commitments(&[
-            proof_2.cm1_a,
-            proof_2.cm1_q_a,
-            proof_2.cm1_b_0,
-            proof_2.cm1_q_b,
-            proof_2.cm1_p,
-        ]);
+        self.script.append_point(b"cm1_a", &proof_2.cm1_a);
+        self.script.append_point(b"cm1_q_a", &proof_2.cm1_q_a);
+        self.script.append_point(b"cm1_b_0", &proof_2.cm1_b_0);
+        self.script.append_point(b"cm1_q_b", &proof_2.cm1_q_b);
+        self.script.append_point(b"cm1_p", &proof_2.cm1_p);
 
 
         // Round 3
-        let [gamma] = self.script.generate_challenges();
+        let gamma = self.script.challenge_scalar(b"gamma");
 
-        let b_0 = proof_2.b_x.evaluate(&P::ScalarField::zero());
-        let proof_3 = self.compute_round_3(cq, gamma, b_0, &proof_2.q_b_x, &f_x, &proof_2.b_0_x, &proof_2.a_sparse).unwrap();
+        let proof_3 = self.compute_round_3(pk, small_n, gamma, &f_x, &proof_2).unwrap();
 
         Ok(Proof {
-            small_n: self.f_i.len(),
+            small_n,
             cm1_f,
             cm1_m,
             cm1_a: proof_2.cm1_a,
@@ -297,8 +488,10 @@ impl <T: Digest + Default, P: Pairing> Prover<T, P> {
             b_0_gamma: proof_3.b_0_gamma,
             f_gamma: proof_3.f_gamma,
             a_0: proof_3.a_0,
+            q_gamma: proof_3.q_gamma,
             cm1_pi_eta: proof_3.cm1_pi_eta,
             cm1_a_0_x: proof_3.cm1_a_0_x,
+            cm1_p_a_0: proof_3.cm1_p_a_0,
         })
     }
 }
@@ -311,41 +504,36 @@ mod tests {
     use ark_poly::univariate::DensePolynomial;
     use crate::cq::Cq;
     use crate::prover::Prover;
-    use crate::fiat_shamir::Script;
+    use crate::fiat_shamir::{Script, Transcript, CQ_PROTOCOL_LABEL};
     use ark_ec::pairing::Pairing;
-    use ark_ff::One;
+    use ark_ff::{Field, One};
+    use ark_poly::Polynomial;
     use sha2::Sha256;
 
     #[test]
     fn test_prover_initialization() {
         let f_i = vec![Fr::from(1), Fr::from(3), Fr::from(2), Fr::from(3)];
-        let prover = Prover::<Sha256, Bls12_381>::new(f_i).unwrap();
+        let prover = Prover::<Bls12_381>::new(f_i).unwrap();
 
         assert_eq!(prover.f_i.len(), 4);
         assert_eq!(prover.f_i_map.len(), 3);
-        for (k, v) in prover.f_i_map {
-            if k == Fr::from(1) {
-                assert_eq!(v, 1);
-            } else if k == Fr::from(2) {
-                assert_eq!(v, 1);
-            } else if k == Fr::from(3) {
-                assert_eq!(v, 2);
-            }
-        }
+        assert_eq!(prover.f_i_map[&Fr::from(1)], 1);
+        assert_eq!(prover.f_i_map[&Fr::from(2)], 1);
+        assert_eq!(prover.f_i_map[&Fr::from(3)], 2);
     }
 
     #[test]
     fn test_round_1() {
         let t_i = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
         let f_i = vec![Fr::from(1), Fr::from(3), Fr::from(3), Fr::from(3)];
-        let cq = Cq::<Bls12_381>::new(&t_i).unwrap();
-        let mut prover = Prover::<Sha256, Bls12_381>::new(f_i).unwrap();
-        let proof = prover.prove(&cq, &t_i).unwrap();
+        let Cq { pk, .. } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bls12_381>::new(f_i).unwrap();
+        let proof = prover.prove(&pk).unwrap();
 
         let m_i = vec![Fr::from(1), Fr::from(0), Fr::from(3), Fr::from(0)];
         let domain = GeneralEvaluationDomain::<Fr>::new(4).unwrap();
         let m_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&m_i));
-        let cm1_mx2 = cq.kzg.commit_g1(&m_x);
+        let cm1_mx2 = pk.kzg.commit_g1(&m_x);
 
         assert_eq!(proof.cm1_m, cm1_mx2);
     }
@@ -354,16 +542,17 @@ mod tests {
     fn test_round_2() {
         let t_i = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
         let f_i = vec![Fr::from(1), Fr::from(3), Fr::from(3), Fr::from(3)];
-        let cq = Cq::<Bls12_381>::new(&t_i).unwrap();
-        let mut prover = Prover::<Sha256, Bls12_381>::new(f_i).unwrap();
-        let proof = prover.prove(&cq, &t_i).unwrap();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bls12_381>::new(f_i).unwrap();
+        let proof = prover.prove(&pk).unwrap();
 
         let m_i = vec![Fr::from(1), Fr::from(0), Fr::from(3), Fr::from(0)];
 
-        let mut script = Script::<Sha256, Bls12_381>::new();
-        script.feed_with_commitments(&[proof.cm1_f]);
-        script.feed_with_commitments(&[proof.cm1_m]);
-        let [beta] = script.generate_challenges();
+        let mut script = Script::<Sha256, Bls12_381>::new(CQ_PROTOCOL_LABEL);
+        script.append_statement(&vk, proof.small_n);
+        script.append_point(b"cm1_f", &proof.cm1_f);
+        script.append_point(b"cm1_m", &proof.cm1_m);
+        let beta = script.challenge_scalar(b"beta");
         let mut vec_a = vec![];
         for (index, value) in m_i.iter().enumerate() {
             let v = (*value).div(t_i[index] + beta);
@@ -372,16 +561,16 @@ mod tests {
         let domain = GeneralEvaluationDomain::<Fr>::new(4).unwrap();
         let a_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&vec_a));
         let z_v_x: DensePolynomial<Fr> = domain.vanishing_polynomial().into();
-        let z_v_2 = cq.kzg.commit_g2(&z_v_x);
+        let z_v_2 = pk.kzg.commit_g2(&z_v_x);
         let t_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&t_i));
-        let t_x_2 = cq.kzg.commit_g2(&t_x);
+        let t_x_2 = pk.kzg.commit_g2(&t_x);
         let m_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&m_i));
         let q_a_x = a_x.clone().mul(t_x + DensePolynomial::from_coefficients_vec(vec![beta])).sub(m_x).div(z_v_x);
         let m_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&m_i));
-        let cm_m = cq.kzg.commit_g1(&m_x);
-        let cm = cq.kzg.commit_g1(&a_x);
-        let cm2 = cq.kzg.commit_g1(&q_a_x);
-        let cm2_1 = cq.kzg.commit_g2(&DensePolynomial::from_coefficients_vec(vec![Fr::one()]));
+        let cm_m = pk.kzg.commit_g1(&m_x);
+        let cm = pk.kzg.commit_g1(&a_x);
+        let cm2 = pk.kzg.commit_g1(&q_a_x);
+        let cm2_1 = pk.kzg.commit_g2(&DensePolynomial::from_coefficients_vec(vec![Fr::one()]));
 
         assert_eq!(proof.cm1_a, cm);
         assert_eq!(proof.cm1_q_a, cm2);
@@ -391,4 +580,50 @@ mod tests {
         let rhs = Bls12_381::multi_pairing([proof.cm1_q_a, tmp.into()], [z_v_2, cm2_1]);
         assert_eq!(lhs, rhs);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_challenges_replayed_from_proof() {
+        // The verifier derives beta, gamma and eta from the proof alone with hash_to_field,
+        // they must be the ones the prover used.
+        let t_i: Vec<Fr> = (1..=8).map(Fr::from).collect();
+        let f_i = vec![Fr::from(2), Fr::from(5), Fr::from(5), Fr::from(8)];
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bls12_381>::new(f_i.clone()).unwrap();
+        let proof = prover.prove(&pk).unwrap();
+
+        let mut script = Script::<Sha256, Bls12_381>::new(CQ_PROTOCOL_LABEL);
+        script.append_statement(&vk, proof.small_n);
+        script.append_point(b"cm1_f", &proof.cm1_f);
+        script.append_point(b"cm1_m", &proof.cm1_m);
+        let beta = script.challenge_scalar(b"beta");
+        script.append_point(b"cm1_a", &proof.cm1_a);
+        script.append_point(b"cm1_q_a", &proof.cm1_q_a);
+        script.append_point(b"cm1_b_0", &proof.cm1_b_0);
+        script.append_point(b"cm1_q_b", &proof.cm1_q_b);
+        script.append_point(b"cm1_p", &proof.cm1_p);
+        let gamma = script.challenge_scalar(b"gamma");
+        script.append_scalar(b"b_0_gamma", &proof.b_0_gamma);
+        script.append_scalar(b"f_gamma", &proof.f_gamma);
+        script.append_scalar(b"a_0", &proof.a_0);
+        let eta = script.challenge_scalar(b"eta");
+
+        let domain = GeneralEvaluationDomain::<Fr>::new(f_i.len()).unwrap();
+        let f_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&f_i));
+        let b_i: Vec<Fr> = f_i.iter().map(|f| (*f + beta).inverse().unwrap()).collect();
+        let b_x = DensePolynomial::from_coefficients_slice(&domain.ifft(&b_i));
+        let b_0_x = DensePolynomial::from_coefficients_slice(&b_x.coeffs[1..]);
+        let f_x_plus_beta = &f_x + &DensePolynomial::from_coefficients_vec(vec![beta]);
+        let (q_b_x, _) = (&b_x * &f_x_plus_beta - DensePolynomial::from_coefficients_vec(vec![Fr::one()]))
+            .divide_by_vanishing_poly(domain);
+
+        // beta
+        assert_eq!(proof.cm1_b_0, pk.kzg.commit_g1(&b_0_x));
+        // gamma
+        assert_eq!(proof.f_gamma, f_x.evaluate(&gamma));
+        assert_eq!(proof.b_0_gamma, b_0_x.evaluate(&gamma));
+        // eta
+        let c_x = &(&b_0_x + &f_x.mul(eta)) + &q_b_x.mul(eta * eta);
+        let (cm1_pi_eta, _) = pk.kzg.open_g1(&c_x, gamma);
+        assert_eq!(proof.cm1_pi_eta, cm1_pi_eta);
+    }
+}
@@ -0,0 +1,267 @@
+// Loader for the powers-of-tau files produced by snarkjs (.ptau).
+// https://github.com/iden3/snarkjs/blob/master/src/powersoftau_new.js
+//
+// Layout (all integers little-endian):
+//   "ptau" | version: u32 | number of sections: u32
+//   sections: type: u32 | size: u64 | data
+// Section 1 (header): n8: u32 | q: n8 bytes | power: u32 | ceremony power: u32
+// Section 2 (tauG1): 2^power * 2 - 1 points of G1
+// Section 3 (tauG2): 2^power points of G2
+// Coordinates are n8 bytes in Montgomery form, Fq2 elements are c0 | c1, the point at infinity is all zeros.
+
+use std::cmp::Ordering;
+use std::io::{self, Read};
+use ark_ec::pairing::Pairing;
+use ark_ec::short_weierstrass::{Affine, SWCurveConfig};
+use ark_ff::{BigInteger, Field, PrimeField, Zero};
+use rand::thread_rng;
+use crate::errors::SrsError;
+use crate::kzg::Kzg;
+
+const MAGIC: &[u8; 4] = b"ptau";
+const HEADER_SECTION: u32 = 1;
+const TAU_G1_SECTION: u32 = 2;
+const TAU_G2_SECTION: u32 = 3;
+
+struct PtauHeader {
+    n8: usize,
+    power: u32,
+}
+
+impl <P: Pairing> Kzg<P> {
+    // Load the first g1_len powers in G1 and g2_len powers in G2 from a snarkjs .ptau file.
+    // The points are validated and the powers are checked to be consistent.
+    pub fn from_ptau<R: Read, G1: SWCurveConfig, G2: SWCurveConfig>(mut reader: R, g1_len: usize, g2_len: usize) -> Result<Self, SrsError>
+    where
+        P: Pairing<G1Affine = Affine<G1>, G2Affine = Affine<G2>>,
+    {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SrsError::MalformedPtau("missing ptau magic".to_string()));
+        }
+        let _version = read_u32(&mut reader)?;
+        let num_sections = read_u32(&mut reader)?;
+
+        let mut header = None;
+        let mut g1_srs = None;
+        let mut g2_srs = None;
+        for _ in 0..num_sections {
+            let section_type = read_u32(&mut reader)?;
+            let size = read_u64(&mut reader)?;
+            let mut section = (&mut reader).take(size);
+
+            match section_type {
+                HEADER_SECTION => header = Some(read_header::<_, G1>(&mut section)?),
+                TAU_G1_SECTION | TAU_G2_SECTION => {
+                    let header = header.as_ref()
+                        .ok_or_else(|| SrsError::MalformedPtau("points section before the header".to_string()))?;
+                    if section_type == TAU_G1_SECTION {
+                        let available = (1usize << header.power) * 2 - 1;
+                        g1_srs = Some(read_points::<_, G1>(&mut section, header.n8, g1_len, available, "G1")?);
+                    } else {
+                        let available = 1usize << header.power;
+                        g2_srs = Some(read_points::<_, G2>(&mut section, header.n8, g2_len, available, "G2")?);
+                    }
+                }
+                _ => {}
+            }
+            // skip the rest of the section
+            io::copy(&mut section, &mut io::sink())?;
+            if section.limit() != 0 {
+                return Err(SrsError::MalformedPtau("truncated section".to_string()));
+            }
+        }
+
+        let kzg = Self {
+            g1_srs: g1_srs.ok_or_else(|| SrsError::MalformedPtau("missing tauG1 section".to_string()))?,
+            g2_srs: g2_srs.ok_or_else(|| SrsError::MalformedPtau("missing tauG2 section".to_string()))?,
+        };
+        kzg.check_powers(&mut thread_rng())?;
+        Ok(kzg)
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_header<R: Read, C: SWCurveConfig>(reader: &mut R) -> Result<PtauHeader, SrsError> {
+    // snarkjs writes the base field elements on whole 64-bit limbs, as arkworks does
+    let modulus = <C::BaseField as Field>::BasePrimeField::MODULUS.to_bytes_le();
+    let n8 = read_u32(reader)? as usize;
+    if n8 != modulus.len() {
+        return Err(SrsError::MalformedPtau(format!("{} byte field elements, expected {}", n8, modulus.len())));
+    }
+    let mut q = vec![0u8; n8];
+    reader.read_exact(&mut q)?;
+    let power = read_u32(reader)?;
+    let _ceremony_power = read_u32(reader)?;
+
+    if q != modulus {
+        return Err(SrsError::MalformedPtau("the ptau file is for a different curve".to_string()));
+    }
+    if power >= usize::BITS - 1 {
+        return Err(SrsError::MalformedPtau(format!("unsupported power {}", power)));
+    }
+    Ok(PtauHeader { n8, power })
+}
+
+fn read_points<R: Read, C: SWCurveConfig>(reader: &mut R, n8: usize, len: usize, available: usize, group: &'static str) -> Result<Vec<Affine<C>>, SrsError> {
+    if available < len {
+        return Err(SrsError::TooShort { group, needed: len, available });
+    }
+    // Montgomery form uses R = 2^(8·n8)
+    let r_inv = <C::BaseField as Field>::BasePrimeField::from(2u64).pow([8 * n8 as u64]).inverse().unwrap();
+    let degree = C::BaseField::extension_degree() as usize;
+    let modulus = <C::BaseField as Field>::BasePrimeField::MODULUS.to_bytes_le();
+
+    let mut bytes = vec![0u8; n8];
+    let mut read_coordinate = |reader: &mut R| -> Result<C::BaseField, SrsError> {
+        let mut limbs = Vec::with_capacity(degree);
+        for _ in 0..degree {
+            reader.read_exact(&mut bytes)?;
+            // each point has a single encoding, compared as little-endian integers
+            if bytes.iter().rev().cmp(modulus.iter().rev()) != Ordering::Less {
+                return Err(SrsError::MalformedPtau("coordinate not reduced modulo q".to_string()));
+            }
+            limbs.push(<C::BaseField as Field>::BasePrimeField::from_le_bytes_mod_order(&bytes) * r_inv);
+        }
+        Ok(C::BaseField::from_base_prime_field_elems(limbs).unwrap())
+    };
+
+    let mut points = Vec::with_capacity(len);
+    for _ in 0..len {
+        let x = read_coordinate(reader)?;
+        let y = read_coordinate(reader)?;
+        let point = if x.is_zero() && y.is_zero() {
+            Affine::<C>::identity()
+        } else {
+            Affine::<C>::new_unchecked(x, y)
+        };
+        if !point.is_on_curve() || !point.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(SrsError::InvalidPoint);
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bn254::{Bn254, Fq, Fr, G1Affine, G2Affine};
+    use ark_ec::AffineRepr;
+    use ark_ff::{BigInteger, Field, PrimeField};
+    use crate::errors::SrsError;
+    use crate::kzg::Kzg;
+    use super::*;
+
+    fn write_fq(bytes: &mut Vec<u8>, x: Fq) {
+        let montgomery = x * Fq::from(2u64).pow([256]);
+        bytes.extend(montgomery.into_bigint().to_bytes_le());
+    }
+
+    fn write_g1(bytes: &mut Vec<u8>, p: &G1Affine) {
+        let (x, y) = p.xy().unwrap_or_default();
+        write_fq(bytes, x);
+        write_fq(bytes, y);
+    }
+
+    fn write_g2(bytes: &mut Vec<u8>, p: &G2Affine) {
+        let (x, y) = p.xy().unwrap_or_default();
+        for c in [x.c0, x.c1, y.c0, y.c1] {
+            write_fq(bytes, c);
+        }
+    }
+
+    fn write_section(bytes: &mut Vec<u8>, section_type: u32, data: &[u8]) {
+        bytes.extend(section_type.to_le_bytes());
+        bytes.extend((data.len() as u64).to_le_bytes());
+        bytes.extend(data);
+    }
+
+    // A ptau file of the given power, as produced by snarkjs for BN254.
+    fn ptau_file(kzg: &Kzg<Bn254>, power: u32) -> Vec<u8> {
+        let mut header = 32u32.to_le_bytes().to_vec();
+        header.extend(Fq::MODULUS.to_bytes_le());
+        header.extend(power.to_le_bytes());
+        header.extend(power.to_le_bytes());
+
+        let mut tau_g1 = Vec::new();
+        kzg.g1_srs[..(1 << power) * 2 - 1].iter().for_each(|p| write_g1(&mut tau_g1, p));
+        let mut tau_g2 = Vec::new();
+        kzg.g2_srs[..1 << power].iter().for_each(|p| write_g2(&mut tau_g2, p));
+
+        let mut file = b"ptau".to_vec();
+        file.extend(1u32.to_le_bytes());
+        file.extend(4u32.to_le_bytes());
+        write_section(&mut file, HEADER_SECTION, &header);
+        write_section(&mut file, TAU_G1_SECTION, &tau_g1);
+        write_section(&mut file, TAU_G2_SECTION, &tau_g2);
+        // sections the loader does not use are skipped
+        write_section(&mut file, 4, &[0u8; 64]);
+        file
+    }
+
+    #[test]
+    fn test_from_ptau() {
+        let kzg = Kzg::<Bn254>::new_from_secret(Fr::from(1234), 16);
+        let file = ptau_file(&kzg, 3);
+
+        let loaded = Kzg::<Bn254>::from_ptau(file.as_slice(), 8, 9).unwrap_err();
+        assert!(matches!(loaded, SrsError::TooShort { group: "G2", needed: 9, available: 8 }));
+
+        let loaded = Kzg::<Bn254>::from_ptau(file.as_slice(), 15, 8).unwrap();
+        assert_eq!(loaded.g1_srs, kzg.g1_srs[..15]);
+        assert_eq!(loaded.g2_srs, kzg.g2_srs[..8]);
+    }
+
+    #[test]
+    fn test_from_ptau_rejects_bad_files() {
+        let kzg = Kzg::<Bn254>::new_from_secret(Fr::from(1234), 16);
+        let file = ptau_file(&kzg, 3);
+
+        let mut bad_magic = file.clone();
+        bad_magic[0] = b'x';
+        assert!(matches!(Kzg::<Bn254>::from_ptau(bad_magic.as_slice(), 4, 4), Err(SrsError::MalformedPtau(_))));
+
+        let truncated = &file[..file.len() / 2];
+        assert!(Kzg::<Bn254>::from_ptau(truncated, 4, 4).is_err());
+
+        // a BN254 file loaded as BLS12-381
+        let wrong_curve = Kzg::<ark_bls12_381::Bls12_381>::from_ptau(file.as_slice(), 4, 4);
+        assert!(matches!(wrong_curve, Err(SrsError::MalformedPtau(_))));
+
+        // flip a byte of the second G1 point: no longer on the curve
+        let mut off_curve = file.clone();
+        let g1_start = 4 + 4 + 4 + (4 + 8 + 4 + 32 + 4 + 4) + (4 + 8);
+        off_curve[g1_start + 64] ^= 1;
+        assert!(matches!(Kzg::<Bn254>::from_ptau(off_curve.as_slice(), 4, 4), Err(SrsError::InvalidPoint)));
+
+        // the same point with its x coordinate written as x·R + q
+        let mut unreduced = file.clone();
+        let mut x = (kzg.g1_srs[1].x().unwrap() * Fq::from(2u64).pow([256])).into_bigint();
+        x.add_with_carry(&Fq::MODULUS);
+        unreduced[g1_start + 64..g1_start + 96].copy_from_slice(&x.to_bytes_le());
+        assert!(matches!(Kzg::<Bn254>::from_ptau(unreduced.as_slice(), 4, 4), Err(SrsError::MalformedPtau(_))));
+
+        // field elements of another size, rejected before reading them
+        let mut bad_n8 = file.clone();
+        bad_n8[12 + 12..12 + 16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Kzg::<Bn254>::from_ptau(bad_n8.as_slice(), 4, 4), Err(SrsError::MalformedPtau(_))));
+
+        // a valid point that is not the right power
+        let mut bad_kzg = kzg.clone();
+        bad_kzg.g1_srs[2] = kzg.g1_srs[3];
+        let file = ptau_file(&bad_kzg, 3);
+        assert!(matches!(Kzg::<Bn254>::from_ptau(file.as_slice(), 4, 4), Err(SrsError::InconsistentPowers)));
+    }
+}
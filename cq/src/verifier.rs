@@ -1,229 +1,466 @@
-use std::ops::Add;
-use ark_ec::CurveGroup;
+use std::collections::BTreeMap;
+use std::ops::{Add, AddAssign};
+use ark_ec::{AffineRepr, CurveGroup};
 use std::ops::{Mul, Sub};
 use ark_ec::pairing::{Pairing};
-use ark_ff::{Field, One, Zero};
-use ark_poly::{DenseUVPolynomial};
-use ark_poly::univariate::DensePolynomial;
-use sha2::Digest;
-use crate::cq::Cq;
-use crate::errors::GeneralError;
-use crate::fiat_shamir::Script;
+use ark_ff::{Field, One, UniformRand, Zero};
+use std::marker::PhantomData;
+use rand::thread_rng;
+use sha2::Sha256;
+use crate::accumulator::PairingAccumulator;
+use crate::cq::PreparedVerifyingKey;
+use crate::errors::VerificationError;
+use crate::fiat_shamir::{Script, Transcript, CQ_PROTOCOL_LABEL};
 use crate::prover::Proof;
 
-pub struct Verifier<T: Digest + Default, P: Pairing> {
-    script: Script<T, P>
+// The transcript must match the one the proof was generated with.
+pub struct Verifier<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    script: T,
+    // [q(x)]_1 of the selector of gated lookups
+    cm1_q: Option<P::G1Affine>,
+    _phantom_data_p: PhantomData<P>,
 }
 
-impl <T: Digest + Default, P: Pairing> Verifier<T, P> {
+// Challenges and derived values the verifier recomputes from the proof.
+pub(crate) struct RoundValues<P: Pairing> {
+    pub(crate) beta: P::ScalarField,
+    // c - [v]_1 + gamma * [pi_eta]_1
+    pub(crate) cm1_kzg_lhs: P::G1,
+    // [a_0]_1
+    pub(crate) cm1_a_0: P::G1,
+}
+
+// The G1 sides of the batched checks, one for each G2 element they are paired with.
+pub(crate) struct PairingTerms<P: Pairing> {
+    // paired with [1]_2
+    pub(crate) cm1_1: P::G1,
+    // paired with [x]_2
+    pub(crate) cm1_x: P::G1,
+    // paired with [x^{D-(n-2)}]_2
+    pub(crate) cm1_x_pow: P::G1,
+    // paired with [T(x)]_2
+    pub(crate) cm1_t_x: P::G1,
+    // paired with [Zv(x)]_2
+    pub(crate) cm1_z_v: P::G1,
+    // paired with [x^{D-(N-2)}]_2
+    pub(crate) cm1_a_0_degree: P::G1,
+}
+
+impl <P: Pairing> AddAssign for PairingTerms<P> {
+    fn add_assign(&mut self, other: Self) {
+        self.cm1_1 += other.cm1_1;
+        self.cm1_x += other.cm1_x;
+        self.cm1_x_pow += other.cm1_x_pow;
+        self.cm1_t_x += other.cm1_t_x;
+        self.cm1_z_v += other.cm1_z_v;
+        self.cm1_a_0_degree += other.cm1_a_0_degree;
+    }
+}
+
+// mu^0..mu^4, all multiplied by scale.
+pub(crate) fn scaled_mu_powers<F: Field>(mu: F, scale: F) -> [F; 5] {
+    let mut mu_powers = [scale; 5];
+    for i in 1..5 {
+        mu_powers[i] = mu_powers[i - 1] * mu;
+    }
+    mu_powers
+}
+
+// first, first·step, first·step^2, .., len values.
+pub(crate) fn powers<F: Field>(first: F, step: F, len: usize) -> Vec<F> {
+    let mut res = Vec::with_capacity(len);
+    let mut power = first;
+    for _ in 0..len {
+        res.push(power);
+        power *= step;
+    }
+    res
+}
+
+// Whether the product of the pairings of many batched checks is one. terms holds, for each check,
+// the index of its verifying key in pvks and its witness size. The [1]_2 and [x]_2 terms are
+// summed over all the checks, the other terms over the checks with the same key and witness size,
+// so the multi-pairing has 2 pairs plus 4 for each distinct (key, size).
+pub(crate) fn combined_pairing_is_one<P: Pairing>(pvks: &[&PreparedVerifyingKey<P>], terms: Vec<(usize, usize, PairingTerms<P>)>) -> bool {
+    let mut groups = BTreeMap::<(usize, usize), PairingTerms<P>>::new();
+    for (index, small_n, terms) in terms {
+        match groups.get_mut(&(index, small_n)) {
+            Some(group) => *group += terms,
+            None => {
+                groups.insert((index, small_n), terms);
+            }
+        }
+    }
+
+    let mut cm1_1 = P::G1::zero();
+    let mut cm1_x = P::G1::zero();
+    let mut g1 = vec![];
+    let mut g2 = vec![];
+    for ((index, small_n), terms) in groups {
+        let pvk = pvks[index];
+        cm1_1 += terms.cm1_1;
+        cm1_x += terms.cm1_x;
+        g1.extend([terms.cm1_x_pow, terms.cm1_t_x, terms.cm1_z_v, terms.cm1_a_0_degree]);
+        g2.extend([
+            pvk.degree_check_g2(small_n).unwrap().clone(),
+            pvk.t_x_2.clone(),
+            pvk.z_v_2.clone(),
+            pvk.degree_check_g2(pvk.vk.big_n).unwrap().clone(),
+        ]);
+    }
+    g1.extend([cm1_1, cm1_x]);
+    g2.extend([pvks[0].cm2_1.clone(), pvks[0].cm2_x.clone()]);
+
+    P::multi_pairing(P::G1::normalize_batch(&g1), g2).is_zero()
+}
+
+impl <P: Pairing, T: Transcript<P>> Verifier<P, T> {
     pub fn new() -> Self {
+        Self::with_transcript(T::new(CQ_PROTOCOL_LABEL))
+    }
+
+    // A verifier continuing a transcript that already absorbed some messages.
+    pub(crate) fn with_transcript(script: T) -> Self {
         Self {
-            script: Script::<T, P>::new()
+            script,
+            cm1_q: None,
+            _phantom_data_p: PhantomData,
         }
     }
-    
-    pub fn verify(&mut self, t_i: &[P::ScalarField], cq: &Cq<P>, proof: Proof<P>) -> Result<bool, GeneralError> {
-        let Proof {
+
+    // A verifier for proofs of Prover::new_gated, given the commitment to the padded selector.
+    pub fn new_gated(cm1_q: P::G1Affine) -> Self {
+        let mut verifier = Self::new();
+        verifier.cm1_q = Some(cm1_q);
+        verifier
+    }
+
+    // Returns [X^{D - (n-2)}]_2 if the witness size is supported by the verifying key.
+    pub(crate) fn check_sizes(pvk: &PreparedVerifyingKey<P>, small_n: usize) -> Result<&P::G2Prepared, VerificationError> {
+        pvk.degree_check_g2(small_n).ok_or(VerificationError::MalformedSizes {
             small_n,
-            cm1_f,
-            cm1_m,
-            cm1_a,
-            cm1_q_a,
-            cm1_b_0,
-            cm1_q_b,
-            cm1_p,
-            b_0_gamma,
-            f_gamma,
-            a_0,
-            cm1_pi_eta,
-            cm1_a_0_x,
-        } = proof;
-        
-        if !small_n.is_power_of_two() {
-            return Err(GeneralError::WitnessSizeNotPowerOf2);
-        } else if !t_i.len().is_power_of_two() {
-            return Err(GeneralError::TableSizeNotPowerOf2);
+            big_n: pvk.vk.big_n,
+        })
+    }
+
+    // The [1]_1, [1]_2 and [x]_2 of the keys must match to merge the terms paired with [1]_2 and [x]_2.
+    pub(crate) fn check_shared_srs(pvks: &[&PreparedVerifyingKey<P>]) -> Result<(), VerificationError> {
+        let vk_0 = &pvks[0].vk;
+        if pvks.iter().any(|pvk| (pvk.vk.cm1_1, pvk.vk.cm2_1, pvk.vk.cm2_x) != (vk_0.cm1_1, vk_0.cm2_1, vk_0.cm2_x)) {
+            return Err(VerificationError::SrsNotShared);
         }
-        
-        
-        self.script.feed_with_commitments(&[cm1_f]);
-        self.script.feed_with_commitments(&[cm1_m]);
+        Ok(())
+    }
+
+    pub(crate) fn into_transcript(self) -> T {
+        self.script
+    }
+
+    // Replays the transcript of the prover and computes the values used by the pairing checks.
+    pub(crate) fn compute_round_values(&mut self, pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>) -> Result<RoundValues<P>, VerificationError> {
+        let vk = &pvk.vk;
+        let small_n = proof.small_n;
+
+        self.script.append_statement(vk, small_n);
+        let selector = match (self.cm1_q, proof.q_gamma) {
+            (Some(cm1_q), Some(q_gamma)) => Some((cm1_q, q_gamma)),
+            (None, None) => None,
+            _ => return Err(VerificationError::SelectorMismatch),
+        };
+        if let Some((cm1_q, _)) = &selector {
+            self.script.append_point(b"cm1_q", cm1_q);
+        }
+        self.script.append_point(b"cm1_f", &proof.cm1_f);
+        self.script.append_point(b"cm1_m", &proof.cm1_m);
 
         // Round 2
-        let [beta] = self.script.generate_challenges();
-        
-        self.script.feed_with_commitments(&[
-            cm1_a,
-            cm1_q_a,
-            cm1_b_0,
-            cm1_q_b,
-            cm1_p,
-        ]);
+        let beta = self.script.challenge_scalar(b"beta");
 
-        // verify e(a, [T(x)]_2) = e(q_a, [Zv(x)]_2) · e(m − β · a, [1]_2)
-        let cm2_1 = cq.kzg.commit_g2(&DensePolynomial::from_coefficients_vec(vec![P::ScalarField::one()]));
+        self.script.append_point(b"cm1_a", &proof.cm1_a);
+        self.script.append_point(b"cm1_q_a", &proof.cm1_q_a);
+        self.script.append_point(b"cm1_b_0", &proof.cm1_b_0);
+        self.script.append_point(b"cm1_q_b", &proof.cm1_q_b);
+        self.script.append_point(b"cm1_p", &proof.cm1_p);
 
-        let lhs = P::pairing(cm1_a, cq.t_x_2);
-        let tmp = cm1_m - cm1_a.mul(beta).into_affine();
-        let rhs = P::multi_pairing([cm1_q_a, tmp.into()], [cq.z_v_2, cm2_1]);
-        assert_eq!(lhs, rhs, "Failed to verify e(a, [T(x)]_2) = e(q_a, [Zv(x)]_2) · e(m − β · a, [1]_2)");
+        // Round 3
+        let gamma = self.script.challenge_scalar(b"gamma");
+        self.script.append_scalar(b"b_0_gamma", &proof.b_0_gamma);
+        self.script.append_scalar(b"f_gamma", &proof.f_gamma);
+        self.script.append_scalar(b"a_0", &proof.a_0);
+        if let Some((_, q_gamma)) = &selector {
+            self.script.append_scalar(b"q_gamma", q_gamma);
+        }
+        // compute b_0:
+        let n_inv = P::ScalarField::from(small_n as u128).inverse()
+            .ok_or(VerificationError::NonInvertibleChallenge)?;
+        let b_0 = P::ScalarField::from(vk.big_n as u128) * proof.a_0 * n_inv;
 
+        // compute z_h(gamma), b_gamma, q_b_gamma, with q(gamma) = 1 without a selector
+        let z_h_gamma = gamma.pow([small_n as u64]) - P::ScalarField::one();
+        let z_h_gamma_inv = z_h_gamma.inverse().ok_or(VerificationError::NonInvertibleChallenge)?;
+        let b_gamma = proof.b_0_gamma * gamma + b_0;
+        let q_gamma = selector.map_or(P::ScalarField::one(), |(_, q_gamma)| q_gamma);
+        let q_b_gamma = (b_gamma * (proof.f_gamma + beta) - q_gamma) * z_h_gamma_inv;
 
-        // verify that B0 has the appropriate degree
-        // e(b_0, [X^{N-1 - (n-2)}]_2) = e(p, [1]_2)
+        // Step 6
+        let eta = self.script.challenge_scalar(b"eta");
+        let mut v = proof.b_0_gamma + eta * proof.f_gamma + eta * eta * q_b_gamma;
+        let mut cm1_c = proof.cm1_b_0 + proof.cm1_f.mul(eta) + proof.cm1_q_b.mul(eta * eta);
+        // the selector is opened with eta^3
+        if let Some((cm1_q, q_gamma)) = selector {
+            let eta_3 = eta * eta * eta;
+            v += eta_3 * q_gamma;
+            cm1_c += cm1_q.mul(eta_3);
+        }
+        let cm1_v = vk.cm1_1.mul(v);
+        let cm1_kzg_lhs = cm1_c.sub(cm1_v).add(proof.cm1_pi_eta.mul(gamma));
+        let cm1_a_0 = vk.cm1_1.mul(proof.a_0);
+
+        Ok(RoundValues {
+            beta,
+            cm1_kzg_lhs,
+            cm1_a_0,
+        })
+    }
 
-        let mut x_pow = vec![P::ScalarField::zero(); cq.big_n - 1 - (small_n - 2)];
-        x_pow.push(P::ScalarField::one());
-        let x_pow_poly = DensePolynomial::from_coefficients_vec(x_pow);
-        let cm2_x_pow = cq.kzg.commit_g2(&x_pow_poly);
-        let lhs = P::pairing(cm1_b_0, cm2_x_pow);
-        let rhs = P::pairing(cm1_p, cm2_1);
-        assert_eq!(lhs, rhs, "Failed to verify e(b_0, [X^(N-1 - (n-2))]_2) = e(p, [1]_2)");
+    // verify e(a, [T(x)]_2) = e(q_a, [Zv(x)]_2) · e(m − β · a, [1]_2)
+    fn check_quotient_identity(pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>, beta: P::ScalarField) -> Result<(), VerificationError> {
+        let tmp = proof.cm1_m - proof.cm1_a.mul(beta);
+        let res = P::multi_pairing(
+            [proof.cm1_a, (-proof.cm1_q_a.into_group()).into_affine(), (-tmp).into_affine()],
+            [pvk.t_x_2.clone(), pvk.z_v_2.clone(), pvk.cm2_1.clone()]
+        );
+        if !res.is_zero() {
+            return Err(VerificationError::QuotientIdentityFailed);
+        }
+        Ok(())
+    }
 
-        // Round 3
-        let [gamma] = self.script.generate_challenges();
-        self.script.feed_with_commitments(&[b_0_gamma, f_gamma, a_0]);
-        // compute b_0:
-        let n_inv = P::ScalarField::from(small_n as u128).inverse().unwrap();
-        let b_0 = P::ScalarField::from(cq.big_n as u128) * a_0 * n_inv;
+    // verify that B0 has the appropriate degree
+    // e(b_0, [X^{N-1 - (n-2)}]_2) = e(p, [1]_2)
+    fn check_degree(pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>, cm2_x_pow: &P::G2Prepared) -> Result<(), VerificationError> {
+        let res = P::multi_pairing(
+            [proof.cm1_b_0, (-proof.cm1_p.into_group()).into_affine()],
+            [cm2_x_pow.clone(), pvk.cm2_1.clone()]
+        );
+        if !res.is_zero() {
+            return Err(VerificationError::DegreeCheckFailed);
+        }
+        Ok(())
+    }
 
-        // compute z_h(gamma), b_gamma, q_b_gamma
-        let z_h_gamma = gamma.pow(&[small_n as u64]) - P::ScalarField::one();
+    // check e(c - [v]_1 + gamma * [pi_eta]_1, [1]_2) = e([pi_gamma]_1, [x]_2)
+    fn check_kzg_opening(pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>, cm1_kzg_lhs: P::G1) -> Result<(), VerificationError> {
+        let res = P::multi_pairing(
+            [cm1_kzg_lhs.into_affine(), (-proof.cm1_pi_eta.into_group()).into_affine()],
+            [pvk.cm2_1.clone(), pvk.cm2_x.clone()]
+        );
+        if !res.is_zero() {
+            return Err(VerificationError::BatchedKzgOpeningFailed);
+        }
+        Ok(())
+    }
 
-        let z_h_gamma_inv = z_h_gamma.inverse().unwrap();
-        let b_gamma = b_0_gamma * gamma + b_0;
-        let q_b_gamma = (b_gamma * (f_gamma + beta) - P::ScalarField::one()) * z_h_gamma_inv;
+    // check e(a_1 - [a_0]_1, [1]_2) = e(a0_1, [x]_2)
+    fn check_a_0(pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>, cm1_a_0: P::G1) -> Result<(), VerificationError> {
+        let lhs = proof.cm1_a.sub(cm1_a_0);
+        let res = P::multi_pairing(
+            [lhs.into_affine(), (-proof.cm1_a_0_x.into_group()).into_affine()],
+            [pvk.cm2_1.clone(), pvk.cm2_x.clone()]
+        );
+        if !res.is_zero() {
+            return Err(VerificationError::AZeroCheckFailed);
+        }
+        Ok(())
+    }
 
-        // Step 6
-        let [eta] = self.script.generate_challenges();
-        let v = b_0_gamma + eta * f_gamma + eta * eta * q_b_gamma;
+    // verify that A0 has degree at most N-2, so that A has degree at most N-1 and N·A(0) is its sum over V
+    // e(a0_x, [x^{D-(N-2)}]_2) = e(p_a_0, [1]_2)
+    fn check_a_0_degree(pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>) -> Result<(), VerificationError> {
+        let res = P::multi_pairing(
+            [proof.cm1_a_0_x, (-proof.cm1_p_a_0.into_group()).into_affine()],
+            [Self::a_0_degree_check_g2(pvk).clone(), pvk.cm2_1.clone()]
+        );
+        if !res.is_zero() {
+            return Err(VerificationError::AZeroDegreeCheckFailed);
+        }
+        Ok(())
+    }
 
-        let cm1_c = cm1_b_0 + cm1_f.mul(eta) + cm1_q_b.mul(eta * eta);
+    fn a_0_degree_check_g2(pvk: &PreparedVerifyingKey<P>) -> &P::G2Prepared {
+        pvk.degree_check_g2(pvk.vk.big_n).unwrap()
+    }
 
-        // check e(c - [v]_1 + gamma * [pi_eta]_1, [1]_2) = e([pi_gamma]_1, [x]_2)
-        let x_poly = DensePolynomial::from_coefficients_vec(vec![P::ScalarField::zero(), P::ScalarField::one()]);
-        let cm2_x = cq.kzg.commit_g2(&x_poly);
-        let cm1_v = cq.kzg.commit_g1(&DensePolynomial::from_coefficients_vec(vec![v]));
-        let lhs = P::pairing(cm1_c.sub(cm1_v).add(cm1_pi_eta.mul(gamma).into()), cm2_1);
-        let rhs = P::pairing(cm1_pi_eta, cm2_x);
-        assert_eq!(lhs, rhs, "Failed to verify e(c - [v]_1 + gamma * [pi_eta]_1, [1]_2) = e([pi_gamma]_1, [x]_2)");
+    pub(crate) fn check_all(pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>, cm2_x_pow: &P::G2Prepared, values: &RoundValues<P>) -> Result<(), VerificationError> {
+        Self::check_quotient_identity(pvk, proof, values.beta)?;
+        Self::check_degree(pvk, proof, cm2_x_pow)?;
+        Self::check_kzg_opening(pvk, proof, values.cm1_kzg_lhs)?;
+        Self::check_a_0(pvk, proof, values.cm1_a_0)?;
+        Self::check_a_0_degree(pvk, proof)
+    }
 
-        // check e(a_1 - [a_0]_1, [1]_2) = e(a0_1, [x]_2)
-        let a_0_poly = DensePolynomial::from_coefficients_vec(vec![a_0]);
-        let cm1_a_0 = cq.kzg.commit_g1(&a_0_poly);
+    pub fn verify(&mut self, pvk: &PreparedVerifyingKey<P>, proof: Proof<P>) -> Result<(), VerificationError> {
+        let cm2_x_pow = Self::check_sizes(pvk, proof.small_n)?;
+        let values = self.compute_round_values(pvk, &proof)?;
+        Self::check_all(pvk, &proof, cm2_x_pow, &values)
+    }
 
-        let lhs = P::pairing(cm1_a.sub(cm1_a_0), cm2_1);
-        let rhs = P::pairing(cm1_a_0_x, cm2_x);
-        assert_eq!(lhs, rhs, "Failed to verify e(a_1 - [a_0]_1, [1]_2) = e(a0_1, [x]_2)");
-        
-        Ok(true)
+    // Verifies a proof for a witness committed by another protocol, cm1_f being public input.
+    pub fn verify_committed(&mut self, pvk: &PreparedVerifyingKey<P>, proof: Proof<P>, cm1_f: P::G1Affine) -> Result<(), VerificationError> {
+        if proof.cm1_f != cm1_f {
+            return Err(VerificationError::WitnessCommitmentMismatch);
+        }
+        self.verify(pvk, proof)
     }
 
+    pub fn batched_verify_committed(&mut self, pvk: &PreparedVerifyingKey<P>, proof: Proof<P>, cm1_f: P::G1Affine) -> Result<(), VerificationError> {
+        if proof.cm1_f != cm1_f {
+            return Err(VerificationError::WitnessCommitmentMismatch);
+        }
+        self.batched_verify(pvk, proof)
+    }
 
     // batched verify: https://aztec.slides.com/suyashbagad_aztec/cq-lookup#/6/0/11
-    pub fn batched_verify(&mut self, t_i: &[P::ScalarField], cq: &Cq<P>, proof: Proof<P>) -> Result<bool, GeneralError> {
+    // When the aggregated check fails, the individual checks are run to report which one failed.
+    pub fn batched_verify(&mut self, pvk: &PreparedVerifyingKey<P>, proof: Proof<P>) -> Result<(), VerificationError> {
+        let cm2_x_pow = Self::check_sizes(pvk, proof.small_n)?;
+        let values = self.compute_round_values(pvk, &proof)?;
+        self.batched_check(pvk, &proof, cm2_x_pow, &values)
+    }
+
+    // The commitments the batched checks depend on, absorbed before mu.
+    pub(crate) fn append_batch_commitments(script: &mut T, proof: &Proof<P>) {
+        script.append_point(b"cm1_pi_eta", &proof.cm1_pi_eta);
+        script.append_point(b"cm1_a_0_x", &proof.cm1_a_0_x);
+        script.append_point(b"cm1_p_a_0", &proof.cm1_p_a_0);
+    }
+
+    // The pairing checks of a proof combined with the weights mu_powers.
+    pub(crate) fn batched_pairing_terms(proof: &Proof<P>, values: &RoundValues<P>, mu_powers: &[P::ScalarField; 5]) -> PairingTerms<P> {
         let Proof {
-            small_n,
-            cm1_f,
             cm1_m,
             cm1_a,
             cm1_q_a,
             cm1_b_0,
-            cm1_q_b,
             cm1_p,
-            b_0_gamma,
-            f_gamma,
-            a_0,
             cm1_pi_eta,
             cm1_a_0_x,
-        } = proof;
-
-        if !small_n.is_power_of_two() {
-            return Err(GeneralError::WitnessSizeNotPowerOf2);
-        } else if !t_i.len().is_power_of_two() {
-            return Err(GeneralError::TableSizeNotPowerOf2);
+            cm1_p_a_0,
+            ..
+        } = *proof;
+
+        let lhs_0 = values.cm1_kzg_lhs.mul(mu_powers[0]);
+        let lhs_1 = lhs_0
+            .add(cm1_a.mul(mu_powers[1])).sub(values.cm1_a_0.mul(mu_powers[1]));
+        let rhs_1 = cm1_pi_eta.mul(mu_powers[0]).add(cm1_a_0_x.mul(mu_powers[1]));
+        let lhs_2 = lhs_1.add(cm1_p.mul(mu_powers[2]));
+        let lhs_3 = lhs_2 + (cm1_m - cm1_a.mul(values.beta)).mul(mu_powers[3]);
+        let lhs_4 = lhs_3 + cm1_p_a_0.mul(mu_powers[4]);
+        PairingTerms {
+            cm1_1: -lhs_4,
+            cm1_x: rhs_1,
+            cm1_x_pow: cm1_b_0.mul(mu_powers[2]),
+            cm1_t_x: cm1_a.mul(mu_powers[3]),
+            cm1_z_v: cm1_q_a.mul(-mu_powers[3]),
+            cm1_a_0_degree: cm1_a_0_x.mul(mu_powers[4]),
         }
+    }
 
+    // All the pairing checks in one multi-pairing, combined with powers of a challenge mu.
+    pub(crate) fn batched_check(&mut self, pvk: &PreparedVerifyingKey<P>, proof: &Proof<P>, cm2_x_pow: &P::G2Prepared, values: &RoundValues<P>) -> Result<(), VerificationError> {
+        // batch commitments
+        Self::append_batch_commitments(&mut self.script, proof);
+        let mu = self.script.challenge_scalar(b"mu");
+        let mu_powers = scaled_mu_powers(mu, P::ScalarField::one());
+        let terms = Self::batched_pairing_terms(proof, values, &mu_powers);
+        if !combined_pairing_is_one(&[pvk], vec![(0, proof.small_n, terms)]) {
+            Self::check_all(pvk, proof, cm2_x_pow, values)?;
+            return Err(VerificationError::BatchedCheckFailed);
+        }
 
-        self.script.feed_with_commitments(&[cm1_f]);
-        self.script.feed_with_commitments(&[cm1_m]);
-
-        // Round 2
-        let [beta] = self.script.generate_challenges();
-
-        self.script.feed_with_commitments(&[
-            cm1_a,
-            cm1_q_a,
-            cm1_b_0,
-            cm1_q_b,
-            cm1_p,
-        ]);
-
-
-        let cm2_1 = cq.kzg.commit_g2(&DensePolynomial::from_coefficients_vec(vec![P::ScalarField::one()]));
-        let mut x_pow = vec![P::ScalarField::zero(); cq.big_n - 1 - (small_n - 2)];
-        x_pow.push(P::ScalarField::one());
-        let x_pow_poly = DensePolynomial::from_coefficients_vec(x_pow);
-        let cm2_x_pow = cq.kzg.commit_g2(&x_pow_poly);
-        // Round 3
-        let [gamma] = self.script.generate_challenges();
-        self.script.feed_with_commitments(&[b_0_gamma, f_gamma, a_0]);
-        // compute b_0:
-        let n_inv = P::ScalarField::from(small_n as u128).inverse().unwrap();
-        let b_0 = P::ScalarField::from(cq.big_n as u128) * a_0 * n_inv;
-        // compute z_h(gamma), b_gamma, q_b_gamma
-        let z_h_gamma = gamma.pow(&[small_n as u64]) - P::ScalarField::one();
-        let z_h_gamma_inv = z_h_gamma.inverse().unwrap();
-        let b_gamma = b_0_gamma * gamma + b_0;
-        let q_b_gamma = (b_gamma * (f_gamma + beta) - P::ScalarField::one()) * z_h_gamma_inv;
+        Ok(())
+    }
 
-        // Step 6
-        let [eta] = self.script.generate_challenges();
-        let v = b_0_gamma + eta * f_gamma + eta * eta * q_b_gamma;
-        let cm1_c = cm1_b_0 + cm1_f.mul(eta) + cm1_q_b.mul(eta * eta);
-        let x_poly = DensePolynomial::from_coefficients_vec(vec![P::ScalarField::zero(), P::ScalarField::one()]);
-        let cm2_x = cq.kzg.commit_g2(&x_poly);
-        let cm1_v = cq.kzg.commit_g1(&DensePolynomial::from_coefficients_vec(vec![v]));
+    // Does all the transcript and scalar work of batched_verify, and returns the pairing check
+    // in an accumulator instead of running it.
+    pub fn deferred_batched_verify(&mut self, pvk: &PreparedVerifyingKey<P>, proof: Proof<P>) -> Result<PairingAccumulator<P>, VerificationError> {
+        Self::check_sizes(pvk, proof.small_n)?;
+        let values = self.compute_round_values(pvk, &proof)?;
+        Self::append_batch_commitments(&mut self.script, &proof);
+        let mu = self.script.challenge_scalar(b"mu");
+        let mu_powers = scaled_mu_powers(mu, P::ScalarField::one());
+        let terms = Self::batched_pairing_terms(&proof, &values, &mu_powers);
+
+        let vk = &pvk.vk;
+        let degree_check_g2 = |small_n: usize| vk.cm2_x_pows[small_n.trailing_zeros() as usize];
+        let mut accumulator = PairingAccumulator::new();
+        accumulator.add_left(terms.cm1_1, vk.cm2_1);
+        accumulator.add_left(terms.cm1_x, vk.cm2_x);
+        accumulator.add_left(terms.cm1_x_pow, degree_check_g2(proof.small_n));
+        accumulator.add_left(terms.cm1_t_x, vk.t_x_2);
+        accumulator.add_left(terms.cm1_z_v, vk.z_v_2);
+        accumulator.add_left(terms.cm1_a_0_degree, degree_check_g2(vk.big_n));
+        Ok(accumulator)
+    }
 
-        let a_0_poly = DensePolynomial::from_coefficients_vec(vec![a_0]);
-        let cm1_a_0 = cq.kzg.commit_g1(&a_0_poly);
+    // Verifies many proofs against the same or different tables preprocessed with the same SRS,
+    // with either one verifying key for all the proofs or one for each proof. The batched checks
+    // of each proof are scaled by a random scalar and summed, so all the proofs share one
+    // multi-pairing and one final exponentiation. When it fails, the proofs are checked one by
+    // one to report which check failed.
+    pub fn verify_batch(pvks: &[&PreparedVerifyingKey<P>], proofs: &[Proof<P>]) -> Result<(), VerificationError> {
+        if pvks.is_empty() || (pvks.len() != 1 && pvks.len() != proofs.len()) {
+            return Err(VerificationError::TableCountMismatch);
+        }
+        Self::check_shared_srs(pvks)?;
+
+        let mut rng = thread_rng();
+        let mut checks = Vec::with_capacity(proofs.len());
+        let mut terms = Vec::with_capacity(proofs.len());
+        for (i, proof) in proofs.iter().enumerate() {
+            let index = if pvks.len() == 1 { 0 } else { i };
+            let pvk = pvks[index];
+            let cm2_x_pow = Self::check_sizes(pvk, proof.small_n)?;
+            let mut verifier = Self::new();
+            let values = verifier.compute_round_values(pvk, proof)?;
+            Self::append_batch_commitments(&mut verifier.script, proof);
+            let mu = verifier.script.challenge_scalar(b"mu");
+            let mu_powers = scaled_mu_powers(mu, P::ScalarField::rand(&mut rng));
+            terms.push((index, proof.small_n, Self::batched_pairing_terms(proof, &values, &mu_powers)));
+            checks.push((pvk, cm2_x_pow, values));
+        }
 
-        // batch commitments
-        self.script.feed_with_commitments(&[cm1_pi_eta, cm1_a_0_x]);
-        let [mu] = self.script.generate_challenges();
-        let mut mu_powers = [P::ScalarField::one(); 5];
-        for i in 1..5 {
-            mu_powers[i] = mu_powers[i - 1] * mu;
+        if !combined_pairing_is_one(pvks, terms) {
+            for (proof, (pvk, cm2_x_pow, values)) in proofs.iter().zip(&checks) {
+                Self::check_all(pvk, proof, cm2_x_pow, values)?;
+            }
+            return Err(VerificationError::BatchedCheckFailed);
         }
-        // test
-        let lhs_1 = cm1_c.sub(cm1_v).add(cm1_pi_eta.mul(gamma).into())
-            .add(cm1_a.mul(mu_powers[1]).into()).sub(cm1_a_0.mul(mu_powers[1]).into());
-        let rhs_1 = cm1_pi_eta.add(cm1_a_0_x.mul(mu_powers[1]).into());
-        let lhs_2 = lhs_1.add(cm1_p.mul(mu_powers[2]).into());
-        let lhs_3 = lhs_2 + (cm1_m - cm1_a.mul(beta)).mul(mu_powers[3]).into();
-        let lhs = P::pairing(lhs_3, cm2_1);
-        let rhs = P::multi_pairing(
-            [rhs_1.into(), cm1_b_0.mul(mu_powers[2]).into(), cm1_a.mul(mu_powers[3]).into(), cm1_q_a.mul(-mu_powers[3]).into()],
-            [cm2_x, cm2_x_pow, cq.t_x_2, cq.z_v_2]
-        );
-        assert_eq!(lhs, rhs, "Failed to verify proof");
 
-        Ok(true)
+        Ok(())
     }
     
 }
 
+impl <P: Pairing, T: Transcript<P>> Default for Verifier<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ark_poly::EvaluationDomain;
     use ark_bls12_381::Bls12_381;
-    use ark_bls12_381::Fr;
-    use ark_ec::CurveGroup;
-    use ark_ff::Field;
+    use ark_bls12_381::{Fr, G1Affine};
+    use ark_ec::{AffineRepr, CurveGroup};
+    use ark_ff::{Field, Zero};
     use ark_poly::{DenseUVPolynomial, GeneralEvaluationDomain};
     use ark_poly::univariate::DensePolynomial;
-    use sha2::Sha256;
+    use crate::cq::{Cq, ProvingKey};
+    use crate::errors::GeneralError;
     use crate::kzg::Kzg;
+    use crate::poseidon::PoseidonTranscript;
     use crate::prover::Prover;
     use super::*;
 
@@ -231,29 +468,291 @@ mod tests {
     fn test_verify() {
         let t_i = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
         let f_i = vec![Fr::from(1), Fr::from(3), Fr::from(3), Fr::from(3)];
-        let cq = Cq::<Bls12_381>::new(&t_i).unwrap();
-        let mut prover = Prover::<Sha256, Bls12_381>::new(f_i).unwrap();
-        let proof = prover.prove(&cq, &t_i).unwrap();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bls12_381>::new(f_i).unwrap();
+        let proof = prover.prove(&pk).unwrap();
 
-        let mut verifier = Verifier::<Sha256, Bls12_381>::new();
-        let result = verifier.verify(&t_i, &cq, proof);
+        let mut verifier = Verifier::<Bls12_381>::new();
+        let result = verifier.verify(&vk.prepare(), proof);
         assert!(result.is_ok());
-        assert!(result.unwrap());
     }
 
     #[test]
     fn test_batched_verify() {
         let t_i = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
         let f_i = vec![Fr::from(1), Fr::from(3), Fr::from(3), Fr::from(3)];
-        let cq = Cq::<Bls12_381>::new(&t_i).unwrap();
-        let mut prover = Prover::<Sha256, Bls12_381>::new(f_i).unwrap();
-        let proof = prover.prove(&cq, &t_i).unwrap();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bls12_381>::new(f_i).unwrap();
+        let proof = prover.prove(&pk).unwrap();
 
-        let mut verifier = Verifier::<Sha256, Bls12_381>::new();
-        let result = verifier.batched_verify(&t_i, &cq, proof);
+        let mut verifier = Verifier::<Bls12_381>::new();
+        let result = verifier.batched_verify(&vk.prepare(), proof);
         assert!(result.is_ok());
-        assert!(result.unwrap());
     }
+
+    #[test]
+    fn test_verify_with_poseidon_transcript() {
+        let t_i = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
+        let f_i = vec![Fr::from(1), Fr::from(3), Fr::from(3), Fr::from(3)];
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let pvk = vk.prepare();
+        let mut prover = Prover::<Bls12_381, PoseidonTranscript<Bls12_381>>::new(f_i).unwrap();
+        let proof = prover.prove(&pk).unwrap();
+
+        let mut verifier = Verifier::<Bls12_381, PoseidonTranscript<Bls12_381>>::new();
+        assert!(verifier.verify(&pvk, proof.clone()).is_ok());
+        let mut verifier = Verifier::<Bls12_381, PoseidonTranscript<Bls12_381>>::new();
+        assert!(verifier.batched_verify(&pvk, proof.clone()).is_ok());
+
+        // a proof is only valid under the transcript it was generated with
+        let mut verifier = Verifier::<Bls12_381>::new();
+        assert!(verifier.verify(&pvk, proof).is_err());
+    }
+
+    fn prove_and_verify(pk: &ProvingKey<Bls12_381>, pvk: &PreparedVerifyingKey<Bls12_381>, f_i: Vec<Fr>) -> Proof<Bls12_381> {
+        let proof = Prover::<Bls12_381>::new(f_i).unwrap().prove(pk).unwrap();
+        assert!(Verifier::<Bls12_381>::new().verify(pvk, proof.clone()).is_ok());
+        assert!(Verifier::<Bls12_381>::new().batched_verify(pvk, proof.clone()).is_ok());
+        proof
+    }
+
+    #[test]
+    fn test_padded_sizes() {
+        // a table of 5 entries is padded to 8 with its last entry
+        let t_i: Vec<Fr> = (10..15).map(Fr::from).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        assert_eq!(pk.big_n, 8);
+        assert_eq!(pk.t_i[5..], [Fr::from(14); 3]);
+        let pvk = vk.prepare();
+
+        // n = 1 is padded to 2
+        let proof = prove_and_verify(&pk, &pvk, vec![Fr::from(12)]);
+        assert_eq!(proof.small_n, 2);
+        // n = 3 is padded to 4 with the first entry of the table
+        let proof = prove_and_verify(&pk, &pvk, vec![Fr::from(14), Fr::from(11), Fr::from(14)]);
+        assert_eq!(proof.small_n, 4);
+        // n = N
+        let proof = prove_and_verify(&pk, &pvk, (0..8).map(|i| Fr::from(10 + i % 5)).collect());
+        assert_eq!(proof.small_n, 8);
+
+        // n > N needs an SRS of the witness size
+        let f_i: Vec<Fr> = (0..11).map(|i| Fr::from(10 + i % 5)).collect();
+        let result = Prover::<Bls12_381>::new(f_i.clone()).unwrap().prove(&pk);
+        assert!(matches!(result, Err(GeneralError::WitnessLargerThanSrs)));
+
+        let srs = Kzg::<Bls12_381>::new(16);
+        let Cq { pk, vk } = Cq::<Bls12_381>::new_with_srs(&t_i, &srs).unwrap();
+        let pvk = vk.prepare();
+        let proof = prove_and_verify(&pk, &pvk, f_i);
+        assert_eq!(proof.small_n, 16);
+        let proof = prove_and_verify(&pk, &pvk, vec![Fr::from(13)]);
+        assert_eq!(proof.small_n, 2);
+
+        // a table with a single entry
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&[Fr::from(7)]).unwrap();
+        assert_eq!(pk.big_n, 2);
+        prove_and_verify(&pk, &vk.prepare(), vec![Fr::from(7); 2]);
+
+        assert!(matches!(Cq::<Bls12_381>::new(&[]), Err(GeneralError::EmptyTable)));
+        assert!(matches!(Prover::<Bls12_381>::new(vec![]), Err(GeneralError::EmptyWitness)));
+    }
+
+    #[test]
+    fn test_verify_against_other_table() {
+        let t_i: Vec<Fr> = (1..=8).map(Fr::from).collect();
+        let other_t_i: Vec<Fr> = (2..=9).map(Fr::from).collect();
+        let f_i = vec![Fr::from(2), Fr::from(3), Fr::from(3), Fr::from(8)];
+        let Cq { pk, .. } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let Cq { vk: other_vk, .. } = Cq::<Bls12_381>::new(&other_t_i).unwrap();
+        let mut prover = Prover::<Bls12_381>::new(f_i).unwrap();
+        let proof = prover.prove(&pk).unwrap();
+
+        let mut verifier = Verifier::<Bls12_381>::new();
+        assert!(verifier.verify(&other_vk.prepare(), proof).is_err());
+    }
+
+    #[test]
+    fn test_verify_committed() {
+        // the table and the outer protocol share one SRS
+        let kzg = Kzg::<Bls12_381>::new(32);
+        let t_i: Vec<Fr> = (0..16).map(Fr::from).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new_with_srs(&t_i, &kzg).unwrap();
+        let pvk = vk.prepare();
+
+        // a wire committed by the outer protocol, padded with t_0 to n = 8
+        let f_i = vec![Fr::from(9), Fr::from(2), Fr::from(2), Fr::from(14), Fr::from(5)];
+        let mut wire = f_i.clone();
+        wire.resize(8, t_i[0]);
+        let domain = GeneralEvaluationDomain::<Fr>::new(8).unwrap();
+        let cm1_f = kzg.commit_g1(&DensePolynomial::from_coefficients_vec(domain.ifft(&wire)));
+
+        let proof = Prover::<Bls12_381>::new(f_i.clone()).unwrap().prove_committed(&pk, cm1_f).unwrap();
+        assert_eq!(proof.cm1_f, cm1_f);
+        assert!(Verifier::<Bls12_381>::new().verify_committed(&pvk, proof.clone(), cm1_f).is_ok());
+        assert!(Verifier::<Bls12_381>::new().batched_verify_committed(&pvk, proof.clone(), cm1_f).is_ok());
+
+        // a commitment to another wire
+        let other_cm1_f = kzg.commit_g1(&DensePolynomial::from_coefficients_vec(domain.ifft(&[Fr::from(1); 8])));
+        let result = Prover::<Bls12_381>::new(f_i).unwrap().prove_committed(&pk, other_cm1_f);
+        assert!(matches!(result, Err(GeneralError::WitnessCommitmentMismatch)));
+        assert_eq!(Verifier::<Bls12_381>::new().verify_committed(&pvk, proof.clone(), other_cm1_f), Err(VerificationError::WitnessCommitmentMismatch));
+        assert_eq!(Verifier::<Bls12_381>::new().batched_verify_committed(&pvk, proof.clone(), other_cm1_f), Err(VerificationError::WitnessCommitmentMismatch));
+
+        // the proof cannot be moved to the other commitment
+        let mut moved = proof;
+        moved.cm1_f = other_cm1_f;
+        assert!(Verifier::<Bls12_381>::new().verify_committed(&pvk, moved, other_cm1_f).is_err());
+    }
+
+    #[test]
+    fn test_verify_gated() {
+        let t_i: Vec<Fr> = (0..16).map(Fr::from).collect();
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let pvk = vk.prepare();
+        let commit_selector = |q_i: &[Fr]| {
+            let mut q_i = q_i.to_vec();
+            q_i.resize(8, Fr::zero());
+            let domain = GeneralEvaluationDomain::<Fr>::new(8).unwrap();
+            pk.kzg.commit_g1(&DensePolynomial::from_coefficients_vec(domain.ifft(&q_i)))
+        };
+
+        // the unselected rows hold values outside the table
+        let f_i = vec![Fr::from(3), Fr::from(100), Fr::from(15), Fr::from(3), Fr::from(-1), Fr::from(7)];
+        let q_i = vec![Fr::from(1), Fr::from(0), Fr::from(1), Fr::from(1), Fr::from(0), Fr::from(1)];
+        let cm1_q = commit_selector(&q_i);
+        let proof = Prover::<Bls12_381>::new_gated(f_i.clone(), q_i.clone()).unwrap().prove(&pk).unwrap();
+        assert_eq!(proof.small_n, 8);
+        assert!(Verifier::<Bls12_381>::new_gated(cm1_q).verify(&pvk, proof.clone()).is_ok());
+        assert!(Verifier::<Bls12_381>::new_gated(cm1_q).batched_verify(&pvk, proof.clone()).is_ok());
+
+        // a selector that also selects a row outside the table
+        let mut wider = q_i.clone();
+        wider[1] = Fr::from(1);
+        assert!(Verifier::<Bls12_381>::new_gated(commit_selector(&wider)).verify(&pvk, proof.clone()).is_err());
+        let result = Prover::<Bls12_381>::new_gated(f_i.clone(), wider).unwrap().prove(&pk);
+        assert!(matches!(result, Err(GeneralError::WitnessNotInTable)));
+
+        // gated and plain proofs are not interchangeable
+        assert_eq!(Verifier::<Bls12_381>::new().verify(&pvk, proof), Err(VerificationError::SelectorMismatch));
+        let plain = Prover::<Bls12_381>::new(vec![Fr::from(3); 8]).unwrap().prove(&pk).unwrap();
+        assert_eq!(Verifier::<Bls12_381>::new_gated(cm1_q).verify(&pvk, plain), Err(VerificationError::SelectorMismatch));
+
+        // no row selected
+        let none = vec![Fr::zero(); 6];
+        let proof = Prover::<Bls12_381>::new_gated(f_i.clone(), none.clone()).unwrap().prove(&pk).unwrap();
+        assert!(Verifier::<Bls12_381>::new_gated(commit_selector(&none)).batched_verify(&pvk, proof).is_ok());
+
+        assert!(matches!(Prover::<Bls12_381>::new_gated(f_i.clone(), q_i[..5].to_vec()), Err(GeneralError::SelectorSizeMismatch)));
+        let mut non_binary = q_i;
+        non_binary[0] = Fr::from(2);
+        assert!(matches!(Prover::<Bls12_381>::new_gated(f_i, non_binary), Err(GeneralError::InvalidSelector)));
+    }
+
+    // The verifying key, the honest proof and the tampered proofs with their errors.
+    type TamperedProofs = (PreparedVerifyingKey<Bls12_381>, Proof<Bls12_381>, Vec<(Proof<Bls12_381>, VerificationError)>);
+
+    fn tampered_proofs() -> TamperedProofs {
+        let t_i: Vec<Fr> = (1..=8).map(Fr::from).collect();
+        let f_i = vec![Fr::from(1), Fr::from(3), Fr::from(3), Fr::from(7)];
+        let Cq { pk, vk } = Cq::<Bls12_381>::new(&t_i).unwrap();
+        let mut prover = Prover::<Bls12_381>::new(f_i).unwrap();
+        let proof = prover.prove(&pk).unwrap();
+
+        let g1 = G1Affine::generator();
+        let shift = |point: G1Affine| (point + g1).into_affine();
+        let one = Fr::one();
+
+        let mut cases = vec![];
+        let mut tamper = |f: &dyn Fn(&mut Proof<Bls12_381>), err: VerificationError| {
+            let mut tampered = proof.clone();
+            f(&mut tampered);
+            cases.push((tampered, err));
+        };
+        tamper(&|p| p.small_n = 3, VerificationError::MalformedSizes { small_n: 3, big_n: 8 });
+        tamper(&|p| p.small_n = 16, VerificationError::MalformedSizes { small_n: 16, big_n: 8 });
+        // n is part of the statement, so changing it changes every challenge
+        tamper(&|p| p.small_n = 2, VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_f = shift(p.cm1_f), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_m = shift(p.cm1_m), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_a = shift(p.cm1_a), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_q_a = shift(p.cm1_q_a), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_b_0 = shift(p.cm1_b_0), VerificationError::DegreeCheckFailed);
+        tamper(&|p| p.cm1_q_b = shift(p.cm1_q_b), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_p = shift(p.cm1_p), VerificationError::DegreeCheckFailed);
+        tamper(&|p| p.b_0_gamma += one, VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.f_gamma += one, VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.a_0 += one, VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_pi_eta = shift(p.cm1_pi_eta), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_a_0_x = shift(p.cm1_a_0_x), VerificationError::AZeroCheckFailed);
+        tamper(&|p| p.cm1_p_a_0 = shift(p.cm1_p_a_0), VerificationError::AZeroDegreeCheckFailed);
+
+        (vk.prepare(), proof, cases)
+    }
+
+    #[test]
+    fn test_verify_tampered_proofs() {
+        let (pvk, _, cases) = tampered_proofs();
+        for (proof, err) in cases {
+            let mut verifier = Verifier::<Bls12_381>::new();
+            assert_eq!(verifier.verify(&pvk, proof), Err(err));
+        }
+    }
+
+    #[test]
+    fn test_batched_verify_tampered_proofs() {
+        let (pvk, _, cases) = tampered_proofs();
+        for (proof, err) in cases {
+            let mut verifier = Verifier::<Bls12_381>::new();
+            assert_eq!(verifier.batched_verify(&pvk, proof), Err(err));
+        }
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let kzg = Kzg::<Bls12_381>::new(32);
+        let range: Vec<Fr> = (0..16).map(Fr::from).collect();
+        let squares: Vec<Fr> = (0..8u64).map(|i| Fr::from(i * i)).collect();
+        let range_cq = Cq::new_with_srs(&range, &kzg).unwrap();
+        let squares_cq = Cq::new_with_srs(&squares, &kzg).unwrap();
+        let range_pvk = range_cq.vk.prepare();
+        let squares_pvk = squares_cq.vk.prepare();
+
+        let prove = |pk: &ProvingKey<Bls12_381>, f_i: Vec<Fr>| Prover::<Bls12_381>::new(f_i).unwrap().prove(pk).unwrap();
+        let range_proofs: Vec<Proof<Bls12_381>> = (1..=16)
+            .map(|n| prove(&range_cq.pk, (0..n).map(|i| Fr::from((7 * i + n) % 16)).collect()))
+            .collect();
+        assert!(Verifier::<Bls12_381>::verify_batch(&[&range_pvk], &range_proofs).is_ok());
+        assert!(Verifier::<Bls12_381>::verify_batch(&[&range_pvk], &[]).is_ok());
+
+        // proofs against both tables, one key for each proof
+        let proofs = vec![
+            range_proofs[3].clone(),
+            prove(&squares_cq.pk, vec![Fr::from(49), Fr::from(0), Fr::from(4)]),
+            range_proofs[15].clone(),
+            prove(&squares_cq.pk, squares.clone()),
+        ];
+        let pvks = [&range_pvk, &squares_pvk, &range_pvk, &squares_pvk];
+        assert!(Verifier::<Bls12_381>::verify_batch(&pvks, &proofs).is_ok());
+
+        // a proof checked against the wrong table
+        let swapped = [&range_pvk, &squares_pvk, &squares_pvk, &range_pvk];
+        assert!(Verifier::<Bls12_381>::verify_batch(&swapped, &proofs).is_err());
+        assert_eq!(Verifier::<Bls12_381>::verify_batch(&pvks[..2], &proofs), Err(VerificationError::TableCountMismatch));
+
+        let other_pvk = Cq::<Bls12_381>::new(&squares).unwrap().vk.prepare();
+        let mixed = [&range_pvk, &other_pvk, &range_pvk, &other_pvk];
+        assert_eq!(Verifier::<Bls12_381>::verify_batch(&mixed, &proofs), Err(VerificationError::SrsNotShared));
+    }
+
+    #[test]
+    fn test_verify_batch_tampered_proofs() {
+        let (pvk, proof, cases) = tampered_proofs();
+        for (tampered, err) in cases {
+            let proofs = vec![proof.clone(), tampered, proof.clone()];
+            assert_eq!(Verifier::<Bls12_381>::verify_batch(&[&pvk], &proofs), Err(err));
+        }
+    }
+
     #[test]
     fn dummy_test_02() {
         let t_i = vec![Fr::from(1), Fr::from(2), Fr::from(3), Fr::from(4)];
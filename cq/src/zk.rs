@@ -0,0 +1,661 @@
+// A zero-knowledge variant of cq, opted into with ZkProver and ZkVerifier.
+//
+// A plain cq proof reveals unblinded commitments to f, B0 and Q_B, the evaluations f(gamma) and
+// B0(gamma), and A(0) = Σ 1/(f_i + beta) / N, so anyone who guesses the witness can check the guess.
+// Here every committed polynomial is blinded with a random multiple of its vanishing polynomial:
+// f' = f + (r0 + r1·X)·Zh, m' = m + r_m·Zv, A' = A + rho·Zv and B' = B + (s0 + s1·X + s2·X^2)·Zh,
+// so each opening reveals a uniformly random value.
+//
+// A(0) is never revealed. With k = N/n and the lift B_lift(X) = B'(X^k), which matches B on the
+// k-th roots of H, Σ_V A = Σ_H B is proven as
+//     N·A' - n·B_lift + chi·S - chi·s_sum/N = Zv·Q_S + X·W,  deg W <= N - 2,
+// for a random mask S = r·X + s_c + s_q·Zv committed in the first round with s_sum = Σ_V S,
+// and a challenge chi. The mask makes the remainder W and the quotient Q_S uniformly random.
+// B_lift is tied to B' at a random point by an opening of B_lift at the k-th roots of z = gamma^k.
+//
+// The proving key needs [T(x)]_1 to blind the quotient of A, computed once in ZkProvingKey.
+// The padded witness must not be larger than the table, and the SRS needs
+// max(N + 2k, n + 3) + 1 powers in G1.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Mul;
+use ark_ec::{AffineRepr, CurveGroup, VariableBaseMSM};
+use ark_ec::pairing::Pairing;
+use ark_ff::{Field, One, UniformRand, Zero};
+use ark_poly::{DenseUVPolynomial, EvaluationDomain, GeneralEvaluationDomain, Polynomial};
+use ark_poly::univariate::DensePolynomial;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+use crate::cq::{PreparedVerifyingKey, ProvingKey};
+use crate::errors::{GeneralError, SrsError, VerificationError};
+use crate::fiat_shamir::{Script, Transcript};
+use crate::kzg::Kzg;
+use crate::prover::{compute_cm1_a, compute_cm1_a_0_x, compute_cm1_mx};
+
+pub const ZK_PROTOCOL_LABEL: &[u8] = b"cq-zk-lookup-v1";
+
+// A proving key with [T(x)]_1, which the zero-knowledge prover needs to blind the quotient of A.
+pub struct ZkProvingKey<'a, P: Pairing> {
+    pub pk: ProvingKey<'a, P>,
+    // [T(x)]_1 = Σ t_i·[L_i(x)]_1
+    pub cm1_t_x: P::G1Affine,
+}
+
+impl <'a, P: Pairing> ZkProvingKey<'a, P> {
+    pub fn new(pk: ProvingKey<'a, P>) -> Self {
+        let cm1_t_x = P::G1::msm_unchecked(&pk.cm1_li, &pk.t_i).into_affine();
+        Self {
+            pk,
+            cm1_t_x,
+        }
+    }
+}
+
+impl <'a, P: Pairing> From<ProvingKey<'a, P>> for ZkProvingKey<'a, P> {
+    fn from(pk: ProvingKey<'a, P>) -> Self {
+        Self::new(pk)
+    }
+}
+
+#[derive(Debug, Clone, CanonicalDeserialize, CanonicalSerialize)]
+pub struct ZkProof<P: Pairing> {
+    pub small_n: usize,
+    // Round 1
+    pub cm1_f: P::G1Affine,
+    pub cm1_m: P::G1Affine,
+    // the mask S of the sum check and Σ_V S
+    pub cm1_s: P::G1Affine,
+    pub s_sum: P::ScalarField,
+    // Round 2
+    pub cm1_a: P::G1Affine,
+    pub cm1_q_a: P::G1Affine,
+    pub cm1_b: P::G1Affine,
+    pub cm1_q_b: P::G1Affine,
+    // [B'(x^k)]_1
+    pub cm1_b_lift: P::G1Affine,
+    // Round 3
+    pub cm1_q_s: P::G1Affine,
+    pub cm1_w: P::G1Affine,
+    // [x^{D-(N-2)}·W(x)]_1 for the degree check of W
+    pub cm1_p_w: P::G1Affine,
+    // Round 4
+    pub b_z: P::ScalarField,
+    pub f_z: P::ScalarField,
+    // batched opening of B', f' and Q_B' at z
+    pub cm1_pi_z: P::G1Affine,
+    // [Q(x^k)]_1 for Q(Y) = (B'(Y) - B'(z)) / (Y - z)
+    pub cm1_pi_lift: P::G1Affine,
+}
+
+// p + r(X)·(X^n - 1), for p of degree < n.
+fn add_vanishing_multiple<F: Field>(p: &DensePolynomial<F>, r: &[F], n: usize) -> DensePolynomial<F> {
+    let mut coeffs = p.coeffs.clone();
+    coeffs.resize(n + r.len(), F::zero());
+    for (j, r_j) in r.iter().enumerate() {
+        coeffs[j] -= r_j;
+        coeffs[n + j] += r_j;
+    }
+    DensePolynomial::from_coefficients_vec(coeffs)
+}
+
+// Σ c_j·[x^{start + j·step}]_1
+fn commit_strided<P: Pairing>(kzg: &Kzg<P>, coeffs: &[P::ScalarField], start: usize, step: usize) -> P::G1 {
+    let bases: Vec<P::G1Affine> = (0..coeffs.len()).map(|j| kzg.g1_srs[start + j * step]).collect();
+    P::G1::msm_unchecked(&bases, coeffs)
+}
+
+// The transcript defaults to SHA-256, as for a single proof.
+pub struct ZkProver<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    f_i: Vec<P::ScalarField>,
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl <P: Pairing, T: Transcript<P>> ZkProver<P, T> {
+    // The witness is padded to a power of two n >= 2 with the first entry of the table, as in Prover::new.
+    pub fn new(f_i: Vec<P::ScalarField>) -> Result<Self, GeneralError> {
+        if f_i.is_empty() {
+            return Err(GeneralError::EmptyWitness);
+        }
+        Ok(Self {
+            f_i,
+            _phantom_data: PhantomData,
+        })
+    }
+
+    pub fn prove(&self, zk_pk: &ZkProvingKey<P>) -> Result<ZkProof<P>, GeneralError> {
+        self.prove_with_rng(zk_pk, &mut thread_rng())
+    }
+
+    fn prove_with_rng<R: Rng>(&self, zk_pk: &ZkProvingKey<P>, rng: &mut R) -> Result<ZkProof<P>, GeneralError> {
+        let pk = &zk_pk.pk;
+        let kzg = pk.kzg.as_ref();
+        let big_n = pk.big_n;
+        let small_n = self.f_i.len().next_power_of_two().max(2);
+        if small_n > big_n {
+            return Err(GeneralError::WitnessLargerThanTable);
+        }
+        let k = big_n / small_n;
+        let needed = (big_n + 2 * k).max(small_n + 3) + 1;
+        if kzg.g1_srs.len() < needed {
+            return Err(SrsError::TooShort { group: "G1", needed, available: kzg.g1_srs.len() }.into());
+        }
+        let max_degree = kzg.max_degree();
+        let g1 = &kzg.g1_srs;
+        let cm1_z_v = g1[big_n].into_group() - g1[0];
+        let big_n_f = P::ScalarField::from(big_n as u64);
+        let small_n_f = P::ScalarField::from(small_n as u64);
+
+        let mut f_i = self.f_i.clone();
+        f_i.resize(small_n, pk.t_i[0]);
+        let mut counts = HashMap::<usize, usize>::new();
+        for f in &f_i {
+            let index = *pk.t_hash_map.get(f).ok_or(GeneralError::WitnessNotInTable)?;
+            *counts.entry(index).or_insert(0) += 1;
+        }
+        let m_i_vec: Vec<(usize, usize)> = counts.into_iter().collect();
+
+        let mut script = T::new(ZK_PROTOCOL_LABEL);
+        script.append_statement(&pk.verifying_key(), small_n);
+
+        // Round 1
+        let domain = GeneralEvaluationDomain::<P::ScalarField>::new(small_n).unwrap();
+        let f_x = DensePolynomial::from_coefficients_vec(domain.ifft(&f_i));
+        let f_blind: Vec<P::ScalarField> = (0..2).map(|_| P::ScalarField::rand(rng)).collect();
+        let f_x = add_vanishing_multiple(&f_x, &f_blind, small_n);
+        let cm1_f = kzg.commit_g1(&f_x);
+
+        let r_m = P::ScalarField::rand(rng);
+        let cm1_m = (compute_cm1_mx(pk, &m_i_vec).into_group() + cm1_z_v.mul(r_m)).into_affine();
+
+        // S = r_s·X + s_c + s_q·Zv, whose sum over V is N·s_c
+        let (r_s, s_c, s_q) = (P::ScalarField::rand(rng), P::ScalarField::rand(rng), P::ScalarField::rand(rng));
+        let cm1_s = (g1[1].mul(r_s) + g1[0].mul(s_c) + cm1_z_v.mul(s_q)).into_affine();
+        let s_sum = big_n_f * s_c;
+
+        script.append_point(b"cm1_f", &cm1_f);
+        script.append_point(b"cm1_m", &cm1_m);
+        script.append_point(b"cm1_s", &cm1_s);
+        script.append_scalar(b"s_sum", &s_sum);
+        let beta = script.challenge_scalar(b"beta");
+
+        // Round 2
+        // A' = A + rho·Zv and Q_A' = Q_A + rho·(T + beta) - r_m
+        let (a_sparse, cm1_a, cm1_q_a) = compute_cm1_a(pk, &m_i_vec, beta);
+        let rho = P::ScalarField::rand(rng);
+        let cm1_a = (cm1_a.into_group() + cm1_z_v.mul(rho)).into_affine();
+        let cm1_q_a = (cm1_q_a.into_group() + (zk_pk.cm1_t_x.into_group() + g1[0].mul(beta)).mul(rho) - g1[0].mul(r_m)).into_affine();
+
+        let b_evals: Vec<P::ScalarField> = f_i.iter().map(|f| (*f + beta).inverse().unwrap()).collect();
+        let b_x = DensePolynomial::from_coefficients_vec(domain.ifft(&b_evals));
+        let b_blind: Vec<P::ScalarField> = (0..3).map(|_| P::ScalarField::rand(rng)).collect();
+        let b_blind_x = add_vanishing_multiple(&b_x, &b_blind, small_n);
+        let cm1_b = kzg.commit_g1(&b_blind_x);
+
+        let mut f_x_plus_beta = f_x.clone();
+        f_x_plus_beta.coeffs[0] += beta;
+        let tmp = (&b_blind_x).mul(&f_x_plus_beta) - DensePolynomial::from_coefficients_vec(vec![P::ScalarField::one()]);
+        let (q_b_x, rem) = tmp.divide_by_vanishing_poly(domain);
+        if !rem.is_zero() {
+            return Err(GeneralError::CannotDivideByVanishingPolynomial);
+        }
+        let cm1_q_b = kzg.commit_g1(&q_b_x);
+        let cm1_b_lift = commit_strided(kzg, &b_blind_x.coeffs, 0, k).into_affine();
+
+        script.append_point(b"cm1_a", &cm1_a);
+        script.append_point(b"cm1_q_a", &cm1_q_a);
+        script.append_point(b"cm1_b", &cm1_b);
+        script.append_point(b"cm1_q_b", &cm1_q_b);
+        script.append_point(b"cm1_b_lift", &cm1_b_lift);
+        let chi = script.challenge_scalar(b"chi");
+
+        // Round 3
+        // W = N·A0 - n·X^{k-1}·B0(X^k) + chi·r_s, with B = X·B0 + B(0) before blinding,
+        // Q_S = N·rho - n·(s0 + s1·X^k + s2·X^{2k}) + chi·s_q
+        let (cm1_a_0_x, cm1_p_a_0) = compute_cm1_a_0_x(pk, &a_sparse);
+        let b_0_coeffs = b_x.coeffs.get(1..).unwrap_or(&[]);
+        let shift = max_degree + 2 - big_n;
+        let cm1_w = (cm1_a_0_x.mul(big_n_f) - commit_strided(kzg, b_0_coeffs, k - 1, k).mul(small_n_f) + g1[0].mul(chi * r_s)).into_affine();
+        let cm1_p_w = (cm1_p_a_0.mul(big_n_f) - commit_strided(kzg, b_0_coeffs, shift + k - 1, k).mul(small_n_f) + g1[shift].mul(chi * r_s)).into_affine();
+        let cm1_q_s = (g1[0].mul(big_n_f * rho - small_n_f * b_blind[0] + chi * s_q)
+            - g1[k].mul(small_n_f * b_blind[1]) - g1[2 * k].mul(small_n_f * b_blind[2])).into_affine();
+
+        script.append_point(b"cm1_q_s", &cm1_q_s);
+        script.append_point(b"cm1_w", &cm1_w);
+        script.append_point(b"cm1_p_w", &cm1_p_w);
+        let gamma = script.challenge_scalar(b"gamma");
+        let z = gamma.pow([k as u64]);
+
+        // Round 4
+        let b_z = b_blind_x.evaluate(&z);
+        let f_z = f_x.evaluate(&z);
+        script.append_scalar(b"b_z", &b_z);
+        script.append_scalar(b"f_z", &f_z);
+        let eta = script.challenge_scalar(b"eta");
+
+        let x_minus_z = DensePolynomial::from_coefficients_vec(vec![-z, P::ScalarField::one()]);
+        let v = b_z + eta * f_z + eta * eta * q_b_x.evaluate(&z);
+        let tmp = &b_blind_x + &(f_x.mul(eta) + q_b_x.mul(eta * eta)) - DensePolynomial::from_coefficients_vec(vec![v]);
+        let cm1_pi_z = kzg.commit_g1(&(&tmp / &x_minus_z));
+        let q_z = &(&b_blind_x - &DensePolynomial::from_coefficients_vec(vec![b_z])) / &x_minus_z;
+        let cm1_pi_lift = commit_strided(kzg, &q_z.coeffs, 0, k).into_affine();
+
+        Ok(ZkProof {
+            small_n,
+            cm1_f,
+            cm1_m,
+            cm1_s,
+            s_sum,
+            cm1_a,
+            cm1_q_a,
+            cm1_b,
+            cm1_q_b,
+            cm1_b_lift,
+            cm1_q_s,
+            cm1_w,
+            cm1_p_w,
+            b_z,
+            f_z,
+            cm1_pi_z,
+            cm1_pi_lift,
+        })
+    }
+}
+
+// Challenges the verifier recomputes from the proof.
+struct ZkRoundValues<P: Pairing> {
+    k: usize,
+    beta: P::ScalarField,
+    chi: P::ScalarField,
+    z: P::ScalarField,
+    eta: P::ScalarField,
+}
+
+// A pairing check as pairs of G1 and G2 points whose pairings multiply to one,
+// and the error reported when it fails.
+type PairingCheck<P> = (VerificationError, Vec<(<P as Pairing>::G1, <P as Pairing>::G2Prepared)>);
+
+// The transcript must match the one the proof was generated with.
+pub struct ZkVerifier<P: Pairing, T: Transcript<P> = Script<Sha256, P>> {
+    script: T,
+    _phantom_data: PhantomData<P>,
+}
+
+impl <P: Pairing, T: Transcript<P>> ZkVerifier<P, T> {
+    pub fn new() -> Self {
+        Self {
+            script: T::new(ZK_PROTOCOL_LABEL),
+            _phantom_data: PhantomData,
+        }
+    }
+
+    // The witness must be a power of two no larger than the table, with the degree checks for
+    // k = N/n, 2k and N supported by the verifying key.
+    fn check_sizes(pvk: &PreparedVerifyingKey<P>, small_n: usize) -> Result<usize, VerificationError> {
+        let big_n = pvk.vk.big_n;
+        let malformed = VerificationError::MalformedSizes { small_n, big_n };
+        if !small_n.is_power_of_two() || small_n > big_n {
+            return Err(malformed);
+        }
+        let k = big_n / small_n;
+        if [k, 2 * k, big_n].iter().any(|n| pvk.degree_check_g2(*n).is_none()) {
+            return Err(malformed);
+        }
+        Ok(k)
+    }
+
+    fn compute_round_values(&mut self, pvk: &PreparedVerifyingKey<P>, proof: &ZkProof<P>) -> Result<ZkRoundValues<P>, VerificationError> {
+        let k = Self::check_sizes(pvk, proof.small_n)?;
+        self.script.append_statement(&pvk.vk, proof.small_n);
+
+        // Round 1
+        self.script.append_point(b"cm1_f", &proof.cm1_f);
+        self.script.append_point(b"cm1_m", &proof.cm1_m);
+        self.script.append_point(b"cm1_s", &proof.cm1_s);
+        self.script.append_scalar(b"s_sum", &proof.s_sum);
+        let beta = self.script.challenge_scalar(b"beta");
+
+        // Round 2
+        self.script.append_point(b"cm1_a", &proof.cm1_a);
+        self.script.append_point(b"cm1_q_a", &proof.cm1_q_a);
+        self.script.append_point(b"cm1_b", &proof.cm1_b);
+        self.script.append_point(b"cm1_q_b", &proof.cm1_q_b);
+        self.script.append_point(b"cm1_b_lift", &proof.cm1_b_lift);
+        let chi = self.script.challenge_scalar(b"chi");
+
+        // Round 3
+        self.script.append_point(b"cm1_q_s", &proof.cm1_q_s);
+        self.script.append_point(b"cm1_w", &proof.cm1_w);
+        self.script.append_point(b"cm1_p_w", &proof.cm1_p_w);
+        let gamma = self.script.challenge_scalar(b"gamma");
+        let z = gamma.pow([k as u64]);
+
+        // Round 4
+        self.script.append_scalar(b"b_z", &proof.b_z);
+        self.script.append_scalar(b"f_z", &proof.f_z);
+        let eta = self.script.challenge_scalar(b"eta");
+
+        Ok(ZkRoundValues {
+            k,
+            beta,
+            chi,
+            z,
+            eta,
+        })
+    }
+
+    fn pairing_checks(pvk: &PreparedVerifyingKey<P>, proof: &ZkProof<P>, values: &ZkRoundValues<P>) -> Result<Vec<PairingCheck<P>>, VerificationError> {
+        let vk = &pvk.vk;
+        let ZkRoundValues { k, beta, chi, z, eta } = *values;
+        let cm1_1 = vk.cm1_1.into_group();
+        let big_n_f = P::ScalarField::from(vk.big_n as u64);
+        let small_n_f = P::ScalarField::from(proof.small_n as u64);
+        let cm2_k = pvk.degree_check_g2(k).unwrap();
+        let cm2_2k = pvk.degree_check_g2(2 * k).unwrap();
+        let cm2_big_n = pvk.degree_check_g2(vk.big_n).unwrap();
+
+        // Q_B'(z) from B'(z)·(f'(z) + beta) - 1 = Q_B'(z)·Zh(z)
+        let z_h_z = z.pow([proof.small_n as u64]) - P::ScalarField::one();
+        let z_h_z_inv = z_h_z.inverse().ok_or(VerificationError::NonInvertibleChallenge)?;
+        let q_b_z = (proof.b_z * (proof.f_z + beta) - P::ScalarField::one()) * z_h_z_inv;
+        let v = proof.b_z + eta * proof.f_z + eta * eta * q_b_z;
+        let cm1_c = proof.cm1_b.into_group() + proof.cm1_f.mul(eta) + proof.cm1_q_b.mul(eta * eta);
+        let big_n_inv = big_n_f.inverse().ok_or(VerificationError::NonInvertibleChallenge)?;
+
+        Ok(vec![
+            // e(A', [T(x)]_2) = e(Q_A', [Zv(x)]_2) · e(m' - beta·A', [1]_2)
+            (VerificationError::QuotientIdentityFailed, vec![
+                (proof.cm1_a.into_group(), pvk.t_x_2.clone()),
+                (-proof.cm1_q_a.into_group(), pvk.z_v_2.clone()),
+                (proof.cm1_a.mul(beta) - proof.cm1_m, pvk.cm2_1.clone()),
+            ]),
+            // e(C - [v]_1 + z·pi_z, [1]_2) = e(pi_z, [x]_2)
+            (VerificationError::BatchedKzgOpeningFailed, vec![
+                (cm1_c - cm1_1.mul(v) + proof.cm1_pi_z.mul(z), pvk.cm2_1.clone()),
+                (-proof.cm1_pi_z.into_group(), pvk.cm2_x.clone()),
+            ]),
+            // B_lift(x) - B'(z) = (x^k - z)·pi_lift, shifted by x^{D-(2k-2)}
+            (VerificationError::LiftCheckFailed, vec![
+                (proof.cm1_b_lift.into_group() - cm1_1.mul(proof.b_z) + proof.cm1_pi_lift.mul(z), cm2_2k.clone()),
+                (-proof.cm1_pi_lift.into_group(), cm2_k.clone()),
+            ]),
+            // e(N·A' + chi·S - [chi·s_sum/N]_1 - n·B_lift, [1]_2) = e(Q_S, [Zv(x)]_2) · e(W, [x]_2)
+            (VerificationError::SumCheckFailed, vec![
+                (proof.cm1_a.mul(big_n_f) + proof.cm1_s.mul(chi) - cm1_1.mul(chi * proof.s_sum * big_n_inv) - proof.cm1_b_lift.mul(small_n_f), pvk.cm2_1.clone()),
+                (-proof.cm1_q_s.into_group(), pvk.z_v_2.clone()),
+                (-proof.cm1_w.into_group(), pvk.cm2_x.clone()),
+            ]),
+            // e(W, [x^{D-(N-2)}]_2) = e(p_w, [1]_2)
+            (VerificationError::DegreeCheckFailed, vec![
+                (proof.cm1_w.into_group(), cm2_big_n.clone()),
+                (-proof.cm1_p_w.into_group(), pvk.cm2_1.clone()),
+            ]),
+        ])
+    }
+
+    fn run_check(pairs: Vec<(P::G1, P::G2Prepared)>) -> bool {
+        let (g1, g2): (Vec<P::G1>, Vec<P::G2Prepared>) = pairs.into_iter().unzip();
+        P::multi_pairing(P::G1::normalize_batch(&g1), g2).is_zero()
+    }
+
+    // Runs the pairing checks one by one.
+    pub fn verify(&mut self, pvk: &PreparedVerifyingKey<P>, proof: ZkProof<P>) -> Result<(), VerificationError> {
+        let values = self.compute_round_values(pvk, &proof)?;
+        for (err, pairs) in Self::pairing_checks(pvk, &proof, &values)? {
+            if !Self::run_check(pairs) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // All the pairing checks in one multi-pairing, combined with powers of a challenge mu.
+    // When it fails, the individual checks are run to report which one failed.
+    pub fn batched_verify(&mut self, pvk: &PreparedVerifyingKey<P>, proof: ZkProof<P>) -> Result<(), VerificationError> {
+        let values = self.compute_round_values(pvk, &proof)?;
+        self.script.append_point(b"cm1_pi_z", &proof.cm1_pi_z);
+        self.script.append_point(b"cm1_pi_lift", &proof.cm1_pi_lift);
+        let mu = self.script.challenge_scalar(b"mu");
+
+        let checks = Self::pairing_checks(pvk, &proof, &values)?;
+        let mut mu_i = P::ScalarField::one();
+        let mut pairs = vec![];
+        for (_, check) in &checks {
+            pairs.extend(check.iter().map(|(g1, g2)| (g1.mul(mu_i), g2.clone())));
+            mu_i *= mu;
+        }
+        if !Self::run_check(pairs) {
+            for (err, pairs) in checks {
+                if !Self::run_check(pairs) {
+                    return Err(err);
+                }
+            }
+            return Err(VerificationError::BatchedCheckFailed);
+        }
+        Ok(())
+    }
+}
+
+impl <P: Pairing, T: Transcript<P>> Default for ZkVerifier<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ark_bls12_381::{Bls12_381, Fr, G1Affine};
+    use crate::cq::Cq;
+    use crate::poseidon::PoseidonTranscript;
+    use crate::prover::Prover;
+    use super::*;
+
+    fn range_table() -> Vec<Fr> {
+        (0..16).map(Fr::from).collect()
+    }
+
+    fn zk_keys(kzg: &Kzg<Bls12_381>) -> (ZkProvingKey<'_, Bls12_381>, PreparedVerifyingKey<Bls12_381>) {
+        let Cq { pk, vk } = Cq::new_with_srs(&range_table(), kzg).unwrap();
+        (ZkProvingKey::new(pk), vk.prepare())
+    }
+
+    #[test]
+    fn test_zk_proof() {
+        // N = 16 and n >= 2 need N + 2k <= 32 powers
+        let kzg = Kzg::<Bls12_381>::new(33);
+        let (zk_pk, pvk) = zk_keys(&kzg);
+
+        for len in [1usize, 2, 3, 8, 16] {
+            let f_i: Vec<Fr> = (0..len).map(|i| Fr::from(((5 * i + 3) % 16) as u64)).collect();
+            let proof = ZkProver::<Bls12_381>::new(f_i).unwrap().prove(&zk_pk).unwrap();
+            assert_eq!(proof.small_n, len.next_power_of_two().max(2));
+            assert!(ZkVerifier::<Bls12_381>::new().verify(&pvk, proof.clone()).is_ok());
+            assert!(ZkVerifier::<Bls12_381>::new().batched_verify(&pvk, proof).is_ok());
+        }
+
+        let f_i = vec![Fr::from(7), Fr::from(7), Fr::from(0), Fr::from(15)];
+        let proof = ZkProver::<Bls12_381, PoseidonTranscript<Bls12_381>>::new(f_i).unwrap().prove(&zk_pk).unwrap();
+        assert!(ZkVerifier::<Bls12_381, PoseidonTranscript<Bls12_381>>::new().batched_verify(&pvk, proof.clone()).is_ok());
+        assert!(ZkVerifier::<Bls12_381>::new().verify(&pvk, proof).is_err());
+    }
+
+    #[test]
+    fn test_zk_errors() {
+        let kzg = Kzg::<Bls12_381>::new(33);
+        let (zk_pk, _) = zk_keys(&kzg);
+
+        let result = ZkProver::<Bls12_381>::new(vec![Fr::from(16)]).unwrap().prove(&zk_pk);
+        assert!(matches!(result, Err(GeneralError::WitnessNotInTable)));
+        let result = ZkProver::<Bls12_381>::new(vec![Fr::from(1); 17]).unwrap().prove(&zk_pk);
+        assert!(matches!(result, Err(GeneralError::WitnessLargerThanTable)));
+        assert!(matches!(ZkProver::<Bls12_381>::new(vec![]), Err(GeneralError::EmptyWitness)));
+
+        // the SRS of Cq::new only has N powers
+        let Cq { pk, .. } = Cq::<Bls12_381>::new(&range_table()).unwrap();
+        let result = ZkProver::<Bls12_381>::new(vec![Fr::from(1)]).unwrap().prove(&ZkProvingKey::new(pk));
+        assert!(matches!(result, Err(GeneralError::Srs(SrsError::TooShort { needed: 33, available: 16, .. }))));
+    }
+
+    #[test]
+    fn test_zk_tampered_proofs() {
+        let kzg = Kzg::<Bls12_381>::new(33);
+        let (zk_pk, pvk) = zk_keys(&kzg);
+        let f_i = vec![Fr::from(1), Fr::from(5), Fr::from(5), Fr::from(9)];
+        let proof = ZkProver::<Bls12_381>::new(f_i).unwrap().prove(&zk_pk).unwrap();
+
+        let g1 = G1Affine::generator();
+        let shift = |point: G1Affine| (point + g1).into_affine();
+        let one = Fr::one();
+        let mut cases: Vec<(ZkProof<Bls12_381>, VerificationError)> = vec![];
+        let mut tamper = |f: &dyn Fn(&mut ZkProof<Bls12_381>), err: VerificationError| {
+            let mut tampered = proof.clone();
+            f(&mut tampered);
+            cases.push((tampered, err));
+        };
+        tamper(&|p| p.small_n = 32, VerificationError::MalformedSizes { small_n: 32, big_n: 16 });
+        tamper(&|p| p.small_n = 3, VerificationError::MalformedSizes { small_n: 3, big_n: 16 });
+        tamper(&|p| p.cm1_f = shift(p.cm1_f), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_m = shift(p.cm1_m), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_s = shift(p.cm1_s), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.s_sum += one, VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_q_a = shift(p.cm1_q_a), VerificationError::QuotientIdentityFailed);
+        tamper(&|p| p.cm1_b = shift(p.cm1_b), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_q_b = shift(p.cm1_q_b), VerificationError::BatchedKzgOpeningFailed);
+        // the commitments of rounds 2 and 3 change z, so the opening at z fails first
+        tamper(&|p| p.cm1_b_lift = shift(p.cm1_b_lift), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_q_s = shift(p.cm1_q_s), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_w = shift(p.cm1_w), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_p_w = shift(p.cm1_p_w), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.b_z += one, VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.f_z += one, VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_pi_z = shift(p.cm1_pi_z), VerificationError::BatchedKzgOpeningFailed);
+        tamper(&|p| p.cm1_pi_lift = shift(p.cm1_pi_lift), VerificationError::LiftCheckFailed);
+
+        for (tampered, err) in cases {
+            let expected = Err(err);
+            assert_eq!(ZkVerifier::<Bls12_381>::new().verify(&pvk, tampered.clone()), expected);
+            assert_eq!(ZkVerifier::<Bls12_381>::new().batched_verify(&pvk, tampered), expected);
+        }
+    }
+
+    // With the trapdoor x, a simulator that knows no witness outputs accepting proofs in which every
+    // element not fixed by a verification equation is sampled uniformly. Each such element of a real
+    // proof is masked by its own blinding factor, so real proofs have the same distribution.
+    // Adding skew_q_s or skew_p_w to Q_S(x) or p_w(x) breaks only the sum check or the degree check.
+    fn simulate(x: Fr, kzg: &Kzg<Bls12_381>, pvk: &PreparedVerifyingKey<Bls12_381>, small_n: usize, (skew_q_s, skew_p_w): (Fr, Fr)) -> ZkProof<Bls12_381> {
+        let mut rng = thread_rng();
+        let mut random = || Fr::rand(&mut rng);
+        let commit = |value: Fr| (G1Affine::generator() * value).into_affine();
+        let vk = &pvk.vk;
+        let big_n = vk.big_n;
+        let k = big_n / small_n;
+        let (big_n_f, small_n_f) = (Fr::from(big_n as u64), Fr::from(small_n as u64));
+
+        let mut t_i = range_table();
+        t_i.resize(big_n, t_i[t_i.len() - 1]);
+        let domain = GeneralEvaluationDomain::<Fr>::new(big_n).unwrap();
+        let t_x = DensePolynomial::from_coefficients_vec(domain.ifft(&t_i)).evaluate(&x);
+        let z_v_x = x.pow([big_n as u64]) - Fr::one();
+
+        let mut script = Script::<Sha256, Bls12_381>::new(ZK_PROTOCOL_LABEL);
+        script.append_statement(vk, small_n);
+        let (f_x, m_x, s_x, s_sum) = (random(), random(), random(), random());
+        script.append_point(b"cm1_f", &commit(f_x));
+        script.append_point(b"cm1_m", &commit(m_x));
+        script.append_point(b"cm1_s", &commit(s_x));
+        script.append_scalar(b"s_sum", &s_sum);
+        let beta = script.challenge_scalar(b"beta");
+
+        let (a_x, b_x, b_lift_x) = (random(), random(), random());
+        let q_a_x = (a_x * (t_x + beta) - m_x) / z_v_x;
+        let q_b_x = (b_x * (f_x + beta) - Fr::one()) / (x.pow([small_n as u64]) - Fr::one());
+        for (label, value) in [(&b"cm1_a"[..], a_x), (b"cm1_q_a", q_a_x), (b"cm1_b", b_x), (b"cm1_q_b", q_b_x), (b"cm1_b_lift", b_lift_x)] {
+            script.append_point(label, &commit(value));
+        }
+        let chi = script.challenge_scalar(b"chi");
+
+        let w_x = random();
+        let p_w_x = x.pow([(kzg.max_degree() + 2 - big_n) as u64]) * w_x + skew_p_w;
+        let q_s_x = (big_n_f * a_x + chi * s_x - chi * s_sum / big_n_f - small_n_f * b_lift_x - x * w_x) / z_v_x + skew_q_s;
+        script.append_point(b"cm1_q_s", &commit(q_s_x));
+        script.append_point(b"cm1_w", &commit(w_x));
+        script.append_point(b"cm1_p_w", &commit(p_w_x));
+        let gamma = script.challenge_scalar(b"gamma");
+        let z = gamma.pow([k as u64]);
+
+        let (b_z, f_z) = (random(), random());
+        script.append_scalar(b"b_z", &b_z);
+        script.append_scalar(b"f_z", &f_z);
+        let eta = script.challenge_scalar(b"eta");
+        let q_b_z = (b_z * (f_z + beta) - Fr::one()) / (z.pow([small_n as u64]) - Fr::one());
+        let pi_z = (b_x + eta * f_x + eta * eta * q_b_x - (b_z + eta * f_z + eta * eta * q_b_z)) / (x - z);
+        let pi_lift = (b_lift_x - b_z) / (x.pow([k as u64]) - z);
+
+        ZkProof {
+            small_n,
+            cm1_f: commit(f_x),
+            cm1_m: commit(m_x),
+            cm1_s: commit(s_x),
+            s_sum,
+            cm1_a: commit(a_x),
+            cm1_q_a: commit(q_a_x),
+            cm1_b: commit(b_x),
+            cm1_q_b: commit(q_b_x),
+            cm1_b_lift: commit(b_lift_x),
+            cm1_q_s: commit(q_s_x),
+            cm1_w: commit(w_x),
+            cm1_p_w: commit(p_w_x),
+            b_z,
+            f_z,
+            cm1_pi_z: commit(pi_z),
+            cm1_pi_lift: commit(pi_lift),
+        }
+    }
+
+    #[test]
+    fn test_zk_simulator() {
+        let x = Fr::from(0x5eed_u64);
+        let kzg = Kzg::<Bls12_381>::new_from_secret(x, 33);
+        let (zk_pk, pvk) = zk_keys(&kzg);
+
+        for small_n in [2, 4, 16] {
+            let simulated = simulate(x, &kzg, &pvk, small_n, (Fr::zero(), Fr::zero()));
+            assert!(ZkVerifier::<Bls12_381>::new().verify(&pvk, simulated.clone()).is_ok());
+            assert!(ZkVerifier::<Bls12_381>::new().batched_verify(&pvk, simulated).is_ok());
+        }
+        for (skew, err) in [((Fr::one(), Fr::zero()), VerificationError::SumCheckFailed), ((Fr::zero(), Fr::one()), VerificationError::DegreeCheckFailed)] {
+            let simulated = simulate(x, &kzg, &pvk, 4, skew);
+            let expected = Err(err);
+            assert_eq!(ZkVerifier::<Bls12_381>::new().verify(&pvk, simulated.clone()), expected);
+            assert_eq!(ZkVerifier::<Bls12_381>::new().batched_verify(&pvk, simulated), expected);
+        }
+
+        // the sampled elements of real proofs do not repeat for the same witness
+        let f_i = vec![Fr::from(2), Fr::from(2), Fr::from(11), Fr::from(4)];
+        let prover = ZkProver::<Bls12_381>::new(f_i).unwrap();
+        let (p1, p2) = (prover.prove(&zk_pk).unwrap(), prover.prove(&zk_pk).unwrap());
+        let sampled = |p: &ZkProof<Bls12_381>| (vec![p.cm1_f, p.cm1_m, p.cm1_s, p.cm1_a, p.cm1_b, p.cm1_b_lift, p.cm1_q_s, p.cm1_w], vec![p.s_sum, p.b_z, p.f_z]);
+        let ((g1_1, fr_1), (g1_2, fr_2)) = (sampled(&p1), sampled(&p2));
+        assert!(g1_1.iter().zip(&g1_2).all(|(a, b)| a != b));
+        assert!(fr_1.iter().zip(&fr_2).all(|(a, b)| a != b));
+    }
+
+    #[test]
+    fn test_zk_proof_does_not_confirm_a_guessed_witness() {
+        let kzg = Kzg::<Bls12_381>::new(33);
+        let (zk_pk, _) = zk_keys(&kzg);
+        let f_i = vec![Fr::from(3), Fr::from(1), Fr::from(4), Fr::from(1)];
+        let domain = GeneralEvaluationDomain::<Fr>::new(4).unwrap();
+        let guessed_cm1_f = kzg.commit_g1(&DensePolynomial::from_coefficients_vec(domain.ifft(&f_i)));
+
+        // a plain proof commits to f without blinding, so a correct guess is confirmed
+        let plain = Prover::<Bls12_381>::new(f_i.clone()).unwrap().prove(&zk_pk.pk).unwrap();
+        assert_eq!(plain.cm1_f, guessed_cm1_f);
+
+        let proof = ZkProver::<Bls12_381>::new(f_i).unwrap().prove(&zk_pk).unwrap();
+        assert_ne!(proof.cm1_f, guessed_cm1_f);
+        assert_ne!(proof.cm1_m, plain.cm1_m);
+        assert_ne!(proof.cm1_a, plain.cm1_a);
+    }
+}